@@ -0,0 +1,224 @@
+//! User accounts and the sessions of signed-in users.
+//!
+//! Accounts are kept in the game store next to the games, with each password
+//! as a salted Argon2 hash. Logging in issues a random session token, sent
+//! back as the `session` cookie, which identifies the user on later requests
+//! and when their browser opens the WebSocket. Sessions only live in memory,
+//! so a restart logs everyone out.
+
+use actix_web::HttpRequest;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use crate::storage::GameStore;
+use crate::validation;
+
+/// Name of the cookie holding the session token
+pub const SESSION_COOKIE: &str = "session";
+/// How long a session lasts after logging in
+pub const SESSION_DAYS: i64 = 30;
+/// Hash of no account's password, made with the same Argon2 parameters as
+/// real ones so that checking a password against it takes as long
+const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$AAAAAAAAAAAAAAAAAAAAAA$pTNcjUI3Kc+B4nXmRS4HnKoEGv3jY9/uwLtO5wiE39M";
+
+/// A registered user as stored, password hash included
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Account {
+    pub id: String,
+    pub username: String,
+    /// Argon2 hash in PHC string format, which carries its own salt
+    pub password_hash: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Who a request or connection belongs to. Seats in games hold the `id`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub id: String,
+    pub username: String,
+}
+
+impl Account {
+    pub fn user(&self) -> User {
+        User {
+            id: self.id.clone(),
+            username: self.username.clone(),
+        }
+    }
+}
+
+/// Body of the register and login requests
+#[derive(Deserialize, Debug)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Why registering or logging in failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountError {
+    /// Not 3 to 20 letters, digits, `_` or `-`
+    InvalidUsername,
+    /// Not 8 to 128 characters
+    InvalidPassword,
+    UsernameTaken,
+    /// No such user, or the wrong password; which one is not given away
+    WrongCredentials,
+    /// The account could not be saved
+    Storage,
+}
+
+impl AccountError {
+    pub fn message(self) -> &'static str {
+        match self {
+            AccountError::InvalidUsername => "Usernames are 3 to 20 letters, digits, _ or -",
+            AccountError::InvalidPassword => "Passwords are 8 to 128 characters",
+            AccountError::UsernameTaken => "That username is taken",
+            AccountError::WrongCredentials => "Wrong username or password",
+            AccountError::Storage => "The account could not be saved",
+        }
+    }
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+struct Session {
+    user: User,
+    expires_at: DateTime<Utc>,
+}
+
+/// Every account and the sessions of the users logged in
+pub struct Accounts {
+    /// By username in lower case, as usernames differing only in case are the same user
+    accounts: Mutex<HashMap<String, Account>>,
+    /// By session token
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl Accounts {
+    pub fn new(accounts: Vec<Account>) -> Self {
+        Accounts {
+            accounts: Mutex::new(
+                accounts
+                    .into_iter()
+                    .map(|account| (account.username.to_lowercase(), account))
+                    .collect(),
+            ),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Create an account and persist it. Hashing is deliberately slow, so
+    /// call this off the async executor.
+    pub fn register(&self, store: &dyn GameStore, credentials: &Credentials) -> Result<User, AccountError> {
+        validation::check_username(&credentials.username)?;
+        validation::check_password(&credentials.password)?;
+
+        // `rand`'s thread generator is a CSPRNG seeded from the operating system
+        let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>()).map_err(|_| AccountError::InvalidPassword)?;
+        let password_hash = Argon2::default()
+            .hash_password(credentials.password.as_bytes(), &salt)
+            .map_err(|_| AccountError::InvalidPassword)?
+            .to_string();
+
+        let mut accounts = self.accounts.lock().unwrap();
+        let key = credentials.username.to_lowercase();
+        if accounts.contains_key(&key) {
+            return Err(AccountError::UsernameTaken);
+        }
+        let account = Account {
+            id: Uuid::new_v4().to_string(),
+            username: credentials.username.clone(),
+            password_hash,
+            created_at: Utc::now(),
+        };
+        store.record_account(&account).map_err(|_| AccountError::Storage)?;
+        let user = account.user();
+        accounts.insert(key, account);
+        Ok(user)
+    }
+
+    /// Check a user's password and start a session, returning its token.
+    /// Like `register`, this is slow.
+    pub fn log_in(&self, credentials: &Credentials) -> Result<(User, String), AccountError> {
+        let account = self.accounts.lock().unwrap().get(&credentials.username.to_lowercase()).cloned();
+
+        // Unknown usernames are checked against a dummy hash, so that how long
+        // this takes does not tell which usernames exist
+        let password_hash = account.as_ref().map_or(DUMMY_PASSWORD_HASH, |account| account.password_hash.as_str());
+        let password_hash = PasswordHash::new(password_hash).map_err(|_| AccountError::WrongCredentials)?;
+        let verified = Argon2::default()
+            .verify_password(credentials.password.as_bytes(), &password_hash)
+            .is_ok();
+
+        match account {
+            Some(account) if verified => Ok((account.user(), self.start_session(account.user()))),
+            _ => Err(AccountError::WrongCredentials),
+        }
+    }
+
+    /// Issue a session token for a user who just registered or logged in
+    pub fn start_session(&self, user: User) -> String {
+        let token = Uuid::new_v4().simple().to_string();
+        let mut sessions = self.sessions.lock().unwrap();
+        // Forget sessions that ran out while we are here
+        let now = Utc::now();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(
+            token.clone(),
+            Session {
+                user,
+                expires_at: now + chrono::Duration::days(SESSION_DAYS),
+            },
+        );
+        token
+    }
+
+    pub fn log_out(&self, token: &str) {
+        self.sessions.lock().unwrap().remove(token);
+    }
+
+    /// The user a session token belongs to, while the session lasts
+    pub fn user(&self, token: &str) -> Option<User> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions.get(token).filter(|session| session.expires_at > Utc::now())?;
+        Some(session.user.clone())
+    }
+
+    /// The user with this ID, if it is one and not a guest
+    pub fn user_by_id(&self, id: &str) -> Option<User> {
+        let accounts = self.accounts.lock().unwrap();
+        accounts.values().find(|account| account.id == id).map(Account::user)
+    }
+
+    /// The user with this username, in any case
+    pub fn user_by_username(&self, username: &str) -> Option<User> {
+        let accounts = self.accounts.lock().unwrap();
+        accounts.get(&username.to_lowercase()).map(Account::user)
+    }
+
+    /// The signed-in user making a request, if any
+    pub fn user_for_request(&self, req: &HttpRequest) -> Option<User> {
+        self.user(&session_token(req)?)
+    }
+}
+
+/// The session token of a request, from the `session` cookie or an
+/// `Authorization: Bearer` header for clients without cookies
+pub fn session_token(req: &HttpRequest) -> Option<String> {
+    if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+        return Some(cookie.value().to_string());
+    }
+    let header = req.headers().get("Authorization")?.to_str().ok()?;
+    header.strip_prefix("Bearer ").map(|token| token.trim().to_string())
+}
@@ -0,0 +1,180 @@
+//! Tests for who players are: user accounts registering and logging in over
+//! HTTP, guests recognised by their signed cookie, and either taking seats in
+//! games as themselves from as many tabs as they like.
+
+use actix_web::test::{self as http, TestRequest};
+use actix_web::{web, App};
+use serde_json::{json, Value};
+use std::time::Instant;
+
+use crate::accounts::{AccountError, Credentials};
+use crate::test_support::{exchange, pump, test_app_state, NullStore, TestClient};
+use crate::guests::GuestSigner;
+use crate::protocol::ProtocolVersion;
+use crate::{current_user, log_in, log_out, register};
+
+fn credentials(username: &str, password: &str) -> Credentials {
+    Credentials {
+        username: username.to_string(),
+        password: password.to_string(),
+    }
+}
+
+#[test]
+fn usernames_are_unique_regardless_of_case() {
+    let app_state = test_app_state();
+    let accounts = &app_state.accounts;
+
+    let user = accounts.register(&NullStore, &credentials("Magnus", "correct horse")).unwrap();
+    assert_eq!(user.username, "Magnus");
+    assert_eq!(
+        accounts.register(&NullStore, &credentials("magnus", "another password")).unwrap_err(),
+        AccountError::UsernameTaken
+    );
+    assert_eq!(accounts.register(&NullStore, &credentials("no spaces", "correct horse")).unwrap_err(), AccountError::InvalidUsername);
+    assert_eq!(accounts.register(&NullStore, &credentials("Hikaru", "short")).unwrap_err(), AccountError::InvalidPassword);
+
+    let (logged_in, token) = accounts.log_in(&credentials("MAGNUS", "correct horse")).unwrap();
+    assert_eq!(logged_in, user);
+    assert_eq!(accounts.user(&token), Some(user));
+    let started = Instant::now();
+    assert_eq!(accounts.log_in(&credentials("Magnus", "wrong horse")).unwrap_err(), AccountError::WrongCredentials);
+    let wrong_password = started.elapsed();
+    let started = Instant::now();
+    assert_eq!(accounts.log_in(&credentials("Hikaru", "correct horse")).unwrap_err(), AccountError::WrongCredentials);
+    // An unknown username still costs a password check, or its speed would give it away
+    assert!(started.elapsed() * 4 > wrong_password, "{:?} against {:?}", started.elapsed(), wrong_password);
+
+    accounts.log_out(&token);
+    assert_eq!(accounts.user(&token), None);
+}
+
+#[actix_rt::test]
+async fn sessions_over_http() {
+    let app_state = test_app_state();
+    let app = http::init_service(
+        App::new()
+            .app_data(app_state.clone())
+            .service(web::resource("/register").route(web::post().to(register)))
+            .service(web::resource("/login").route(web::post().to(log_in)))
+            .service(web::resource("/logout").route(web::post().to(log_out)))
+            .service(web::resource("/me").route(web::get().to(current_user))),
+    )
+    .await;
+
+    let body = json!({"username": "judit", "password": "polgar1976"});
+    let request = TestRequest::post().uri("/register").set_json(&body).to_request();
+    let response = http::call_service(&app, request).await;
+    assert_eq!(response.status(), 201);
+    let request = TestRequest::post().uri("/register").set_json(&body).to_request();
+    assert_eq!(http::call_service(&app, request).await.status(), 409);
+
+    let wrong = json!({"username": "judit", "password": "polgar1977"});
+    let request = TestRequest::post().uri("/login").set_json(&wrong).to_request();
+    assert_eq!(http::call_service(&app, request).await.status(), 401);
+
+    let request = TestRequest::post().uri("/login").set_json(&body).to_request();
+    let response = http::call_service(&app, request).await;
+    assert_eq!(response.status(), 200);
+    let cookie = response.response().cookies().find(|cookie| cookie.name() == "session").unwrap().into_owned();
+    assert!(cookie.http_only().unwrap_or(false));
+    let session: Value = http::read_body_json(response).await;
+    assert_eq!(session["username"], "judit");
+    assert_eq!(session["token"], cookie.value());
+
+    // The cookie and the bearer token both identify the user
+    let request = TestRequest::get().uri("/me").cookie(cookie.clone()).to_request();
+    let me: Value = http::call_and_read_body_json(&app, request).await;
+    assert_eq!(me["id"], session["id"]);
+    let bearer = format!("Bearer {}", cookie.value());
+    let request = TestRequest::get().uri("/me").insert_header(("Authorization", bearer)).to_request();
+    assert_eq!(http::call_service(&app, request).await.status(), 200);
+
+    let request = TestRequest::post().uri("/logout").cookie(cookie.clone()).to_request();
+    assert_eq!(http::call_service(&app, request).await.status(), 204);
+    let request = TestRequest::get().uri("/me").cookie(cookie).to_request();
+    assert_eq!(http::call_service(&app, request).await.status(), 401);
+}
+
+#[actix_rt::test]
+async fn signed_in_users_take_seats_as_themselves() {
+    let app_state = test_app_state();
+    let user = app_state.accounts.register(&NullStore, &credentials("anand", "madras1969")).unwrap();
+    let mut clients = vec![
+        TestClient::connect_as(&app_state, ProtocolVersion::V2, Some(user.clone()), None),
+        TestClient::connect_as(&app_state, ProtocolVersion::V2, Some(user.clone()), None),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+    ];
+    pump(&mut clients);
+
+    let reply = exchange(&mut clients, 0, json!({"type": "create", "color_preference": "white"}));
+    let game_id = reply["game_id"].as_str().unwrap().to_string();
+
+    // The same user in another tab gets their own seat rather than the other side
+    let reply = exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    assert_eq!((reply["type"].as_str(), reply["color"].as_str()), (Some("rejoined"), Some("white")), "{}", reply);
+
+    let reply = exchange(&mut clients, 2, json!({"type": "join", "game_id": game_id}));
+    assert_eq!(reply["type"], "joined", "{}", reply);
+
+    let record = app_state.find_game_record(&game_id).unwrap();
+    assert_eq!(record.white_player.as_deref(), Some(user.id.as_str()));
+    assert!(record.black_player.is_some_and(|player| player != user.id));
+
+    let reply = exchange(&mut clients, 0, json!({"type": "move", "uci": "e2e4"}));
+    assert_eq!(reply["type"], "move_made", "{}", reply);
+}
+
+#[test]
+fn guest_cookies_only_carry_ids_we_signed() {
+    let signer = GuestSigner::new(b"one key".to_vec());
+    let guest_id = GuestSigner::new_guest();
+    let value = signer.sign(&guest_id);
+    assert_eq!(signer.verify(&value), Some(guest_id.clone()));
+
+    let other_guest = GuestSigner::new_guest();
+    let (_, signature) = value.rsplit_once('.').unwrap();
+    assert_eq!(signer.verify(&format!("{}.{}", other_guest, signature)), None);
+    assert_eq!(GuestSigner::new(b"another key".to_vec()).verify(&value), None);
+    assert_eq!(signer.verify(&guest_id), None);
+    assert_eq!(signer.verify("guest-1.zz"), None);
+
+    // Only guest IDs are signed, so a cookie can never pass for a user
+    let user_id = uuid::Uuid::new_v4().to_string();
+    assert_eq!(signer.verify(&signer.sign(&user_id)), None);
+}
+
+#[actix_rt::test]
+async fn every_tab_of_a_guest_plays_the_same_color() {
+    let app_state = test_app_state();
+    let guest_id = GuestSigner::new_guest();
+    let mut clients = vec![
+        TestClient::connect_as(&app_state, ProtocolVersion::V2, None, Some(guest_id.clone())),
+        TestClient::connect_as(&app_state, ProtocolVersion::V2, None, Some(guest_id.clone())),
+        TestClient::connect_as(&app_state, ProtocolVersion::V2, None, Some(GuestSigner::new_guest())),
+    ];
+    pump(&mut clients);
+
+    let reply = exchange(&mut clients, 0, json!({"type": "create", "color_preference": "white"}));
+    let game_id = reply["game_id"].as_str().unwrap().to_string();
+    let reply = exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    assert_eq!(reply["color"], "white", "{}", reply);
+    exchange(&mut clients, 2, json!({"type": "join", "game_id": game_id}));
+
+    // Either tab can move, and both see every move
+    for (index, uci) in [(1, "e2e4"), (2, "e7e5"), (0, "g1f3")] {
+        let reply = exchange(&mut clients, index, json!({"type": "move", "uci": uci}));
+        assert_eq!(reply["type"], "move_made", "{}", reply);
+        for client in &clients[..2] {
+            assert!(client.messages.iter().any(|message| message["type"] == "move_made" && message["uci"] == uci));
+        }
+    }
+
+    // One tab going off to start another game leaves the other one playing
+    exchange(&mut clients, 0, json!({"type": "create"}));
+    let reply = exchange(&mut clients, 2, json!({"type": "move", "uci": "b8c6"}));
+    assert_eq!(reply["type"], "move_made", "{}", reply);
+    assert!(clients[1].messages.iter().any(|message| message["type"] == "move_made"));
+    let record = app_state.find_game_record(&game_id).unwrap();
+    assert_eq!(record.white_player.as_deref(), Some(guest_id.as_str()));
+}
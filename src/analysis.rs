@@ -0,0 +1,177 @@
+//! Position analysis and post-game reports from the built-in engine.
+//!
+//! The engine scores positions for the side to move; everything here turns
+//! that into evaluations from White's point of view with lines in SAN, the
+//! way they are shown to people. A configured UCI engine can stand in for
+//! the built-in one in live analysis; its lines are read the same way.
+
+use chess::{Board, ChessMove, Color, MoveGen, EMPTY};
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use crate::engine::{self, Line, Score, SearchLimits};
+use crate::models::{Evaluation, Judgement, MoveReport};
+use crate::pgn;
+use crate::uci::Info;
+use crate::validation;
+
+/// How far a live analysis searches before it stops on its own
+pub const ANALYSIS_LIMITS: SearchLimits = SearchLimits {
+    max_depth: 32,
+    max_time: Duration::from_secs(20),
+};
+
+/// How long each position of a game is searched for its report
+const REPORT_LIMITS: SearchLimits = SearchLimits {
+    max_depth: 6,
+    max_time: Duration::from_millis(150),
+};
+
+/// Evaluations beyond this many centipawns count as decided when measuring
+/// what a move lost, so a slower mate is not a blunder
+const DECIDED_CENTIPAWNS: i32 = 1000;
+
+/// Centipawns a move may lose before it is an inaccuracy, a mistake and a blunder
+const INACCURACY_LOSS: u32 = 50;
+const MISTAKE_LOSS: u32 = 100;
+const BLUNDER_LOSS: u32 = 300;
+
+/// A line found in `board` as shown to clients
+pub fn describe(board: &Board, line: &Line) -> (Evaluation, Vec<String>) {
+    (evaluation(board, line.score), san_line(board, &line.pv))
+}
+
+/// The line in a UCI engine's `info` about `board`, if it has one. Only the
+/// main line counts, and its moves are kept up to the first one that is not
+/// legal.
+pub fn uci_line(board: &Board, info: &Info) -> Option<Line> {
+    if info.multipv.is_some_and(|multipv| multipv > 1) {
+        return None;
+    }
+
+    let mut position = *board;
+    let mut pv = Vec::new();
+    for uci in &info.pv {
+        match validation::parse_uci(uci) {
+            Ok(chess_move) if position.legal(chess_move) => {
+                position = position.make_move_new(chess_move);
+                pv.push(chess_move);
+            }
+            _ => break,
+        }
+    }
+    if pv.is_empty() {
+        return None;
+    }
+
+    Some(Line {
+        depth: u8::try_from(info.depth.unwrap_or(0)).unwrap_or(u8::MAX),
+        score: info.score?,
+        pv,
+    })
+}
+
+/// The engine's score, which is for the side to move, from White's point of view
+fn evaluation(board: &Board, score: Score) -> Evaluation {
+    let sign = if board.side_to_move() == Color::White { 1 } else { -1 };
+    match score {
+        Score::Centipawns(centipawns) => Evaluation::Cp(sign * centipawns),
+        Score::Mate(moves) => Evaluation::Mate(sign * moves),
+    }
+}
+
+fn san_line(board: &Board, moves: &[ChessMove]) -> Vec<String> {
+    let mut board = *board;
+    moves
+        .iter()
+        .map(|chess_move| {
+            let san = pgn::san(&board, *chess_move);
+            board = board.make_move_new(*chess_move);
+            san
+        })
+        .collect()
+}
+
+/// Judge every move of a game played from `start_position`.
+///
+/// Each position is searched in turn; a move is judged by how much worse the
+/// position got for the player who made it, compared to the engine's choice.
+pub fn report(start_position: Board, moves: &[ChessMove]) -> Vec<MoveReport> {
+    let mut boards = vec![start_position];
+    for chess_move in moves {
+        let board = boards.last().unwrap().make_move_new(*chess_move);
+        boards.push(board);
+    }
+
+    // The best move and evaluation of every position, searched knowing the positions before it
+    let mut positions = Vec::new();
+    let searches: Vec<(Option<ChessMove>, Option<Evaluation>, i32)> = boards
+        .iter()
+        .map(|board| {
+            positions.push(board.get_hash());
+            let mut best = None;
+            engine::analyse(board, &positions, REPORT_LIMITS, &AtomicBool::new(false), |line| best = Some(line));
+            match best {
+                Some(line) => {
+                    let evaluation = evaluation(board, line.score);
+                    (Some(line.pv[0]), Some(evaluation), decided_value(evaluation))
+                }
+                None => (None, None, final_value(board)),
+            }
+        })
+        .collect();
+
+    moves
+        .iter()
+        .enumerate()
+        .map(|(index, chess_move)| {
+            let board = &boards[index];
+            let (best_move, _, before) = searches[index];
+            let (_, evaluation, after) = searches[index + 1];
+
+            let loss = if best_move == Some(*chess_move) {
+                0
+            } else {
+                let sign = if board.side_to_move() == Color::White { 1 } else { -1 };
+                (sign * (before - after)).max(0) as u32
+            };
+            let judgement = if loss >= BLUNDER_LOSS {
+                Some(Judgement::Blunder)
+            } else if loss >= MISTAKE_LOSS {
+                Some(Judgement::Mistake)
+            } else if loss >= INACCURACY_LOSS {
+                Some(Judgement::Inaccuracy)
+            } else {
+                None
+            };
+
+            MoveReport {
+                san: pgn::san(board, *chess_move),
+                evaluation,
+                best_move: best_move.map(|best_move| pgn::san(board, best_move)),
+                loss,
+                judgement,
+            }
+        })
+        .collect()
+}
+
+/// An evaluation in centipawns for White, with mates and large advantages cut off
+fn decided_value(evaluation: Evaluation) -> i32 {
+    match evaluation {
+        Evaluation::Cp(centipawns) => centipawns.clamp(-DECIDED_CENTIPAWNS, DECIDED_CENTIPAWNS),
+        Evaluation::Mate(moves) if moves > 0 => DECIDED_CENTIPAWNS,
+        Evaluation::Mate(_) => -DECIDED_CENTIPAWNS,
+    }
+}
+
+/// The value of a position without legal moves: lost for the side to move
+/// if it is checkmated, otherwise a draw
+fn final_value(board: &Board) -> i32 {
+    debug_assert_eq!(MoveGen::new_legal(board).len(), 0);
+    match (board.side_to_move(), *board.checkers() != EMPTY) {
+        (_, false) => 0,
+        (Color::White, true) => -DECIDED_CENTIPAWNS,
+        (Color::Black, true) => DECIDED_CENTIPAWNS,
+    }
+}
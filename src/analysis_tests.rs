@@ -0,0 +1,103 @@
+//! Tests for engine analysis: spectators following a game with the engine,
+//! players kept from it until the game is over, from whichever tab they ask,
+//! and post-game reports.
+
+use serde_json::json;
+
+use crate::protocol::ProtocolVersion;
+use crate::test_support::{exchange, pump, test_app_state, wait_for, TestClient};
+
+#[actix_rt::test]
+async fn analysis_for_spectators_and_reports() {
+    let app_state = test_app_state();
+    let mut clients = vec![
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+    ];
+    pump(&mut clients);
+
+    let reply = exchange(&mut clients, 0, json!({"type": "create", "color_preference": "white"}));
+    let game_id = reply["game_id"].as_str().unwrap().to_string();
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    exchange(&mut clients, 2, json!({"type": "watch", "game_id": game_id}));
+    let fen = exchange(&mut clients, 0, json!({"type": "move", "uci": "f2f3"}))["fen"].clone();
+
+    // Players get no help from the engine while the game is on
+    for index in [0, 1] {
+        let reply = exchange(&mut clients, index, json!({"type": "start_analysis"}));
+        assert_eq!(reply["code"], "analysis_not_allowed", "{}", reply);
+    }
+    let reply = exchange(&mut clients, 2, json!({"type": "get_report"}));
+    assert_eq!(reply["code"], "game_not_over", "{}", reply);
+
+    // Spectators do, and the analysis follows the game
+    clients[2].messages.clear();
+    clients[2].send_text(json!({"type": "start_analysis"}).to_string());
+    let update = wait_for(&mut clients, 2, "analysis").await;
+    assert_eq!(update["fen"], fen, "{}", update);
+    assert_eq!(update["depth"], 1, "{}", update);
+    assert!(update["evaluation"]["cp"].is_i64(), "{}", update);
+    assert_eq!(update["pv"].as_array().unwrap().len(), 1, "{}", update);
+
+    let fen = exchange(&mut clients, 1, json!({"type": "move", "uci": "e7e5"}))["fen"].clone();
+    let update = loop {
+        let update = wait_for(&mut clients, 2, "analysis").await;
+        if update["fen"] == fen {
+            break update;
+        }
+    };
+    assert!(update["pv"][0].is_string(), "{}", update);
+    clients[2].send_text(json!({"type": "stop_analysis"}).to_string());
+
+    exchange(&mut clients, 0, json!({"type": "move", "uci": "g2g4"}));
+    exchange(&mut clients, 1, json!({"type": "move", "uci": "d8h4"}));
+
+    // Once it is over, the players may look too; a mated position has nothing left to analyse
+    clients[0].messages.clear();
+    clients[0].send_text(json!({"type": "start_analysis"}).to_string());
+    let update = wait_for(&mut clients, 0, "analysis").await;
+    assert_eq!((update["depth"].as_u64(), update["done"].as_bool()), (Some(0), Some(true)), "{}", update);
+    assert!(update["evaluation"].is_null(), "{}", update);
+
+    clients[0].send_text(json!({"type": "get_report"}).to_string());
+    let report = wait_for(&mut clients, 0, "report").await;
+    let moves = report["moves"].as_array().unwrap();
+    let sans: Vec<_> = moves.iter().map(|entry| entry["san"].as_str().unwrap()).collect();
+    assert_eq!(sans, ["f3", "e5", "g4", "Qh4#"]);
+    assert_eq!(moves[2]["judgement"], "blunder", "{}", report);
+    assert_eq!(moves[2]["evaluation"], json!({"mate": -1}), "{}", report);
+    assert_eq!(moves[3]["best_move"], "Qh4#", "{}", report);
+    assert!(moves[3]["judgement"].is_null() && moves[3]["evaluation"].is_null(), "{}", report);
+}
+
+#[actix_rt::test]
+async fn players_get_no_analysis_of_their_game_from_another_tab() {
+    let app_state = test_app_state();
+    let guest_id = Some("guest-tal".to_string());
+    let mut clients = vec![
+        TestClient::connect_as(&app_state, ProtocolVersion::V2, None, guest_id.clone()),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect_as(&app_state, ProtocolVersion::V2, None, guest_id),
+    ];
+    pump(&mut clients);
+
+    let game_id = exchange(&mut clients, 0, json!({"type": "create"}))["game_id"].as_str().unwrap().to_string();
+    let reply = exchange(&mut clients, 2, json!({"type": "start_analysis", "game_id": game_id}));
+    assert_eq!(reply["code"], "analysis_not_allowed", "{}", reply);
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    exchange(&mut clients, 0, json!({"type": "move", "uci": "e2e4"}));
+
+    // Not even while watching the game in that tab
+    exchange(&mut clients, 2, json!({"type": "watch", "game_id": game_id}));
+    for message in [json!({"type": "start_analysis"}), json!({"type": "get_report", "game_id": game_id})] {
+        let reply = exchange(&mut clients, 2, message.clone());
+        assert_eq!(reply["code"], "analysis_not_allowed", "{} answered with {}", message, reply);
+    }
+
+    exchange(&mut clients, 1, json!({"type": "resign"}));
+    clients[2].messages.clear();
+    clients[2].send_text(json!({"type": "start_analysis", "game_id": game_id}).to_string());
+    let update = wait_for(&mut clients, 2, "analysis").await;
+    assert_eq!(update["game_id"], game_id.as_str(), "{}", update);
+}
@@ -0,0 +1,228 @@
+use chess::{Board, ChessMove};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::models::{GameState, HistoryEntry, Termination};
+use crate::time_control::TimeControl;
+use crate::{pgn, validation};
+
+/// Snapshot of a game for browsing, replaying and PGN export.
+///
+/// Finished and abandoned games are kept as records in the archive after
+/// their `GameState` is dropped; live games are turned into one on request.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GameRecord {
+    pub game_id: String,
+    pub status: GameRecordStatus,
+    pub white_player: Option<String>,
+    pub black_player: Option<String>,
+    /// The players as others see them: their usernames or `engine`, and
+    /// `None` for guests and in games archived before names were kept
+    #[serde(default)]
+    pub white_name: Option<String>,
+    #[serde(default)]
+    pub black_name: Option<String>,
+    pub initial_time_ms: u64,
+    pub increment_ms: u64,
+    /// Stages and clock mode, missing from games archived before time
+    /// controls had stages
+    #[serde(default)]
+    pub time_control: Option<TimeControl>,
+    /// FEN the game started from, if not the standard starting position
+    pub start_fen: Option<String>,
+    /// Moves in UCI notation
+    pub moves: Vec<String>,
+    /// The same moves in SAN
+    pub san: Vec<String>,
+    /// The moves with the positions and clocks after each, empty for games
+    /// archived before the history was kept
+    #[serde(default)]
+    pub history: Vec<HistoryEntry>,
+    /// PGN result (`1-0`, `0-1` or `1/2-1/2`) once the game is over
+    pub result: Option<String>,
+    pub termination: Option<Termination>,
+    pub created_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    /// Whether the result changed the players' ratings
+    #[serde(default)]
+    pub rated: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GameRecordStatus {
+    WaitingForOpponent,
+    InProgress,
+    Finished,
+    /// Removed before it finished because nobody reconnected
+    Abandoned,
+}
+
+impl GameRecord {
+    /// Snapshot of `game_state`, without the players' names, which need the
+    /// accounts to look up
+    pub fn from_state(game_id: &str, game_state: &GameState, status: GameRecordStatus) -> Self {
+        let moves = game_state
+            .game
+            .actions()
+            .iter()
+            .filter_map(|action| match action {
+                chess::Action::MakeMove(chess_move) => Some(chess_move.to_string()),
+                _ => None,
+            })
+            .collect();
+
+        let time_control = game_state.clock.control();
+        GameRecord {
+            game_id: game_id.to_string(),
+            status,
+            white_player: game_state.white_player.clone(),
+            black_player: game_state.black_player.clone(),
+            white_name: None,
+            black_name: None,
+            initial_time_ms: time_control.initial_time_ms(),
+            increment_ms: time_control.stages[0].increment_ms,
+            time_control: Some(time_control.clone()),
+            start_fen: game_state.start_fen.clone(),
+            moves,
+            san: pgn::san_moves(game_state.start_position, game_state.game.actions()),
+            history: game_state.history.clone(),
+            result: game_state.game_result.map(|result| pgn::result_token(Some(result)).to_string()),
+            termination: game_state.termination,
+            created_at: game_state.created_at,
+            ended_at: match status {
+                GameRecordStatus::Finished | GameRecordStatus::Abandoned => Some(Utc::now()),
+                _ => None,
+            },
+            rated: game_state.rated,
+        }
+    }
+
+    /// Status of a game still in memory
+    pub fn live_status(game_state: &GameState) -> GameRecordStatus {
+        if game_state.game_result.is_some() {
+            GameRecordStatus::Finished
+        } else if game_state.white_player.is_none() || game_state.black_player.is_none() {
+            GameRecordStatus::WaitingForOpponent
+        } else {
+            GameRecordStatus::InProgress
+        }
+    }
+
+    /// The starting position and the moves played from it, or `None` if the
+    /// record does not replay (which only a hand-edited archive would cause)
+    pub fn replay(&self) -> Option<(Board, Vec<ChessMove>)> {
+        let start_position = match self.start_fen.as_deref() {
+            Some(fen) => Board::from_str(fen).ok()?,
+            None => Board::default(),
+        };
+
+        let mut board = start_position;
+        let mut moves = Vec::new();
+        for uci in &self.moves {
+            let chess_move = validation::parse_uci(uci).ok().filter(|chess_move| board.legal(*chess_move))?;
+            board = board.make_move_new(chess_move);
+            moves.push(chess_move);
+        }
+        Some((start_position, moves))
+    }
+
+    /// The game's time control, which for older records is the base time and increment
+    pub fn time_control(&self) -> TimeControl {
+        self.time_control
+            .clone()
+            .unwrap_or_else(|| TimeControl::fischer(self.initial_time_ms, self.increment_ms))
+    }
+
+    pub fn has_player(&self, player: &str) -> bool {
+        self.white_player.as_deref() == Some(player) || self.black_player.as_deref() == Some(player)
+    }
+}
+
+/// Game list entry, a `GameRecord` without its moves
+#[derive(Serialize, Debug)]
+pub struct GameSummary {
+    pub game_id: String,
+    pub status: GameRecordStatus,
+    pub white_player: Option<String>,
+    pub black_player: Option<String>,
+    pub white_name: Option<String>,
+    pub black_name: Option<String>,
+    pub initial_time_ms: u64,
+    pub increment_ms: u64,
+    pub time_control: TimeControl,
+    pub start_fen: Option<String>,
+    pub move_count: usize,
+    pub result: Option<String>,
+    pub termination: Option<Termination>,
+    pub created_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub rated: bool,
+}
+
+impl From<&GameRecord> for GameSummary {
+    fn from(record: &GameRecord) -> Self {
+        GameSummary {
+            game_id: record.game_id.clone(),
+            status: record.status,
+            white_player: record.white_player.clone(),
+            black_player: record.black_player.clone(),
+            white_name: record.white_name.clone(),
+            black_name: record.black_name.clone(),
+            initial_time_ms: record.initial_time_ms,
+            increment_ms: record.increment_ms,
+            time_control: record.time_control(),
+            start_fen: record.start_fen.clone(),
+            move_count: record.moves.len(),
+            result: record.result.clone(),
+            termination: record.termination,
+            created_at: record.created_at,
+            ended_at: record.ended_at,
+            rated: record.rated,
+        }
+    }
+}
+
+/// Query string of `GET /games`
+#[derive(Deserialize, Debug)]
+pub struct GameQuery {
+    pub status: Option<GameRecordStatus>,
+    pub player: Option<String>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+/// One page of `GET /games`
+#[derive(Serialize, Debug)]
+pub struct GamePage {
+    pub games: Vec<GameSummary>,
+    /// Number of games matching the filters across all pages
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+
+impl GameQuery {
+    /// Filter `records` and return the requested page, newest games first
+    pub fn page(&self, mut records: Vec<GameRecord>) -> GamePage {
+        records.retain(|record| {
+            self.status.is_none_or(|status| record.status == status)
+                && self.player.as_deref().is_none_or(|player| record.has_player(player))
+        });
+        records.sort_by_key(|record| std::cmp::Reverse(record.created_at));
+
+        let offset = self.offset.unwrap_or(0);
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+        GamePage {
+            games: records.iter().skip(offset).take(limit).map(GameSummary::from).collect(),
+            total: records.len(),
+            offset,
+            limit,
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Tests for browsing the archive: filtering games and paging through them.
+
+use actix_web::test::{self as http, TestRequest};
+use actix_web::{web, App};
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+
+use crate::accounts::Credentials;
+use crate::archive::{GameQuery, GameRecord, GameRecordStatus};
+use crate::list_games;
+use crate::protocol::ProtocolVersion;
+use crate::test_support::{exchange, pump, test_app_state, NullStore, TestClient};
+
+/// A record of game `number`, created that many minutes ago
+fn record(number: usize, status: GameRecordStatus, white: Option<&str>, black: Option<&str>) -> GameRecord {
+    GameRecord {
+        game_id: format!("game-{}", number),
+        status,
+        white_player: white.map(str::to_string),
+        black_player: black.map(str::to_string),
+        white_name: None,
+        black_name: None,
+        initial_time_ms: 300_000,
+        increment_ms: 0,
+        time_control: None,
+        start_fen: None,
+        moves: Vec::new(),
+        san: Vec::new(),
+        history: Vec::new(),
+        result: None,
+        termination: None,
+        created_at: Utc::now() - Duration::minutes(number as i64),
+        ended_at: None,
+        rated: false,
+    }
+}
+
+fn query(status: Option<GameRecordStatus>, player: Option<&str>, offset: Option<usize>, limit: Option<usize>) -> GameQuery {
+    GameQuery { status, player: player.map(str::to_string), offset, limit }
+}
+
+#[test]
+fn pages_hold_the_newest_matching_games() {
+    use GameRecordStatus::*;
+
+    let records: Vec<GameRecord> = (0..50)
+        .map(|number| match number % 3 {
+            0 => record(number, Finished, Some("alice"), Some("bob")),
+            1 => record(number, InProgress, Some("bob"), Some("carol")),
+            _ => record(number, WaitingForOpponent, None, Some("alice")),
+        })
+        .rev()
+        .collect();
+    let game_ids = |query: GameQuery| -> (Vec<String>, usize) {
+        let page = query.page(records.clone());
+        (page.games.into_iter().map(|game| game.game_id).collect(), page.total)
+    };
+
+    // Newest first, 20 to a page by default
+    let page = query(None, None, None, None).page(records.clone());
+    assert_eq!((page.total, page.offset, page.limit, page.games.len()), (50, 0, 20, 20));
+    assert_eq!(page.games[0].game_id, "game-0");
+    assert_eq!(page.games[19].game_id, "game-19");
+
+    let (games, total) = game_ids(query(None, None, Some(45), Some(10)));
+    assert_eq!((games, total), (["game-45", "game-46", "game-47", "game-48", "game-49"].map(String::from).to_vec(), 50));
+    assert!(game_ids(query(None, None, Some(50), None)).0.is_empty());
+
+    // Filters apply before paging, and a player matches either seat
+    let (games, total) = game_ids(query(Some(Finished), None, Some(1), Some(2)));
+    assert_eq!((games, total), (["game-3", "game-6"].map(String::from).to_vec(), 17));
+    assert_eq!(game_ids(query(None, Some("alice"), None, Some(100))).1, 33);
+    assert_eq!(game_ids(query(None, Some("carol"), None, Some(100))).1, 17);
+    let (games, total) = game_ids(query(Some(WaitingForOpponent), Some("alice"), None, Some(1)));
+    assert_eq!((games, total), (vec!["game-2".to_string()], 16));
+    assert_eq!(game_ids(query(Some(Abandoned), None, None, None)).1, 0);
+    assert_eq!(game_ids(query(None, Some("mallory"), None, None)).1, 0);
+
+    // Page sizes stay between 1 and 100
+    assert_eq!(query(None, None, None, Some(0)).page(records.clone()).limit, 1);
+    assert_eq!(query(None, None, None, Some(1000)).page(records.clone()).limit, 100);
+}
+
+#[actix_rt::test]
+async fn users_are_found_by_username_and_listed_by_name() {
+    let app_state = test_app_state();
+    let credentials = Credentials {
+        username: "polgar".to_string(),
+        password: "long enough".to_string(),
+    };
+    let user = app_state.accounts.register(&NullStore, &credentials).unwrap();
+    let mut clients = vec![
+        TestClient::connect_as(&app_state, ProtocolVersion::V2, Some(user.clone()), None),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+    ];
+    pump(&mut clients);
+
+    let game_id = exchange(&mut clients, 0, json!({"type": "create", "color_preference": "black"}))["game_id"].clone();
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    exchange(&mut clients, 2, json!({"type": "create", "engine_level": 1}));
+
+    let app = http::init_service(App::new().app_data(app_state.clone()).service(web::resource("/games").route(web::get().to(list_games)))).await;
+    let games = |uri: &str| {
+        let request = TestRequest::get().uri(uri).to_request();
+        let app = &app;
+        async move { http::call_and_read_body_json::<_, _, Value>(app, request).await["games"].clone() }
+    };
+
+    let games_of_polgar = games("/games?player=polgar").await;
+    assert_eq!(games_of_polgar.as_array().map(Vec::len), Some(1), "{}", games_of_polgar);
+    assert_eq!(games_of_polgar[0]["game_id"], game_id);
+    assert_eq!((games_of_polgar[0]["white_name"].clone(), games_of_polgar[0]["black_name"].as_str()), (Value::Null, Some("polgar")));
+
+    // Guests stay anonymous and the engine is named; ids match as before
+    let engine_games = games("/games?player=engine").await;
+    assert_eq!((engine_games[0]["white_name"].clone(), engine_games[0]["black_name"].as_str()), (Value::Null, Some("engine")), "{}", engine_games);
+    assert_eq!(games(&format!("/games?player={}", user.id)).await.as_array().map(Vec::len), Some(1));
+    assert_eq!(games("/games?player=nobody").await, json!([]));
+}
@@ -0,0 +1,152 @@
+use actix::prelude::*;
+use actix_web::web;
+use chess::{Color, GameResult};
+use log::info;
+use std::time::{Duration, Instant};
+
+use crate::models::{GameState, Termination};
+use crate::{game_over_message, has_insufficient_material, AppState};
+
+/// Server-side clock for a single game.
+///
+/// The clock sleeps until the side to move would run out of time and then ends
+/// the game itself, so a player who stops moving still loses on time even when
+/// no client is sending `time_sync` requests.
+pub struct GameClock {
+    game_id: String,
+    app_state: web::Data<AppState>,
+    timer: Option<SpawnHandle>,
+}
+
+/// Re-arm the clock after the game state changed (a move, a join, a sync)
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ResetClock;
+
+/// Stop the clock when its game is removed
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StopClock;
+
+impl GameClock {
+    pub fn new(game_id: String, app_state: web::Data<AppState>) -> Self {
+        GameClock {
+            game_id,
+            app_state,
+            timer: None,
+        }
+    }
+
+    fn schedule(&mut self, ctx: &mut Context<Self>) {
+        if let Some(handle) = self.timer.take() {
+            ctx.cancel_future(handle);
+        }
+
+        let remaining = {
+            let games = self.app_state.games.lock().unwrap();
+            games.get(&self.game_id).and_then(|game_state| time_left(game_state, self.app_state.now()))
+        };
+
+        if let Some((color, remaining_ms)) = remaining {
+            info!("Clock for game {} armed: {:?} flags in {} ms", self.game_id, color, remaining_ms);
+            self.timer = Some(ctx.run_later(Duration::from_millis(remaining_ms), |act, ctx| {
+                act.timer = None;
+                act.check_flag(ctx);
+            }));
+        }
+    }
+
+    fn check_flag(&mut self, ctx: &mut Context<Self>) {
+        let mut games = self.app_state.games.lock().unwrap();
+        let game_state = match games.get_mut(&self.game_id) {
+            Some(game_state) => game_state,
+            None => {
+                info!("Game {} no longer exists, stopping its clock", self.game_id);
+                ctx.stop();
+                return;
+            }
+        };
+
+        let now = self.app_state.now();
+        match time_left(game_state, now) {
+            Some((color, 0)) => {
+                flag_player(game_state, color);
+                self.app_state.record_finished(&self.game_id, game_state);
+
+                let msg = game_over_message(&self.game_id, game_state, Some(color), now);
+
+                // Drop the lock before broadcasting
+                drop(games);
+
+                info!("Broadcasting game_over for game {}", self.game_id);
+                self.app_state.broadcast_to_game(&self.game_id, &msg, None);
+            }
+            Some(_) => {
+                // The player moved (or time was synced) since the timer was armed
+                drop(games);
+                self.schedule(ctx);
+            }
+            None => {}
+        }
+    }
+}
+
+impl Actor for GameClock {
+    type Context = Context<Self>;
+}
+
+impl Handler<ResetClock> for GameClock {
+    type Result = ();
+
+    fn handle(&mut self, _: ResetClock, ctx: &mut Self::Context) {
+        self.schedule(ctx);
+    }
+}
+
+impl Handler<StopClock> for GameClock {
+    type Result = ();
+
+    fn handle(&mut self, _: StopClock, ctx: &mut Self::Context) {
+        info!("Stopping clock for game {}", self.game_id);
+        ctx.stop();
+    }
+}
+
+/// How long until the side to move runs out of time, or `None` if the clock is
+/// not running or is left to the correspondence sweeper
+fn time_left(game_state: &GameState, now: Instant) -> Option<(Color, u64)> {
+    if game_state.game_result.is_some() || game_state.game.result().is_some() || game_state.is_correspondence() {
+        return None;
+    }
+
+    game_state.clock.time_to_flag(now)
+}
+
+/// End the game because `color` ran out of time
+pub fn flag_player(game_state: &mut GameState, color: Color) {
+    // A player who runs out of time only loses if the opponent could still mate
+    let draw = has_insufficient_material(&game_state.game.current_position());
+    game_state.termination = Some(Termination::Timeout);
+    game_state.clock.flag(color);
+
+    match color {
+        Color::White => {
+            if draw {
+                info!("White lost on time but opponent has insufficient material - draw");
+                game_state.game_result = Some(GameResult::DrawDeclared);
+            } else {
+                info!("White lost on time");
+                game_state.game_result = Some(GameResult::WhiteResigns);
+            }
+        }
+        Color::Black => {
+            if draw {
+                info!("Black lost on time but opponent has insufficient material - draw");
+                game_state.game_result = Some(GameResult::DrawDeclared);
+            } else {
+                info!("Black lost on time");
+                game_state.game_result = Some(GameResult::BlackResigns);
+            }
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Tests for the server-side game clock: players losing on time, and moves
+//! that arrive after the mover's time ran out.
+
+use serde_json::json;
+
+use crate::protocol::ProtocolVersion;
+use crate::test_support::{exchange, pump, test_app_state_from, FakeTime, TestClient};
+
+const MINUTE: u64 = 60 * 1000;
+
+#[actix_rt::test]
+async fn a_move_after_the_flag_loses_on_time() {
+    let time = FakeTime::new();
+    let app_state = test_app_state_from(None, time.clone());
+    let mut clients = vec![
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+    ];
+    pump(&mut clients);
+
+    let create = json!({"type": "create", "color_preference": "white", "start_time_minutes": 1, "increment_seconds": 0});
+    let game_id = exchange(&mut clients, 0, create)["game_id"].as_str().unwrap().to_string();
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    exchange(&mut clients, 0, json!({"type": "move", "uci": "e2e4"}));
+
+    // With time left the move counts, however close it was
+    time.advance(MINUTE - 1);
+    let reply = exchange(&mut clients, 1, json!({"type": "move", "uci": "e7e5"}));
+    assert_eq!(reply["type"], "move_made", "{}", reply);
+    assert_eq!(reply["black_time_ms"], 1);
+
+    // Past it, the move is refused and everyone hears White lost on time
+    time.advance(MINUTE);
+    let reply = exchange(&mut clients, 0, json!({"type": "move", "uci": "g1f3"}));
+    assert_eq!(reply["code"], "game_over", "{}", reply);
+    for client in &clients {
+        let game_over = client.messages.iter().find(|message| message["type"] == "game_over").expect("no game_over");
+        assert_eq!((game_over["termination"].as_str(), game_over["game_status"].as_str()), (Some("timeout"), Some("black_wins")));
+        assert_eq!(game_over["white_time_ms"], 0);
+    }
+
+    let record = app_state.find_game_record(&game_id).unwrap();
+    assert_eq!(record.history.len(), 2, "the refused move is not played");
+    assert_eq!(record.result.as_deref(), Some("0-1"));
+}
@@ -0,0 +1,79 @@
+//! Correspondence games, with days for every move.
+//!
+//! Nobody keeps a socket open for days, so these games stay in memory without
+//! connections, and a player learns about the opponent's move when they
+//! reconnect. Their timeouts are found by a sweeper that looks at every
+//! correspondence game now and then, instead of per-game timers.
+
+use actix_web::web;
+use chess::Color;
+use chrono::Utc;
+use log::info;
+use std::time::{Duration, Instant};
+
+use crate::models::{GameState, ServerMessage};
+use crate::{clock, game_over_message, schedule_abandoned_game_removal, AppState};
+
+/// Run `sweep` every `interval` for as long as the server runs
+pub fn start_sweeper(app_state: web::Data<AppState>, interval: Duration) {
+    actix::spawn(async move {
+        loop {
+            actix::clock::sleep(interval).await;
+            sweep(&app_state);
+        }
+    });
+}
+
+/// End every correspondence game whose side to move has run out of time,
+/// returning how many were ended. Those nobody is connected to are removed
+/// after the usual grace period.
+pub fn sweep(app_state: &web::Data<AppState>) -> usize {
+    let now = app_state.now();
+    let mut games = app_state.games.lock().unwrap();
+
+    let mut game_overs = Vec::new();
+    for (game_id, game_state) in games.iter_mut() {
+        if !game_state.is_correspondence() || game_state.game_result.is_some() {
+            continue;
+        }
+        if let Some(color) = game_state.clock.flagged(now) {
+            info!("{:?} ran out of days in correspondence game {}", color, game_id);
+            clock::flag_player(game_state, color);
+            app_state.record_finished(game_id, game_state);
+            game_overs.push((game_id.clone(), game_over_message(game_id, game_state, Some(color), now)));
+        }
+    }
+
+    // Drop the lock before broadcasting
+    drop(games);
+
+    for (game_id, message) in &game_overs {
+        app_state.broadcast_to_game(game_id, message, None);
+        // A finished game is only kept while someone is still looking at it
+        let connected = app_state.connections.lock().unwrap().get(game_id).is_some_and(|ids| !ids.is_empty());
+        if !connected {
+            schedule_abandoned_game_removal(app_state.clone(), game_id.clone());
+        }
+    }
+    game_overs.len()
+}
+
+/// The opponent's last move, for a player of `color` coming back to a game in
+/// which it is their move
+pub fn opponent_moved(game_id: &str, game_state: &GameState, color: Color, now: Instant) -> Option<ServerMessage> {
+    let to_move = game_state.game.side_to_move();
+    if !game_state.is_correspondence() || game_state.game_result.is_some() || to_move != color {
+        return None;
+    }
+
+    let last_move = game_state.history.last()?;
+    let time_left = Duration::from_millis(game_state.clock.time_left_ms(color, now));
+    Some(ServerMessage::OpponentMoved {
+        game_id: game_id.to_string(),
+        san: last_move.san.clone(),
+        uci: last_move.uci.clone(),
+        fen: last_move.fen.clone(),
+        played_at: last_move.played_at,
+        move_deadline: Utc::now() + chrono::Duration::from_std(time_left).unwrap_or_default(),
+    })
+}
@@ -0,0 +1,94 @@
+//! Tests for creating games: which starting positions are accepted, and
+//! which colors the creator and the joiner get.
+
+use chess::Color;
+use serde_json::json;
+
+use crate::parse_start_position;
+use crate::protocol::ProtocolVersion;
+use crate::test_support::{exchange, pump, test_app_state, TestClient};
+
+#[test]
+fn starting_positions_must_be_playable() {
+    let rejected = [
+        ("4k3/8/8/8/8/8/8/4K3 w - - 0 1 extra", "between 4 and 6"),
+        ("4k3/8/8/8/8/8/4K3 w - -", "8 ranks"),
+        ("4k3/8/8/8/8/8/8/4K4 w - -", "8 squares"),
+        ("4k3/8/8/8/8/8/8/4K2X w - -", "unexpected character X"),
+        ("4k3/8/8/8/8/8/8/4K3 w - - -1 1", "halfmove clock"),
+        ("4k3/8/8/8/8/8/8/4K3 x - -", "not a valid FEN"),
+        ("8/8/8/8/8/8/8/4K3 w - -", "black must have exactly one king"),
+        ("4k3/8/8/8/8/8/8/3KK3 w - -", "white must have exactly one king"),
+        ("4k3/8/8/8/8/8/8/P3K3 w - -", "first or last rank"),
+        ("4k3/4R3/8/8/8/8/8/4K3 w - -", "side not to move is in check"),
+        ("4k3/8/8/8/8/8/8/4K3 w K -", "castling"),
+        ("k7/2Q5/1K6/8/8/8/8/8 b - -", "no legal moves"),
+        ("k7/1Q6/1K6/8/8/8/8/8 b - -", "no legal moves"),
+    ];
+    for (fen, reason) in rejected {
+        let error = parse_start_position(fen).expect_err(fen);
+        assert!(error.starts_with("Invalid starting position: ") && error.contains(reason), "{}: {}", fen, error);
+    }
+
+    let (board, halfmove_clock) = parse_start_position("4k3/8/8/8/8/8/4P3/4K3 b - - 12 40").unwrap();
+    assert_eq!((board.side_to_move(), halfmove_clock), (Color::Black, 12));
+    assert_eq!(parse_start_position("r3k3/8/8/8/8/8/8/4K2R w Kq -").unwrap().1, 0);
+}
+
+#[actix_rt::test]
+async fn games_start_from_an_accepted_fen_only() {
+    let app_state = test_app_state();
+    let mut clients = vec![TestClient::connect(&app_state, ProtocolVersion::V2)];
+    pump(&mut clients);
+
+    let reply = exchange(&mut clients, 0, json!({"type": "create", "start_fen": "4k3/8/8/8/8/8/8/4K3 w K -"}));
+    assert_eq!(reply["code"], "invalid_position", "{}", reply);
+    assert!(reply["message"].as_str().unwrap().contains("castling"), "{}", reply);
+    assert!(app_state.games.lock().unwrap().is_empty());
+
+    // Surrounding whitespace is ignored, and so is an empty FEN
+    let reply = exchange(&mut clients, 0, json!({"type": "create", "start_fen": " 4k3/8/8/8/8/8/4P3/4K3 b - - 0 30 "}));
+    assert_eq!(reply["type"], "game_created", "{}", reply);
+    assert_eq!(reply["active_color"], "black");
+    let game_id = reply["game_id"].as_str().unwrap().to_string();
+    assert_eq!(app_state.find_game_record(&game_id).unwrap().start_fen.as_deref(), Some("4k3/8/8/8/8/8/4P3/4K3 b - - 0 30"));
+
+    let reply = exchange(&mut clients, 0, json!({"type": "create", "start_fen": ""}));
+    assert_eq!(reply["active_color"], "white", "{}", reply);
+    assert_eq!(app_state.find_game_record(reply["game_id"].as_str().unwrap()).unwrap().start_fen, None);
+}
+
+#[actix_rt::test]
+async fn the_joiner_takes_the_color_the_creator_left() {
+    let app_state = test_app_state();
+    let mut clients = vec![
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+    ];
+    pump(&mut clients);
+
+    for (preference, creator, joiner) in [(json!("white"), "white", "black"), (json!("black"), "black", "white"), (json!(null), "white", "black")] {
+        let reply = exchange(&mut clients, 0, json!({"type": "create", "color_preference": preference}));
+        assert_eq!(reply["color"], creator, "{}", reply);
+        let game_id = reply["game_id"].clone();
+        let reply = exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+        assert_eq!((reply["type"].as_str(), reply["color"].as_str()), (Some("joined"), Some(joiner)), "{}", reply);
+        let player_joined = clients[0].messages.iter().find(|message| message["type"] == "player_joined").expect("no player_joined");
+        assert_eq!(player_joined["color"], joiner);
+
+        let reply = exchange(&mut clients, 2, json!({"type": "join", "game_id": game_id}));
+        assert_eq!(reply["code"], "game_full", "{}", reply);
+    }
+
+    // A random preference gives the creator either color, and the joiner the other
+    let mut creator_colors = Vec::new();
+    for _ in 0..32 {
+        let reply = exchange(&mut clients, 0, json!({"type": "create", "color_preference": "random"}));
+        let creator = reply["color"].as_str().unwrap().to_string();
+        let reply = exchange(&mut clients, 1, json!({"type": "join", "game_id": reply["game_id"]}));
+        assert_ne!(reply["color"], creator.as_str(), "{}", reply);
+        creator_colors.push(creator);
+    }
+    assert!(creator_colors.iter().any(|color| color == "white") && creator_colors.iter().any(|color| color == "black"));
+}
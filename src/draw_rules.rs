@@ -0,0 +1,75 @@
+use chess::{Action, Board, Color, Game, Piece};
+
+use crate::models::Termination;
+
+/// Repetition count and halfmove clock of a game's current position.
+///
+/// The `chess` crate drops the move counters of a FEN it reads, always writes
+/// `0 1` instead, and its `Game::can_declare_draw` does not say which rule
+/// applies, so both are tracked here by replaying the game's moves.
+pub struct DrawRules {
+    /// How many times the current position has occurred, including now
+    pub repetitions: usize,
+    /// Half-moves since the last capture or pawn move
+    pub halfmove_clock: u32,
+}
+
+impl DrawRules {
+    /// Replay `game` from `start_position`, the board it was created with,
+    /// whose FEN gave `start_halfmove_clock`
+    pub fn from_game(start_position: Board, start_halfmove_clock: u32, game: &Game) -> Self {
+        let mut board = start_position;
+        let mut halfmove_clock = start_halfmove_clock;
+        let mut positions = vec![board.get_hash()];
+
+        for action in game.actions() {
+            if let Action::MakeMove(chess_move) = action {
+                let irreversible = board.piece_on(chess_move.get_source()) == Some(Piece::Pawn)
+                    || board.piece_on(chess_move.get_dest()).is_some();
+                let castle_rights = (board.castle_rights(Color::White), board.castle_rights(Color::Black));
+
+                board = board.make_move_new(*chess_move);
+
+                if irreversible {
+                    halfmove_clock = 0;
+                } else {
+                    halfmove_clock = halfmove_clock.saturating_add(1);
+                }
+
+                // Positions before a capture, pawn move or loss of castling rights can never recur
+                if irreversible || castle_rights != (board.castle_rights(Color::White), board.castle_rights(Color::Black)) {
+                    positions.clear();
+                }
+                positions.push(board.get_hash());
+            }
+        }
+
+        let current = board.get_hash();
+        DrawRules {
+            repetitions: positions.iter().filter(|hash| **hash == current).count(),
+            halfmove_clock,
+        }
+    }
+
+    /// Draw that ends the game without a claim (fivefold repetition or the 75-move rule)
+    pub fn automatic_draw(&self) -> Option<Termination> {
+        if self.repetitions >= 5 {
+            Some(Termination::FivefoldRepetition)
+        } else if self.halfmove_clock >= 150 {
+            Some(Termination::SeventyFiveMoveRule)
+        } else {
+            None
+        }
+    }
+
+    /// Draw a player may claim (threefold repetition or the 50-move rule)
+    pub fn claimable_draw(&self) -> Option<Termination> {
+        if self.repetitions >= 3 {
+            Some(Termination::ThreefoldRepetition)
+        } else if self.halfmove_clock >= 100 {
+            Some(Termination::FiftyMoveRule)
+        } else {
+            None
+        }
+    }
+}
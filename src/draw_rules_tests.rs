@@ -0,0 +1,95 @@
+//! Tests for repetition and fifty-move rule draws, counted from the position
+//! a game started in.
+
+use chess::{Board, ChessMove, Game};
+use serde_json::json;
+use std::str::FromStr;
+
+use crate::draw_rules::DrawRules;
+use crate::models::Termination;
+use crate::protocol::ProtocolVersion;
+use crate::test_support::{exchange, pump, test_app_state, TestClient};
+use crate::validation;
+
+/// Play UCI moves from `fen` and look at the draw rules afterwards
+fn draw_rules_after(fen: &str, moves: &[&str]) -> DrawRules {
+    let start_position = Board::from_str(fen).unwrap();
+    let mut game = Game::new_with_board(start_position);
+    for uci in moves {
+        assert!(game.make_move(ChessMove::from_str(uci).unwrap()), "{} is not legal", uci);
+    }
+    DrawRules::from_game(start_position, validation::fen_halfmove_clock(fen), &game)
+}
+
+const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+const KNIGHTS_OUT_AND_BACK: [&str; 4] = ["g1f3", "g8f6", "f3g1", "f6g8"];
+
+#[test]
+fn repetitions_are_claimable_at_three_and_automatic_at_five() {
+    let twice: Vec<&str> = KNIGHTS_OUT_AND_BACK.repeat(2);
+    let rules = draw_rules_after(START, &twice[..7]);
+    assert_eq!(rules.repetitions, 2);
+    assert_eq!(rules.claimable_draw(), None);
+
+    let rules = draw_rules_after(START, &twice);
+    assert_eq!(rules.repetitions, 3);
+    assert_eq!(rules.claimable_draw(), Some(Termination::ThreefoldRepetition));
+    assert_eq!(rules.automatic_draw(), None);
+
+    let rules = draw_rules_after(START, &KNIGHTS_OUT_AND_BACK.repeat(4));
+    assert_eq!(rules.automatic_draw(), Some(Termination::FivefoldRepetition));
+
+    // A pawn move means the earlier positions can never come back
+    let mut moves = KNIGHTS_OUT_AND_BACK.repeat(2);
+    moves.extend(["e2e4", "e7e5"]);
+    moves.extend(KNIGHTS_OUT_AND_BACK);
+    assert_eq!(draw_rules_after(START, &moves).repetitions, 2);
+}
+
+#[test]
+fn the_move_rules_count_on_from_the_starting_fen() {
+    let rules = draw_rules_after("4k3/8/8/8/8/8/4P3/4K1N1 w - - 98 60", &["g1f3"]);
+    assert_eq!(rules.halfmove_clock, 99);
+    assert_eq!(rules.claimable_draw(), None);
+
+    let rules = draw_rules_after("4k3/8/8/8/8/8/4P3/4K1N1 w - - 98 60", &["g1f3", "e8d8"]);
+    assert_eq!(rules.claimable_draw(), Some(Termination::FiftyMoveRule));
+    assert_eq!(rules.automatic_draw(), None);
+
+    let rules = draw_rules_after("4k3/8/8/8/8/8/4P3/4K1N1 w - - 149 90", &["g1f3"]);
+    assert_eq!(rules.automatic_draw(), Some(Termination::SeventyFiveMoveRule));
+
+    // A pawn move or a capture starts the count again
+    let rules = draw_rules_after("4k3/8/8/8/8/8/4P3/4K1N1 w - - 149 90", &["e2e4"]);
+    assert_eq!(rules.halfmove_clock, 0);
+
+    // FENs without the move counters start from zero
+    assert_eq!(validation::fen_halfmove_clock("4k3/8/8/8/8/8/4P3/4K1N1 w - -"), 0);
+}
+
+#[actix_rt::test]
+async fn games_from_a_fen_end_on_its_move_rules() {
+    let app_state = test_app_state();
+    let mut clients = vec![
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+    ];
+    pump(&mut clients);
+
+    let reply = exchange(&mut clients, 0, json!({"type": "create", "start_fen": "4k3/8/8/8/8/8/4P3/4K1N1 w - - x 60"}));
+    assert_eq!(reply["code"], "invalid_position", "{}", reply);
+
+    let create = json!({"type": "create", "color_preference": "white", "start_fen": "4k3/8/8/8/8/8/4P3/4K1N1 w - - 99 60"});
+    let game_id = exchange(&mut clients, 0, create)["game_id"].clone();
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    let reply = exchange(&mut clients, 0, json!({"type": "move", "uci": "g1f3"}));
+    assert_eq!((reply["game_status"].as_str(), reply["draw_claim"].as_str()), (Some("black_turn"), Some("fifty_move_rule")), "{}", reply);
+    let reply = exchange(&mut clients, 1, json!({"type": "claim_draw"}));
+    assert_eq!((reply["game_status"].as_str(), reply["termination"].as_str()), (Some("draw"), Some("fifty_move_rule")), "{}", reply);
+
+    let create = json!({"type": "create", "color_preference": "white", "start_fen": "4k3/8/8/8/8/8/4P3/4K1N1 w - - 149 90"});
+    let game_id = exchange(&mut clients, 0, create)["game_id"].clone();
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    let reply = exchange(&mut clients, 0, json!({"type": "move", "uci": "g1f3"}));
+    assert_eq!((reply["game_status"].as_str(), reply["termination"].as_str()), (Some("draw"), Some("seventy_five_move_rule")), "{}", reply);
+}
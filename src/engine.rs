@@ -0,0 +1,512 @@
+//! Built-in computer opponent.
+//!
+//! An alpha-beta search over `chess::MoveGen` with iterative deepening, a
+//! transposition table and a quiescence search on captures, scoring positions
+//! by material and piece-square tables. Strength levels cap the search depth
+//! and thinking time. The same search analyses positions for spectators and
+//! post-game reports.
+
+use chess::{Action, Board, ChessMove, Color, Game, MoveGen, Piece, ALL_PIECES, EMPTY};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Player ID the engine is seated under, in place of a connection ID
+pub const ENGINE_PLAYER_ID: &str = "engine";
+
+pub const MIN_LEVEL: u8 = 1;
+pub const MAX_LEVEL: u8 = 8;
+
+/// Search depth and thinking time in milliseconds per level, weakest first
+const LEVELS: [(u8, u64); 8] = [(1, 50), (2, 100), (3, 250), (4, 500), (5, 1000), (6, 2000), (8, 4000), (64, 8000)];
+
+const MATE: i32 = 30_000;
+const INFINITY: i32 = 32_000;
+/// Scores beyond this are mates
+const MATE_THRESHOLD: i32 = MATE - 1000;
+/// Quiescence search stops following captures this far from the root
+const MAX_PLY: i32 = 64;
+/// The table is cleared when it grows past this many positions
+const MAX_TABLE_ENTRIES: usize = 1 << 20;
+
+/// How long and how deep the engine may think about one move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchLimits {
+    pub max_depth: u8,
+    pub max_time: Duration,
+}
+
+impl SearchLimits {
+    /// Limits for a strength level, shortened when the engine's clock runs low:
+    /// it spends at most a thirtieth of its remaining time plus most of the increment
+    pub fn for_level(level: u8, time_left_ms: u64, increment_ms: u64) -> Self {
+        let (max_depth, level_time_ms) = LEVELS[(level.clamp(MIN_LEVEL, MAX_LEVEL) - MIN_LEVEL) as usize];
+        let clock_time_ms = (time_left_ms / 30 + increment_ms * 3 / 4).min(time_left_ms / 2);
+
+        SearchLimits {
+            max_depth,
+            max_time: Duration::from_millis(level_time_ms.min(clock_time_ms)),
+        }
+    }
+}
+
+/// Hashes of the positions a game went through, oldest first, so the search
+/// can score a return to one of them as a draw
+pub fn positions_played(start_position: Board, game: &Game) -> Vec<u64> {
+    let mut board = start_position;
+    let mut positions = vec![board.get_hash()];
+    for action in game.actions() {
+        if let Action::MakeMove(chess_move) = action {
+            board = board.make_move_new(*chess_move);
+            positions.push(board.get_hash());
+        }
+    }
+    positions
+}
+
+/// The move the engine plays in `board`, or `None` if the game is over.
+///
+/// The first iteration always completes, so a move is found even when the
+/// time limit is zero.
+pub fn best_move(board: &Board, positions: &[u64], limits: SearchLimits) -> Option<ChessMove> {
+    let moves: Vec<ChessMove> = MoveGen::new_legal(board).collect();
+    match moves.as_slice() {
+        [] => return None,
+        [only_move] => return Some(*only_move),
+        _ => {}
+    }
+
+    let mut best = moves[0];
+    analyse(board, positions, limits, &AtomicBool::new(false), |line| best = line.pv[0]);
+    Some(best)
+}
+
+/// An evaluation from the side to move's point of view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Score {
+    Centipawns(i32),
+    /// Mate in this many moves, negative when the side to move is getting mated
+    Mate(i32),
+}
+
+impl Score {
+    fn from_search(score: i32) -> Self {
+        // Mate scores count the plies to the mate down from `MATE`
+        if score >= MATE_THRESHOLD {
+            Score::Mate((MATE - score + 1) / 2)
+        } else if score <= -MATE_THRESHOLD {
+            Score::Mate(-(MATE + score) / 2)
+        } else {
+            Score::Centipawns(score)
+        }
+    }
+}
+
+/// The result of one iteration of an analysis
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub depth: u8,
+    pub score: Score,
+    /// The best move followed by the expected replies, never empty
+    pub pv: Vec<ChessMove>,
+}
+
+/// Search `board` deeper and deeper, reporting each finished depth to
+/// `on_line`, until the limits are reached or `stop` is set. Nothing is
+/// reported when the side to move has no legal moves.
+pub fn analyse(board: &Board, positions: &[u64], limits: SearchLimits, stop: &AtomicBool, mut on_line: impl FnMut(Line)) {
+    let moves: Vec<ChessMove> = MoveGen::new_legal(board).collect();
+    if moves.is_empty() {
+        return;
+    }
+
+    let mut search = Search {
+        deadline: Instant::now() + limits.max_time,
+        stop,
+        table: HashMap::new(),
+        path: positions.to_vec(),
+        nodes: 0,
+        can_stop: false,
+        stopped: false,
+    };
+
+    let mut best = moves[0];
+    for depth in 1..=limits.max_depth.max(1) {
+        search.can_stop = depth > 1;
+        let (chess_move, score) = search.root(board, depth, best);
+        if search.stopped {
+            break;
+        }
+        best = chess_move;
+        on_line(Line {
+            depth,
+            score: Score::from_search(score),
+            pv: search.principal_variation(board, best, depth),
+        });
+
+        // A forced mate will not get any better, and a new iteration would not finish in time
+        if score.abs() >= MATE_THRESHOLD || Instant::now() >= search.deadline {
+            break;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Bound {
+    Exact,
+    /// The score is at least this (the search failed high)
+    Lower,
+    /// The score is at most this (the search failed low)
+    Upper,
+}
+
+#[derive(Clone, Copy)]
+struct TableEntry {
+    depth: u8,
+    score: i32,
+    bound: Bound,
+    best_move: Option<ChessMove>,
+}
+
+struct Search<'a> {
+    deadline: Instant,
+    /// Set from outside to abandon the search
+    stop: &'a AtomicBool,
+    /// Transposition table keyed by position hash
+    table: HashMap<u64, TableEntry>,
+    /// Positions of the game and of the line being searched
+    path: Vec<u64>,
+    nodes: u64,
+    /// Whether running out of time may abandon the current iteration
+    can_stop: bool,
+    stopped: bool,
+}
+
+impl Search<'_> {
+    fn root(&mut self, board: &Board, depth: u8, previous_best: ChessMove) -> (ChessMove, i32) {
+        let mut alpha = -INFINITY;
+        let mut best = (previous_best, -INFINITY);
+
+        for chess_move in ordered_moves(board, Some(previous_best)) {
+            let score = -self.negamax(&board.make_move_new(chess_move), depth - 1, -INFINITY, -alpha, 1);
+            if self.stopped {
+                break;
+            }
+            if score > best.1 {
+                best = (chess_move, score);
+            }
+            alpha = alpha.max(score);
+        }
+
+        best
+    }
+
+    fn out_of_time(&mut self) -> bool {
+        self.nodes += 1;
+        if self.can_stop
+            && self.nodes.is_multiple_of(1024)
+            && (Instant::now() >= self.deadline || self.stop.load(Ordering::Relaxed))
+        {
+            self.stopped = true;
+        }
+        self.stopped
+    }
+
+    /// The best move followed by the table's best replies, as far as the
+    /// table remembers them and at most `depth` moves long
+    fn principal_variation(&self, board: &Board, best_move: ChessMove, depth: u8) -> Vec<ChessMove> {
+        let mut pv = vec![best_move];
+        let mut board = board.make_move_new(best_move);
+        let mut seen = vec![board.get_hash()];
+        while pv.len() < depth as usize {
+            let chess_move = match self.table.get(&board.get_hash()).and_then(|entry| entry.best_move) {
+                Some(chess_move) if board.legal(chess_move) => chess_move,
+                _ => break,
+            };
+            board = board.make_move_new(chess_move);
+            // Stop where the line starts going round in circles
+            if seen.contains(&board.get_hash()) {
+                break;
+            }
+            seen.push(board.get_hash());
+            pv.push(chess_move);
+        }
+        pv
+    }
+
+    fn negamax(&mut self, board: &Board, depth: u8, mut alpha: i32, mut beta: i32, ply: i32) -> i32 {
+        if self.out_of_time() {
+            return 0;
+        }
+
+        let hash = board.get_hash();
+        if self.path.contains(&hash) {
+            return 0;
+        }
+
+        if depth == 0 {
+            return self.quiescence(board, alpha, beta, ply);
+        }
+
+        let original_alpha = alpha;
+        let table_entry = self.table.get(&hash).copied();
+        if let Some(entry) = table_entry.filter(|entry| entry.depth >= depth) {
+            match entry.bound {
+                Bound::Exact => return entry.score,
+                Bound::Lower => alpha = alpha.max(entry.score),
+                Bound::Upper => beta = beta.min(entry.score),
+            }
+            if alpha >= beta {
+                return entry.score;
+            }
+        }
+
+        let moves = ordered_moves(board, table_entry.and_then(|entry| entry.best_move));
+        if moves.is_empty() {
+            return if *board.checkers() != EMPTY { -MATE + ply } else { 0 };
+        }
+
+        self.path.push(hash);
+        let mut best_score = -INFINITY;
+        let mut best_move = None;
+        for chess_move in moves {
+            let score = -self.negamax(&board.make_move_new(chess_move), depth - 1, -beta, -alpha, ply + 1);
+            if self.stopped {
+                break;
+            }
+            if score > best_score {
+                best_score = score;
+                best_move = Some(chess_move);
+            }
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        self.path.pop();
+
+        if !self.stopped {
+            if self.table.len() >= MAX_TABLE_ENTRIES {
+                self.table.clear();
+            }
+            let bound = if best_score <= original_alpha {
+                Bound::Upper
+            } else if best_score >= beta {
+                Bound::Lower
+            } else {
+                Bound::Exact
+            };
+            self.table.insert(hash, TableEntry {
+                depth,
+                score: best_score,
+                bound,
+                best_move,
+            });
+        }
+
+        best_score
+    }
+
+    /// Follow captures (or every evasion when in check) until the position is quiet
+    fn quiescence(&mut self, board: &Board, mut alpha: i32, beta: i32, ply: i32) -> i32 {
+        if self.out_of_time() {
+            return 0;
+        }
+
+        let in_check = *board.checkers() != EMPTY;
+        if !in_check {
+            let stand_pat = evaluate(board);
+            if stand_pat >= beta || ply >= MAX_PLY {
+                return stand_pat;
+            }
+            alpha = alpha.max(stand_pat);
+        }
+
+        let mut moves = MoveGen::new_legal(board);
+        if in_check {
+            if moves.len() == 0 {
+                return -MATE + ply;
+            }
+        } else {
+            moves.set_iterator_mask(*board.color_combined(!board.side_to_move()));
+        }
+
+        let mut captures: Vec<ChessMove> = moves.collect();
+        captures.sort_by_key(|chess_move| Reverse(move_order_score(board, *chess_move)));
+
+        let mut best_score = if in_check { -INFINITY } else { alpha };
+        for chess_move in captures {
+            let score = -self.quiescence(&board.make_move_new(chess_move), -beta, -alpha, ply + 1);
+            if self.stopped {
+                break;
+            }
+            best_score = best_score.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        best_score
+    }
+}
+
+/// Legal moves, most promising first: the table move, then captures of the
+/// most valuable piece by the least valuable one, then promotions
+fn ordered_moves(board: &Board, table_move: Option<ChessMove>) -> Vec<ChessMove> {
+    let mut moves: Vec<ChessMove> = MoveGen::new_legal(board).collect();
+    moves.sort_by_key(|chess_move| {
+        if Some(*chess_move) == table_move {
+            Reverse(i32::MAX)
+        } else {
+            Reverse(move_order_score(board, *chess_move))
+        }
+    });
+    moves
+}
+
+fn move_order_score(board: &Board, chess_move: ChessMove) -> i32 {
+    let capture = board
+        .piece_on(chess_move.get_dest())
+        .map_or(0, |victim| 10 * piece_value(victim) - board.piece_on(chess_move.get_source()).map_or(0, piece_value));
+    capture + chess_move.get_promotion().map_or(0, piece_value)
+}
+
+fn piece_value(piece: Piece) -> i32 {
+    match piece {
+        Piece::Pawn => 100,
+        Piece::Knight => 320,
+        Piece::Bishop => 330,
+        Piece::Rook => 500,
+        Piece::Queen => 900,
+        Piece::King => 0,
+    }
+}
+
+/// Material and piece placement from the side to move's point of view
+fn evaluate(board: &Board) -> i32 {
+    // Kings walk to the centre once most pieces are off the board
+    let endgame = [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen]
+        .iter()
+        .map(|piece| board.pieces(*piece).popcnt() as i32 * piece_value(*piece))
+        .sum::<i32>()
+        <= 1300;
+
+    let mut score = 0;
+    for piece in ALL_PIECES {
+        for color in [Color::White, Color::Black] {
+            for square in *board.pieces(piece) & *board.color_combined(color) {
+                // Tables are laid out from White's side, rank 8 first
+                let index = match color {
+                    Color::White => square.to_index() ^ 56,
+                    Color::Black => square.to_index(),
+                };
+                let value = piece_value(piece) + piece_square_table(piece, endgame)[index];
+                score += if color == Color::White { value } else { -value };
+            }
+        }
+    }
+
+    if board.side_to_move() == Color::White {
+        score
+    } else {
+        -score
+    }
+}
+
+fn piece_square_table(piece: Piece, endgame: bool) -> &'static [i32; 64] {
+    match piece {
+        Piece::Pawn => &PAWN_TABLE,
+        Piece::Knight => &KNIGHT_TABLE,
+        Piece::Bishop => &BISHOP_TABLE,
+        Piece::Rook => &ROOK_TABLE,
+        Piece::Queen => &QUEEN_TABLE,
+        Piece::King if endgame => &KING_ENDGAME_TABLE,
+        Piece::King => &KING_TABLE,
+    }
+}
+
+#[rustfmt::skip]
+const PAWN_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const KNIGHT_TABLE: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+
+#[rustfmt::skip]
+const BISHOP_TABLE: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const ROOK_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const QUEEN_TABLE: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const KING_TABLE: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+
+#[rustfmt::skip]
+const KING_ENDGAME_TABLE: [i32; 64] = [
+    -50,-40,-30,-20,-20,-30,-40,-50,
+    -30,-20,-10,  0,  0,-10,-20,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-30,  0,  0,  0,  0,-30,-30,
+    -50,-30,-30,-30,-30,-30,-30,-50,
+];
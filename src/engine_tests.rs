@@ -0,0 +1,34 @@
+//! Tests for the built-in engine as an opponent.
+
+use serde_json::json;
+
+use crate::protocol::ProtocolVersion;
+use crate::test_support::{pump, test_app_state, wait_for_move, TestClient};
+
+#[actix_rt::test]
+async fn engine_answers_moves() {
+    let app_state = test_app_state();
+    let mut clients = vec![TestClient::connect(&app_state, ProtocolVersion::V2)];
+    pump(&mut clients);
+    clients[0].messages.clear();
+
+    // Mate in one for white, but the engine moves first as white here
+    let start_fen = "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1";
+    clients[0].send_text(
+        json!({"type": "create", "start_fen": start_fen, "color_preference": "black", "engine_level": 1}).to_string(),
+    );
+    let engine_move = wait_for_move(&mut clients).await;
+    assert_eq!(clients[0].messages[0]["game_status"], "in_progress");
+    assert_eq!(engine_move["san"], "Ra8#", "{}", engine_move);
+    assert_eq!(engine_move["game_status"], "white_wins", "{}", engine_move);
+
+    // A game the human starts gets an answer to every move
+    clients[0].messages.clear();
+    clients[0].send_text(json!({"type": "create", "color_preference": "white", "engine_level": 1}).to_string());
+    pump(&mut clients);
+    clients[0].messages.clear();
+    clients[0].send_text(json!({"type": "move", "uci": "e2e4"}).to_string());
+    wait_for_move(&mut clients).await;
+    let engine_move = wait_for_move(&mut clients).await;
+    assert_eq!(engine_move["game_status"], "white_turn", "{}", engine_move);
+}
@@ -0,0 +1,327 @@
+//! Property tests that throw arbitrary client messages at the WebSocket
+//! handler and check that the server answers instead of panicking.
+
+use actix_http::ws::Message;
+use chess::{MoveGen, Piece};
+use rand::rngs::StdRng;
+use rand::seq::IndexedRandom;
+use rand::{Rng, SeedableRng};
+use serde_json::{json, Map, Value};
+
+use crate::models::ClientMessage;
+use crate::pgn;
+use crate::protocol::ProtocolVersion;
+use crate::test_support::{exchange, pump, test_app_state, TestClient};
+use crate::AppState;
+
+const MESSAGES_PER_RUN: usize = 1500;
+const SEEDS: [u64; 4] = [1, 7, 42, 2024];
+
+/// IDs and tokens the server handed out, so fuzzed messages can refer to real games
+#[derive(Default)]
+struct Seen {
+    game_ids: Vec<String>,
+    resume_tokens: Vec<(String, String)>,
+}
+
+impl Seen {
+    fn learn(&mut self, message: &Value) {
+        let game_id = message.get("game_id").and_then(Value::as_str);
+        if let Some(game_id) = game_id {
+            if !self.game_ids.iter().any(|id| id == game_id) {
+                self.game_ids.push(game_id.to_string());
+            }
+        }
+        if let (Some(game_id), Some(token)) = (game_id, message.get("resume_token").and_then(Value::as_str)) {
+            self.resume_tokens.push((game_id.to_string(), token.to_string()));
+        }
+    }
+}
+
+const SQUARE_LIKE: &[&str] = &[
+    "e2", "e4", "E2", "a1", "h8", "z9", "i1", "a0", "a9", "e", "", " ", "e2e4", "e22", "é", "ée", "22", "ee",
+    "\u{0}", "h8\u{0}", "٣٤", "e\u{301}4", "🙂",
+];
+
+const MOVE_LIKE: &[&str] = &[
+    "e2e4", "e7e8q", "e7e8k", "e2e4q", "e2e", "e2e4e5", "z9e4", "é2e4", "Nf3", "nf3", "e4", "exd5", "e8=Q", "e8Q",
+    "e8=K", "O-O", "0-0-0", "O-O-O-O", "Nbd2", "N1d2", "Ng1f3", "Qh5#", "Bxf7+!?", "dxe6 e.p.", "Kxx", "x", "=Q",
+    "", "🙂",
+];
+
+const PIECE_LIKE: &[&str] = &["queen", "rook", "bishop", "knight", "q", "N", "king", "pawn", "", "QUEEN", "é", "🙂"];
+
+const FEN_LIKE: &[&str] = &[
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "4k3/8/8/8/8/8/8/4K3 w - - 0 1",
+    "8/8/8/8/8/8/8/8 w - - 0 1",
+    "4k3/8/8/8/8/8/8/4K3 w - é 0 1",
+    "4k3/8/8/8/8/8/8/4K3 w - e3 0 1",
+    "9k/8/8/8/8/8/8/4K3 w - - 0 1",
+    "4k3/8/8/8/8/8/8/4K3/8/8/8 w - - 0 1",
+    "kkkkkkkk/8/8/8/8/8/8/KKKKKKKK w - - 0 1",
+    "4k3/8/8/8/8/8/8/4K2P w - - 0 1",
+    "4k3/8/8/8/8/8/8/4K3 x - - 0 1",
+    "4k3/8/8/8/8/8/8/4K3 w",
+    "4k3/8/8/8/8/8/8/4K3 w - - -5 999999999999999999999",
+    "k7/8/1Q6/8/8/8/8/7K b - - 0 1",
+    "",
+];
+
+fn arbitrary_string(rng: &mut StdRng) -> String {
+    match rng.random_range(0..4) {
+        0 => SQUARE_LIKE.choose(rng).unwrap().to_string(),
+        1 => uuid::Uuid::new_v4().to_string(),
+        2 => {
+            let len = rng.random_range(0..40);
+            (0..len).map(|_| rng.random::<char>()).collect()
+        }
+        _ => "x".repeat(rng.random_range(0..2000)),
+    }
+}
+
+fn arbitrary_value(rng: &mut StdRng, depth: u32) -> Value {
+    let kinds = if depth >= 3 { 6 } else { 8 };
+    match rng.random_range(0..kinds) {
+        0 => Value::Null,
+        1 => json!(rng.random::<bool>()),
+        2 => json!([0u64, 1, 15, u64::MAX, u64::MAX / 60].choose(rng).unwrap()),
+        3 => json!(rng.random::<i64>()),
+        4 => json!(rng.random::<f64>() * 1e6),
+        5 => json!(arbitrary_string(rng)),
+        6 => Value::Array((0..rng.random_range(0..4)).map(|_| arbitrary_value(rng, depth + 1)).collect()),
+        _ => {
+            let mut object = Map::new();
+            for _ in 0..rng.random_range(0..4) {
+                object.insert(arbitrary_string(rng), arbitrary_value(rng, depth + 1));
+            }
+            Value::Object(object)
+        }
+    }
+}
+
+/// A plausible value for a field, or an arbitrary one
+fn field_value(rng: &mut StdRng, field: &str, seen: &Seen) -> Value {
+    if rng.random_bool(0.2) {
+        return arbitrary_value(rng, 0);
+    }
+
+    match field {
+        "game_id" => match seen.game_ids.choose(rng) {
+            Some(game_id) if rng.random_bool(0.8) => json!(game_id),
+            _ => json!(arbitrary_string(rng)),
+        },
+        "resume_token" => match seen.resume_tokens.choose(rng) {
+            Some((_, token)) if rng.random_bool(0.8) => json!(token),
+            _ => json!(arbitrary_string(rng)),
+        },
+        "from" | "to" | "move_from" | "move_to" => json!(SQUARE_LIKE.choose(rng).unwrap()),
+        "uci" | "san" => json!(MOVE_LIKE.choose(rng).unwrap()),
+        "promote_to" => json!(PIECE_LIKE.choose(rng).unwrap()),
+        "start_fen" => json!(FEN_LIKE.choose(rng).unwrap()),
+        "color_preference" => json!(["white", "black", "random", "purple"].choose(rng).unwrap()),
+        "start_time_minutes" | "increment_seconds" => {
+            json!([0u64, 1, 5, 15, 180, 1440, 1441, 3601, u64::MAX / 1000, u64::MAX].choose(rng).unwrap())
+        }
+        // Only the weakest level, so engine replies stay quick
+        "engine_level" => json!([0, 1, 9, 255, -1].choose(rng).unwrap()),
+        "engine" => json!(["builtin", "uci", "stockfish"].choose(rng).unwrap()),
+        "time_control" => {
+            let stage = |rng: &mut StdRng| {
+                let moves = [json!(null), json!(0), json!(40), json!(201)].choose(rng).unwrap().clone();
+                let minutes = *[0u64, 1, 90, 1441, u64::MAX].choose(rng).unwrap();
+                let increment_seconds = *[0u64, 30, 3601, u64::MAX].choose(rng).unwrap();
+                json!({"moves": moves, "minutes": minutes, "increment_seconds": increment_seconds})
+            };
+            let stages: Vec<Value> = (0..rng.random_range(0..6)).map(|_| stage(rng)).collect();
+            let mode = *["fischer", "bronstein", "delay", "hourglass"].choose(rng).unwrap();
+            json!({"mode": mode, "stages": stages})
+        }
+        _ => arbitrary_value(rng, 0),
+    }
+}
+
+const FIELDS: &[&str] = &[
+    "game_id",
+    "resume_token",
+    "from",
+    "to",
+    "move_from",
+    "move_to",
+    "promote_to",
+    "uci",
+    "san",
+    "start_fen",
+    "color_preference",
+    "start_time_minutes",
+    "increment_seconds",
+    "engine_level",
+    "engine",
+    "time_control",
+];
+
+/// Some JSON shaped like a client message, in either protocol version
+fn arbitrary_message(rng: &mut StdRng, seen: &Seen) -> String {
+    if rng.random_bool(0.05) {
+        // Not even an object
+        return match rng.random_range(0..3) {
+            0 => arbitrary_string(rng),
+            1 => arbitrary_value(rng, 0).to_string(),
+            _ => "{\"type\": \"move\", \"from\": ".to_string(),
+        };
+    }
+
+    let mut object = Map::new();
+    let message_type = if rng.random_bool(0.9) {
+        json!(ClientMessage::TYPES.choose(rng).unwrap())
+    } else {
+        arbitrary_value(rng, 0)
+    };
+    let type_key = if rng.random_bool(0.5) { "type" } else { "message_type" };
+    object.insert(type_key.to_string(), message_type);
+
+    for field in FIELDS {
+        if rng.random_bool(0.5) {
+            object.insert(field.to_string(), field_value(rng, field, seen));
+        }
+    }
+    if rng.random_bool(0.1) {
+        object.insert(arbitrary_string(rng), arbitrary_value(rng, 0));
+    }
+
+    Value::Object(object).to_string()
+}
+
+/// A legal move for a client whose turn it is, so games actually get played to the end
+fn legal_move(app_state: &AppState, client_id: &str, rng: &mut StdRng) -> Option<String> {
+    let games = app_state.games.lock().unwrap();
+    let game_state = games.values().find(|game_state| {
+        game_state.white_player.as_deref() == Some(client_id) || game_state.black_player.as_deref() == Some(client_id)
+    })?;
+    let board = game_state.game.current_position();
+    let moves: Vec<_> = MoveGen::new_legal(&board).collect();
+    let chess_move = moves.choose(rng)?;
+
+    // Send it in each of the ways a client may
+    let message = match rng.random_range(0..3) {
+        0 => json!({"type": "move", "uci": chess_move.to_string()}),
+        1 => json!({"type": "move", "san": pgn::san(&board, *chess_move)}),
+        _ => json!({
+            "type": "move",
+            "from": chess_move.get_source().to_string(),
+            "to": chess_move.get_dest().to_string(),
+            "promote_to": chess_move.get_promotion().map(|piece| match piece {
+                Piece::Rook => "rook",
+                Piece::Bishop => "bishop",
+                Piece::Knight => "knight",
+                _ => "queen",
+            }),
+        }),
+    };
+    Some(message.to_string())
+}
+
+fn fuzz_run(seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let app_state = test_app_state();
+    let mut clients = vec![
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V1),
+    ];
+    let mut seen = Seen::default();
+    pump(&mut clients);
+
+    for _ in 0..MESSAGES_PER_RUN {
+        let index = rng.random_range(0..clients.len());
+        if clients[index].closed {
+            clients[index] = TestClient::connect(&app_state, ProtocolVersion::V2);
+        }
+
+        let roll = rng.random_range(0..100);
+        if roll < 25 {
+            if let Some(text) = legal_move(&app_state, &clients[index].id, &mut rng) {
+                clients[index].send_text(text);
+            }
+        } else if roll < 27 {
+            let len = rng.random_range(0..64);
+            let bytes: Vec<u8> = (0..len).map(|_| rng.random()).collect();
+            clients[index].send(Message::Binary(bytes.into()));
+        } else if roll < 28 {
+            clients[index].send(Message::Close(None));
+        } else {
+            let text = arbitrary_message(&mut rng, &seen);
+            clients[index].send_text(text);
+        }
+
+        pump(&mut clients);
+        for client in &mut clients {
+            for message in client.messages.drain(..) {
+                seen.learn(&message);
+            }
+        }
+    }
+}
+
+#[actix_rt::test]
+async fn arbitrary_client_messages_never_panic() {
+    for seed in SEEDS {
+        fuzz_run(seed);
+    }
+}
+
+#[actix_rt::test]
+async fn malformed_input_gets_typed_errors() {
+    let app_state = test_app_state();
+    let mut clients = vec![
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V1),
+    ];
+    pump(&mut clients);
+
+    // Version 1 errors carry the same codes
+    let reply = exchange(&mut clients, 2, json!({"message_type": "join", "game_id": "../../etc/passwd"}));
+    assert_eq!((reply["message_type"].as_str(), reply["code"].as_str()), (Some("error"), Some("invalid_game_id")), "{}", reply);
+    let reply = exchange(&mut clients, 2, json!({"message_type": "resign"}));
+    assert_eq!(reply["code"], "not_in_game", "{}", reply);
+
+    let cases = [
+        (json!({"type": "create", "start_fen": "4k3/8/8/8/8/8/8/4K3 w - é 0 1"}), "invalid_position"),
+        (json!({"type": "create", "start_fen": "9k/8/8/8/8/8/8/4K3 w - - 0 1"}), "invalid_position"),
+        (json!({"type": "create", "start_time_minutes": u64::MAX}), "invalid_time_control"),
+        (json!({"type": "create", "increment_seconds": u64::MAX}), "invalid_time_control"),
+        (json!({"type": "create", "engine_level": 0}), "invalid_engine_level"),
+        (json!({"type": "create", "engine_level": 9}), "invalid_engine_level"),
+        (json!({"type": "join", "game_id": "../../etc/passwd"}), "invalid_game_id"),
+        (json!({"type": "watch", "game_id": ""}), "invalid_game_id"),
+        (json!({"type": "rejoin", "game_id": uuid::Uuid::new_v4(), "resume_token": "é"}), "invalid_resume_token"),
+        (json!({"type": "get_pgn", "game_id": "🙂"}), "invalid_game_id"),
+    ];
+    for (message, code) in cases {
+        let reply = exchange(&mut clients, 0, message.clone());
+        assert_eq!(reply["code"], code, "{} answered with {}", message, reply);
+    }
+
+    exchange(&mut clients, 0, json!({"type": "create"}));
+    let game_id = clients[0].messages[0]["game_id"].as_str().unwrap().to_string();
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+
+    let cases = [
+        (json!({"type": "move", "from": "z9", "to": "e4"}), "invalid_square"),
+        (json!({"type": "move", "from": "é", "to": "e4"}), "invalid_square"),
+        (json!({"type": "move", "from": "e2", "to": "e44"}), "invalid_square"),
+        (json!({"type": "move", "from": "e2", "to": "e4", "promote_to": "king"}), "invalid_promotion"),
+        (json!({"type": "get_moves", "from": "é"}), "invalid_square"),
+        (json!({"type": "move", "from": "e2", "to": "e5"}), "illegal_move"),
+    ];
+    for (message, code) in cases {
+        let reply = exchange(&mut clients, 0, message.clone());
+        assert_eq!(reply["code"], code, "{} answered with {}", message, reply);
+    }
+
+    // Squares are case insensitive
+    let reply = exchange(&mut clients, 0, json!({"type": "move", "from": "E2", "to": "E4"}));
+    assert_eq!(reply["type"], "move_made", "{}", reply);
+    assert_eq!(reply["last_move"], json!({"from": "e2", "to": "e4"}));
+}
@@ -0,0 +1,105 @@
+//! Stable identities for players without an account.
+//!
+//! The first time a browser opens the WebSocket without being logged in, the
+//! server makes up a guest ID and sets it as the `guest` cookie, signed with
+//! HMAC-SHA256 so nobody can pass off another guest's ID as their own. Every
+//! tab of that browser then plays as the same guest, and keeps doing so after
+//! a reload. The signing key is kept in the data directory so guest cookies
+//! outlive a restart.
+
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::HttpRequest;
+use hmac::{Hmac, Mac};
+use log::info;
+use sha2::Sha256;
+use std::fs;
+use std::io;
+use std::path::Path;
+use uuid::Uuid;
+
+/// Name of the cookie holding the signed guest ID
+pub const GUEST_COOKIE: &str = "guest";
+/// Guest cookies last a year; every connection refreshes them
+pub const GUEST_COOKIE_DAYS: i64 = 365;
+/// Guest IDs start with this, so they are never mistaken for user IDs
+pub const GUEST_PREFIX: &str = "guest-";
+
+/// Signs guest IDs and checks the signatures of guest cookies
+pub struct GuestSigner {
+    key: Vec<u8>,
+}
+
+impl GuestSigner {
+    pub fn new(key: Vec<u8>) -> Self {
+        GuestSigner { key }
+    }
+
+    /// The key in `guest.key` under the data directory, created the first time
+    pub fn load_or_create(data_dir: &Path) -> io::Result<Self> {
+        let path = data_dir.join("guest.key");
+        if let Some(key) = fs::read_to_string(&path).ok().and_then(|text| decode_hex(text.trim())) {
+            return Ok(GuestSigner::new(key));
+        }
+
+        info!("Creating a new guest cookie key in {}", path.display());
+        let key = rand::random::<[u8; 32]>().to_vec();
+        fs::write(&path, encode_hex(&key))?;
+        Ok(GuestSigner::new(key))
+    }
+
+    /// A guest ID nobody has had before
+    pub fn new_guest() -> String {
+        format!("{}{}", GUEST_PREFIX, Uuid::new_v4())
+    }
+
+    fn mac(&self, guest_id: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any length");
+        mac.update(guest_id.as_bytes());
+        mac
+    }
+
+    /// The cookie value for a guest: the ID and its signature
+    pub fn sign(&self, guest_id: &str) -> String {
+        format!("{}.{}", guest_id, encode_hex(&self.mac(guest_id).finalize().into_bytes()))
+    }
+
+    /// The guest ID in a cookie value, if it was signed with our key
+    pub fn verify(&self, value: &str) -> Option<String> {
+        let (guest_id, signature) = value.rsplit_once('.')?;
+        if !guest_id.starts_with(GUEST_PREFIX) {
+            return None;
+        }
+        // Compared in constant time, so the signature cannot be guessed byte by byte
+        self.mac(guest_id).verify_slice(&decode_hex(signature)?).ok()?;
+        Some(guest_id.to_string())
+    }
+
+    /// The guest a request comes from, if it has a valid guest cookie
+    pub fn guest_for_request(&self, req: &HttpRequest) -> Option<String> {
+        self.verify(req.cookie(GUEST_COOKIE)?.value())
+    }
+
+    /// The cookie that makes a browser this guest
+    pub fn cookie(&self, guest_id: &str) -> Cookie<'static> {
+        Cookie::build(GUEST_COOKIE, self.sign(guest_id))
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .max_age(actix_web::cookie::time::Duration::days(GUEST_COOKIE_DAYS))
+            .finish()
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.is_ascii() || !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&text[index..index + 2], 16).ok())
+        .collect()
+}
@@ -0,0 +1,312 @@
+//! The lobby, where players find opponents without passing game IDs around.
+//!
+//! A seek says what game a player wants: the time control, whether it is
+//! rated, the color they would like and how strong an opponent they accept.
+//! A new seek is paired with the oldest open seek it is compatible with, and
+//! both players are seated in a new game at once; failing that it stays open
+//! until someone else's seek fits. Connections subscribed to the lobby are
+//! sent the open seeks whenever they change. A seek belongs to the connection
+//! that made it and goes away when that connection closes or takes a seat.
+//!
+//! The lobby also lists the games being played and those waiting for an
+//! opponent, so people can join or watch them without being sent an ID.
+//! Subscribers get that list again whenever a game is created, gains or loses
+//! a player or a spectator, has a move played or ends, at most once per update
+//! interval.
+
+use actix_web::web;
+use chess::Color;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::models::{ClockTimes, ColorPreference, GameState, PlayerRatings, ServerMessage};
+use crate::time_control::TimeControl;
+use crate::{color_to_string, engine, AppState};
+
+/// How often subscribers are sent the games list if it changed
+pub const GAMES_UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// An open request for a game, as listed in the lobby
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Seek {
+    pub seek_id: String,
+    /// Connection that made the seek and is told when it is paired
+    #[serde(skip)]
+    pub connection_id: String,
+    /// Who takes the seat once the seek is paired
+    #[serde(skip)]
+    pub player_id: String,
+    /// The seeker's username, if they are logged in
+    pub username: Option<String>,
+    /// The seeker's rating in the time control's pool, if they are logged in
+    pub rating: Option<u32>,
+    pub time_control: TimeControl,
+    pub rated: bool,
+    pub color_preference: ColorPreference,
+    /// Lowest rating accepted in an opponent
+    pub min_rating: Option<u32>,
+    /// Highest rating accepted in an opponent
+    pub max_rating: Option<u32>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Seek {
+    /// Whether an opponent with this rating is acceptable. Guests have no
+    /// rating, so they are only paired with seeks without a rating range.
+    fn accepts(&self, rating: Option<u32>) -> bool {
+        if self.min_rating.is_none() && self.max_rating.is_none() {
+            return true;
+        }
+        rating.is_some_and(|rating| {
+            self.min_rating.is_none_or(|min| rating >= min) && self.max_rating.is_none_or(|max| rating <= max)
+        })
+    }
+
+    /// Whether the two seeks' players can play each other: different players
+    /// after the same game, colors that fit, and each in the other's range
+    pub fn matches(&self, other: &Seek) -> bool {
+        let colors_clash = self.color_preference == other.color_preference && self.color_preference != ColorPreference::Random;
+        self.player_id != other.player_id
+            && self.time_control == other.time_control
+            && self.rated == other.rated
+            && !colors_clash
+            && self.accepts(other.rating)
+            && other.accepts(self.rating)
+    }
+
+    /// The color this seek's player gets when paired with `other`: what
+    /// either asked for, or a coin toss if neither minds
+    pub fn color_against(&self, other: &Seek) -> Color {
+        match (self.color_preference, other.color_preference) {
+            (ColorPreference::White, _) | (_, ColorPreference::Black) => Color::White,
+            (ColorPreference::Black, _) | (_, ColorPreference::White) => Color::Black,
+            (ColorPreference::Random, ColorPreference::Random) => {
+                if rand::random() {
+                    Color::White
+                } else {
+                    Color::Black
+                }
+            }
+        }
+    }
+}
+
+/// A game with one player waiting for another, as listed in the lobby
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WaitingGame {
+    pub game_id: String,
+    pub time_control: TimeControl,
+    /// The color the creator plays
+    pub color: String,
+    /// The creator's username, `None` for guests
+    pub creator: Option<String>,
+    pub creator_rating: Option<u32>,
+    pub rated: bool,
+    /// FEN the game starts from, if not the standard starting position
+    pub start_fen: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A game both players are seated in, as listed in the lobby
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LiveGame {
+    pub game_id: String,
+    pub time_control: TimeControl,
+    /// Username of the white player, `engine` for the engine, `None` for guests
+    pub white: Option<String>,
+    pub black: Option<String>,
+    #[serde(flatten)]
+    pub ratings: PlayerRatings,
+    pub move_count: usize,
+    #[serde(flatten)]
+    pub clock: ClockTimes,
+    pub active_color: String,
+    pub spectator_count: usize,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Every unfinished game anyone is seated in, newest first
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LobbyGames {
+    pub waiting: Vec<WaitingGame>,
+    pub in_progress: Vec<LiveGame>,
+}
+
+/// Open seeks and the connections following them
+#[derive(Default)]
+pub struct Lobby {
+    /// Oldest first, which is the order they are paired in
+    seeks: Mutex<Vec<Seek>>,
+    /// Connections sent the open seeks and the games list whenever they change
+    subscribers: Mutex<HashSet<String>>,
+    /// Whether the games list changed since subscribers were last sent it
+    games_changed: AtomicBool,
+}
+
+impl Lobby {
+    /// Pair a seek with the oldest open seek it matches, which leaves the
+    /// lobby and is returned; or, if none does, add it to the lobby in place
+    /// of any seek its connection already had
+    pub fn seek(&self, seek: Seek) -> Option<Seek> {
+        let mut seeks = self.seeks.lock().unwrap();
+        seeks.retain(|open| open.connection_id != seek.connection_id);
+        if let Some(index) = seeks.iter().position(|open| open.matches(&seek)) {
+            return Some(seeks.remove(index));
+        }
+        seeks.push(seek);
+        None
+    }
+
+    /// Withdraw a connection's seek, returning it if there was one
+    pub fn cancel(&self, connection_id: &str) -> Option<Seek> {
+        let mut seeks = self.seeks.lock().unwrap();
+        let index = seeks.iter().position(|seek| seek.connection_id == connection_id)?;
+        Some(seeks.remove(index))
+    }
+
+    pub fn open_seeks(&self) -> Vec<Seek> {
+        self.seeks.lock().unwrap().clone()
+    }
+
+    pub fn subscribe(&self, connection_id: &str) {
+        self.subscribers.lock().unwrap().insert(connection_id.to_string());
+    }
+
+    pub fn unsubscribe(&self, connection_id: &str) {
+        self.subscribers.lock().unwrap().remove(connection_id);
+    }
+
+    pub fn subscribers(&self) -> Vec<String> {
+        self.subscribers.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Note that the games list changed. Safe to call with the games locked.
+    pub fn games_changed(&self) {
+        self.games_changed.store(true, Ordering::Relaxed);
+    }
+}
+
+/// What the games list shows of a game, copied out under the games lock so
+/// that names and ratings are looked up after it is released
+struct ListedGame {
+    game_id: String,
+    white_player: Option<String>,
+    black_player: Option<String>,
+    time_control: TimeControl,
+    rated: bool,
+    move_count: usize,
+    clock: ClockTimes,
+    active_color: String,
+    spectator_count: usize,
+    start_fen: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl ListedGame {
+    fn new(game_id: &str, game_state: &GameState, now: Instant) -> Self {
+        ListedGame {
+            game_id: game_id.to_string(),
+            white_player: game_state.white_player.clone(),
+            black_player: game_state.black_player.clone(),
+            time_control: game_state.clock.control().clone(),
+            rated: game_state.rated,
+            move_count: game_state.history.len(),
+            clock: game_state.clock_times(now),
+            active_color: color_to_string(game_state.game.side_to_move()),
+            spectator_count: game_state.spectators.len(),
+            start_fen: game_state.start_fen.clone(),
+            created_at: game_state.created_at,
+        }
+    }
+
+    fn ratings(&self, app_state: &AppState) -> PlayerRatings {
+        app_state.seat_ratings(self.rated, &self.time_control, [&self.white_player, &self.black_player])
+    }
+}
+
+/// The games waiting for an opponent and the games in progress
+pub fn list_games(app_state: &AppState) -> LobbyGames {
+    let now = app_state.now();
+    let games = app_state.games.lock().unwrap();
+    let listed: Vec<ListedGame> = games
+        .iter()
+        .filter(|(_, game_state)| game_state.game_result.is_none())
+        .map(|(game_id, game_state)| ListedGame::new(game_id, game_state, now))
+        .collect();
+    drop(games);
+
+    let mut list = LobbyGames::default();
+    for game in listed {
+        match (&game.white_player, &game.black_player) {
+            (Some(_), Some(_)) => list.in_progress.push(LiveGame {
+                white: player_name(app_state, &game.white_player),
+                black: player_name(app_state, &game.black_player),
+                ratings: game.ratings(app_state),
+                game_id: game.game_id,
+                time_control: game.time_control,
+                move_count: game.move_count,
+                clock: game.clock,
+                active_color: game.active_color,
+                spectator_count: game.spectator_count,
+                created_at: game.created_at,
+            }),
+            (Some(_), None) | (None, Some(_)) => list.waiting.push(waiting_game(app_state, game)),
+            (None, None) => {}
+        }
+    }
+
+    list.waiting.sort_by_key(|game| std::cmp::Reverse(game.created_at));
+    list.in_progress.sort_by_key(|game| std::cmp::Reverse(game.created_at));
+    list
+}
+
+fn waiting_game(app_state: &AppState, game: ListedGame) -> WaitingGame {
+    let (color, seat) = match &game.white_player {
+        Some(_) => (Color::White, &game.white_player),
+        None => (Color::Black, &game.black_player),
+    };
+    let ratings = game.ratings(app_state);
+    WaitingGame {
+        color: color_to_string(color),
+        creator: player_name(app_state, seat),
+        creator_rating: ratings.white_rating.or(ratings.black_rating),
+        game_id: game.game_id,
+        time_control: game.time_control,
+        rated: game.rated,
+        start_fen: game.start_fen,
+        created_at: game.created_at,
+    }
+}
+
+/// How a seated player is shown to others: their username, or `engine`;
+/// guests stay anonymous
+pub fn player_name(app_state: &AppState, seat: &Option<String>) -> Option<String> {
+    let player_id = seat.as_deref()?;
+    if player_id == engine::ENGINE_PLAYER_ID {
+        return Some(engine::ENGINE_PLAYER_ID.to_string());
+    }
+    app_state.accounts.user_by_id(player_id).map(|user| user.username)
+}
+
+/// Send subscribers the games list every `interval` in which it changed, for
+/// as long as the server runs
+pub fn start_games_updates(app_state: web::Data<AppState>, interval: Duration) {
+    actix::spawn(async move {
+        loop {
+            actix::clock::sleep(interval).await;
+            send_games_if_changed(&app_state);
+        }
+    });
+}
+
+/// Send subscribers the games list if it changed since they were last sent it
+pub fn send_games_if_changed(app_state: &AppState) {
+    if app_state.lobby.games_changed.swap(false, Ordering::Relaxed) {
+        let games = list_games(app_state);
+        app_state.send_to_lobby(&ServerMessage::LobbyGames { games });
+    }
+}
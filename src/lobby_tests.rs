@@ -0,0 +1,177 @@
+//! Tests for the lobby: which seeks are paired with which, players who seek
+//! being seated together while everyone watching the lobby sees the open
+//! seeks come and go, and the list of games to join or watch.
+
+use actix_http::ws::Message;
+use actix_web::test::{self as http, TestRequest};
+use actix_web::{web, App};
+use chess::Color;
+use chrono::Utc;
+use serde_json::{json, Value};
+
+use crate::accounts::Credentials;
+use crate::api_games;
+use crate::test_support::{self, exchange, pump, test_app_state, NullStore, TestClient};
+use crate::lobby::{self, Lobby, Seek};
+use crate::models::ColorPreference;
+use crate::protocol::ProtocolVersion;
+use crate::time_control::TimeControl;
+
+fn seek(player_id: &str, color_preference: ColorPreference, rating: Option<u32>) -> Seek {
+    Seek {
+        seek_id: format!("{}-seek", player_id),
+        connection_id: format!("{}-connection", player_id),
+        player_id: player_id.to_string(),
+        username: None,
+        rating,
+        time_control: TimeControl::fischer(300_000, 3_000),
+        rated: false,
+        color_preference,
+        min_rating: None,
+        max_rating: None,
+        created_at: Utc::now(),
+    }
+}
+
+#[test]
+fn seeks_pair_when_both_players_get_what_they_asked_for() {
+    let white = seek("a", ColorPreference::White, Some(1500));
+    assert!(white.matches(&seek("b", ColorPreference::Black, None)));
+    assert!(white.matches(&seek("b", ColorPreference::Random, None)));
+    assert!(!white.matches(&seek("b", ColorPreference::White, None)));
+    assert!(!white.matches(&seek("a", ColorPreference::Black, None)), "a player cannot play themselves");
+    assert_eq!(seek("b", ColorPreference::Random, None).color_against(&white), Color::Black);
+
+    let blitz = Seek { time_control: TimeControl::fischer(180_000, 2_000), ..seek("b", ColorPreference::Random, None) };
+    assert!(!white.matches(&blitz));
+    let rated = Seek { rated: true, ..seek("b", ColorPreference::Random, None) };
+    assert!(!white.matches(&rated));
+
+    // Rating ranges hold both ways, and leave out guests, who have no rating
+    let picky = Seek { min_rating: Some(1400), max_rating: Some(1600), ..seek("b", ColorPreference::Random, Some(1450)) };
+    assert!(white.matches(&picky));
+    assert!(!picky.matches(&Seek { rating: Some(1700), ..white.clone() }));
+    assert!(!picky.matches(&seek("c", ColorPreference::Random, None)));
+
+    // The oldest compatible seek is paired first, and the rest stay open
+    let lobby = Lobby::default();
+    assert!(lobby.seek(seek("a", ColorPreference::White, None)).is_none());
+    assert!(lobby.seek(seek("b", ColorPreference::Random, None)).is_some_and(|paired| paired.player_id == "a"));
+    assert!(lobby.open_seeks().is_empty());
+    assert!(lobby.seek(seek("c", ColorPreference::Black, None)).is_none());
+    assert!(lobby.seek(seek("d", ColorPreference::Black, None)).is_none());
+    assert_eq!(lobby.open_seeks().len(), 2);
+    assert!(lobby.cancel("c-connection").is_some());
+    assert_eq!(lobby.open_seeks()[0].player_id, "d");
+}
+
+#[actix_rt::test]
+async fn compatible_seeks_start_a_game_for_both_players() {
+    let app_state = test_app_state();
+    let credentials = Credentials {
+        username: "carlsen".to_string(),
+        password: "long enough".to_string(),
+    };
+    let user = app_state.accounts.register(&NullStore, &credentials).unwrap();
+    let mut clients = vec![
+        TestClient::connect_as(&app_state, ProtocolVersion::V2, Some(user.clone()), None),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V1),
+    ];
+    pump(&mut clients);
+
+    let reply = exchange(&mut clients, 2, json!({"message_type": "subscribe_lobby"}));
+    assert_eq!((reply["message_type"].as_str(), &reply["seeks"]), (Some("seeks"), &json!([])), "{}", reply);
+
+    // Guests cannot seek rated games, and anyone seeking shows up in the lobby
+    let reply = exchange(&mut clients, 1, json!({"type": "seek", "rated": true}));
+    assert_eq!(reply["code"], "login_required", "{}", reply);
+    let reply = exchange(&mut clients, 0, json!({"type": "seek", "start_time_minutes": 5, "increment_seconds": 3, "color_preference": "black"}));
+    assert_eq!(reply["type"], "seek_created", "{}", reply);
+    let seeks = &clients[2].messages.last().expect("no lobby update")["seeks"];
+    assert_eq!(seeks.as_array().map(Vec::len), Some(1), "{}", seeks);
+    assert_eq!((seeks[0]["username"].as_str(), seeks[0]["rating"].as_u64()), (Some("carlsen"), Some(1500)));
+
+    // A seek for another time control waits, and one that fits pairs the two
+    let reply = exchange(&mut clients, 1, json!({"type": "seek", "start_time_minutes": 3, "increment_seconds": 2}));
+    assert_eq!(reply["type"], "seek_created", "{}", reply);
+    let reply = exchange(&mut clients, 1, json!({"type": "seek", "start_time_minutes": 5, "increment_seconds": 3}));
+    assert_eq!((reply["type"].as_str(), reply["color"].as_str()), (Some("joined"), Some("white")), "{}", reply);
+    let joined = clients[0].messages.iter().find(|message| message["type"] == "joined").cloned().expect("no joined");
+    assert_eq!(joined["game_id"], reply["game_id"]);
+    assert_eq!(joined["color"], "black");
+    let seeks = &clients[2].messages.last().expect("no lobby update")["seeks"];
+    assert_eq!(seeks, &json!([]));
+
+    let reply = exchange(&mut clients, 1, json!({"type": "move", "uci": "e2e4"}));
+    assert_eq!(reply["type"], "move_made", "{}", reply);
+    assert!(clients[0].messages.iter().any(|message| message["type"] == "move_made"));
+    let record = app_state.find_game_record(reply["game_id"].as_str().unwrap()).unwrap();
+    assert_eq!(record.black_player.as_deref(), Some(user.id.as_str()));
+}
+
+#[actix_rt::test]
+async fn the_lobby_lists_games_to_join_and_games_to_watch() {
+    let app_state = test_app_state();
+    let mut clients = vec![
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+    ];
+    pump(&mut clients);
+
+    // Subscribers are sent the games list after anything changed, as the server does every interval
+    let exchange = |clients: &mut Vec<TestClient>, index: usize, message: Value| -> Value {
+        let reply = test_support::exchange(clients, index, message);
+        lobby::send_games_if_changed(&app_state);
+        pump(clients);
+        reply
+    };
+    let lobby_games = |client: &TestClient| -> Value {
+        client.messages.iter().rev().find(|message| message["type"] == "lobby_games").cloned().expect("no games list")
+    };
+
+    exchange(&mut clients, 0, json!({"type": "subscribe_lobby"}));
+    assert_eq!(lobby_games(&clients[0]), json!({"type": "lobby_games", "waiting": [], "in_progress": []}));
+
+    let create = json!({"type": "create", "color_preference": "black", "start_time_minutes": 3, "increment_seconds": 2});
+    let game_id = exchange(&mut clients, 1, create)["game_id"].clone();
+    let games = lobby_games(&clients[0]);
+    assert_eq!(games["waiting"].as_array().map(Vec::len), Some(1), "{}", games);
+    let waiting = &games["waiting"][0];
+    assert_eq!((&waiting["game_id"], waiting["color"].as_str()), (&game_id, Some("black")));
+    assert_eq!(waiting["time_control"]["stages"][0]["time_ms"], 180_000);
+    assert!(waiting["creator"].is_null(), "guests are not named: {}", waiting);
+
+    exchange(&mut clients, 2, json!({"type": "join", "game_id": game_id}));
+    exchange(&mut clients, 2, json!({"type": "move", "uci": "e2e4"}));
+    let games = lobby_games(&clients[0]);
+    assert_eq!(games["waiting"], json!([]));
+    let live = &games["in_progress"][0];
+    assert_eq!((&live["game_id"], live["move_count"].as_u64()), (&game_id, Some(1)), "{}", live);
+    assert_eq!((live["active_color"].as_str(), live["white_time_ms"].as_u64()), (Some("black"), Some(182_000)), "{}", live);
+
+    // Spectators coming and going are counted
+    exchange(&mut clients, 3, json!({"type": "watch", "game_id": game_id}));
+    assert_eq!(lobby_games(&clients[0])["in_progress"][0]["spectator_count"], 1);
+    clients[3].send(Message::Close(None));
+    pump(&mut clients);
+    lobby::send_games_if_changed(&app_state);
+    pump(&mut clients);
+    assert_eq!(lobby_games(&clients[0])["in_progress"][0]["spectator_count"], 0);
+
+    // Nothing changed, so nothing is sent
+    clients[0].messages.clear();
+    lobby::send_games_if_changed(&app_state);
+    pump(&mut clients);
+    assert!(clients[0].messages.is_empty(), "{:?}", clients[0].messages);
+
+    let app = http::init_service(App::new().app_data(app_state.clone()).service(web::resource("/api/games").route(web::get().to(api_games)))).await;
+    let body: Value = http::call_and_read_body_json(&app, TestRequest::get().uri("/api/games").to_request()).await;
+    assert_eq!(body["in_progress"][0]["game_id"], game_id);
+    assert_eq!(body["in_progress"][0]["move_count"], 1);
+
+    exchange(&mut clients, 1, json!({"type": "resign"}));
+    assert_eq!(lobby_games(&clients[0])["in_progress"], json!([]));
+}
@@ -1,25 +1,163 @@
 use actix::*;
 use actix_files as fs;
+use actix_web::cookie::{Cookie, SameSite};
 use actix_web::{web, App, Error, HttpRequest, HttpResponse, HttpServer, Responder};
 use actix_web_actors::ws;
-use actix::prelude::*;
-use chess::{ChessMove, Color, Game, GameResult, MoveGen, Piece, Square};
+use chess::{Board, ChessMove, Color, Game, GameResult, MoveGen, Piece};
 use log::{info, warn};
-use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::str::FromStr;
+use std::time::Duration;
 use uuid::Uuid;
 
 // Models for our application
 mod models;
+// Server-side game clocks
+mod clock;
+// Time controls and the clock accounting behind them
+mod time_control;
+// Days-per-move games and their timeout sweeper
+mod correspondence;
+// Repetition and fifty-move rule draws
+mod draw_rules;
+// PGN export
+mod pgn;
+// Finished game archive and game browsing
+mod archive;
+// User accounts and sessions
+mod accounts;
+// Signed guest cookies for players without an account
+mod guests;
+// Glicko-2 ratings of users
+mod ratings;
+// Seeks and pairing players in the lobby
+mod lobby;
+// Persistent game storage
+mod storage;
+// WebSocket protocol versions and message parsing
+mod protocol;
+// Checks on client supplied squares, IDs and settings
+mod validation;
+// Built-in computer opponent
+mod engine;
+// Engine analysis of positions and finished games
+mod analysis;
+// External engines spoken to over UCI
+mod uci;
+#[cfg(test)]
+mod test_support;
+#[cfg(test)]
+mod fuzz_tests;
+#[cfg(test)]
+mod clock_tests;
+#[cfg(test)]
+mod resume_tests;
+#[cfg(test)]
+mod resignation_tests;
+#[cfg(test)]
+mod draw_rules_tests;
+#[cfg(test)]
+mod create_tests;
+#[cfg(test)]
+mod storage_tests;
+#[cfg(test)]
+mod archive_tests;
+#[cfg(test)]
+mod pgn_tests;
+#[cfg(test)]
+mod engine_tests;
+#[cfg(test)]
+mod analysis_tests;
+#[cfg(test)]
+mod uci_tests;
+#[cfg(test)]
+mod time_control_tests;
+#[cfg(test)]
+mod accounts_tests;
+#[cfg(test)]
+mod ratings_tests;
+#[cfg(test)]
+mod lobby_tests;
+#[cfg(test)]
+mod spectator_tests;
+#[cfg(test)]
+mod protocol_tests;
+
+use clock::{GameClock, ResetClock, StopClock};
+use accounts::{Accounts, Credentials, User};
+use archive::{GameQuery, GameRecord, GameRecordStatus};
+use guests::GuestSigner;
+use ratings::{Pool, Ratings};
+use lobby::{Lobby, Seek};
+use draw_rules::DrawRules;
+use storage::{FileGameStore, GameEvent, GameStore};
+use models::{ChessWebSocketMessage, ChessError, ClientMessage, ColorPreference, EngineKind, Evaluation, GameState, LastMove, MoveReport, PlayerRatings, ServerMessage, Termination, TimeControlRequest, PROTOCOL_VERSION};
+use protocol::{ConnectQuery, ProtocolVersion};
+use time_control::{Clock, RealTime, TimeControl, TimeSource};
 
 // WebSocket handler for chess games
 struct ChessWebSocket {
     id: String,
+    // The signed-in user, who takes seats in games instead of the connection
+    user: Option<User>,
+    // Otherwise the guest from the browser's guest cookie, shared by all its tabs
+    guest_id: Option<String>,
     app_state: web::Data<AppState>,
     game_id: String,
     color: Option<Color>,
+    // Message format this connection speaks
+    protocol: ProtocolVersion,
+    // Engine analysis streaming to this connection, if any
+    analysis: Option<RunningAnalysis>,
+}
+
+// An analysis a connection started, so it can be stopped or moved on to the next position
+struct RunningAnalysis {
+    game_id: String,
+    stop: Arc<AtomicBool>,
+}
+
+// The `analysis` messages of one running analysis, whichever engine finds the lines
+struct AnalysisUpdates {
+    addr: Addr<ChessWebSocket>,
+    game_id: String,
+    board: Board,
+    stop: Arc<AtomicBool>,
+    // The deepest line so far, repeated as the final word
+    last: Option<(u8, Evaluation, Vec<String>)>,
+}
+
+impl AnalysisUpdates {
+    fn line(&mut self, line: &engine::Line) {
+        let (evaluation, pv) = analysis::describe(&self.board, line);
+        self.send(line.depth, Some(evaluation), pv.clone(), false);
+        self.last = Some((line.depth, evaluation, pv));
+    }
+
+    // Repeat the deepest line, or say there is nothing to analyse
+    fn finish(mut self) {
+        match self.last.take() {
+            Some((depth, evaluation, pv)) => self.send(depth, Some(evaluation), pv, true),
+            None => self.send(0, None, Vec::new(), true),
+        }
+    }
+
+    fn send(&self, depth: u8, evaluation: Option<Evaluation>, pv: Vec<String>, done: bool) {
+        // Nobody is waiting for an analysis that was stopped
+        if self.stop.load(Ordering::Relaxed) {
+            return;
+        }
+        self.addr.do_send(ChessWebSocketMessage(ServerMessage::Analysis {
+            game_id: self.game_id.clone(),
+            fen: self.board.to_string(),
+            depth,
+            evaluation,
+            pv,
+            done,
+        }));
+    }
 }
 
 impl Actor for ChessWebSocket {
@@ -29,50 +167,56 @@ impl Actor for ChessWebSocket {
         // Register the actor with the application state
         let addr = ctx.address();
         self.app_state.sessions.lock().unwrap().insert(self.id.clone(), addr);
+        self.app_state.players.lock().unwrap().insert(self.id.clone(), self.player_id().to_string());
         
         // Log the connection and total active sessions
         let total_sessions = self.app_state.sessions.lock().unwrap().len();
         info!("WebSocket connection started: {}", self.id);
         info!("Total active sessions: {}", total_sessions);
+        
+        // Versioned clients learn which protocol the server speaks; version 1 clients never expected this
+        if self.protocol != ProtocolVersion::V1 {
+            self.send(ctx, &ServerMessage::Hello { protocol_version: PROTOCOL_VERSION });
+        }
     }
 
     fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        self.stop_analysis();
+        self.cancel_seek();
+        self.app_state.lobby.unsubscribe(&self.id);
+
         // Remove the actor from any game it was part of
         if !self.game_id.is_empty() {
-            let mut connections = self.app_state.connections.lock().unwrap();
-            if let Some(connection_ids) = connections.get_mut(&self.game_id) {
-                // Remove this connection from the previous game
-                connection_ids.retain(|id| id != &self.id);
-                info!("Removed player {} from game {}'s connections", self.id, self.game_id);
+            // Scoped so the connections lock is released before broadcasting below
+            {
+                let mut connections = self.app_state.connections.lock().unwrap();
+                if let Some(connection_ids) = connections.get_mut(&self.game_id) {
+                    // Remove this connection from the previous game
+                    connection_ids.retain(|id| id != &self.id);
+                    info!("Removed player {} from game {}'s connections", self.id, self.game_id);
                 
-                // If this was the last player, we could clean up the game state
-                if connection_ids.is_empty() {
-                    info!("No more players in game {}. Cleaning up.", self.game_id);
-                    connections.remove(&self.game_id);
-                    
-                    // Also remove the game state
-                    let mut games = self.app_state.games.lock().unwrap();
-                    games.remove(&self.game_id);
-                    info!("Removed game state for {}", self.game_id);
+                    // If this was the last player, give them a chance to reconnect before cleaning up
+                    if connection_ids.is_empty() {
+                        info!("No more players in game {}. Removing it in {:?} unless someone reconnects",
+                              self.game_id, self.app_state.abandoned_game_grace);
+                        connections.remove(&self.game_id);
+                        schedule_abandoned_game_removal(self.app_state.clone(), self.game_id.clone());
+                    }
                 }
             }
             
-            // Also remove player from the game state if they were assigned a color
-            let mut games = self.app_state.games.lock().unwrap();
-            if let Some(game_state) = games.get_mut(&self.game_id) {
-                if game_state.white_player.as_ref() == Some(&self.id) {
-                    info!("Removing player {} as white from game {}", self.id, self.game_id);
-                    game_state.white_player = None;
-                }
-                if game_state.black_player.as_ref() == Some(&self.id) {
-                    info!("Removing player {} as black from game {}", self.id, self.game_id);
-                    game_state.black_player = None;
-                }
+            // Spectators simply leave; players keep their color so they can reclaim it with their resume token
+            if let Some(spectator_count) = self.remove_spectator() {
+                self.broadcast_spectators(ServerMessage::SpectatorLeft {
+                    game_id: self.game_id.clone(),
+                    spectator_count,
+                });
             }
         }
         
         // Remove the actor from the sessions
         self.app_state.sessions.lock().unwrap().remove(&self.id);
+        self.app_state.players.lock().unwrap().remove(&self.id);
         let total_sessions = self.app_state.sessions.lock().unwrap().len();
         info!("WebSocket connection closed: {}", self.id);
         info!("Total active sessions: {}", total_sessions);
@@ -86,69 +230,304 @@ struct AppState {
     games: Mutex<HashMap<String, GameState>>,
     connections: Mutex<HashMap<String, Vec<String>>>,
     sessions: Mutex<HashMap<String, Addr<ChessWebSocket>>>,
+    // Who each connection plays as, as several connections can be the same player
+    players: Mutex<HashMap<String, String>>,
+    clocks: Mutex<HashMap<String, Addr<GameClock>>>,
+    // Finished and abandoned games, kept after they are removed from `games`
+    archive: Mutex<HashMap<String, GameRecord>>,
+    // How long a game without connections is kept around for players to rejoin
+    abandoned_game_grace: Duration,
+    // Number of the latest removal timer set for each game; only that one may remove it
+    removal_timers: Mutex<HashMap<String, u64>>,
+    // End games on fivefold repetition and the 75-move rule without waiting for a claim
+    automatic_draws: bool,
+    // Where games are persisted so they survive a restart
+    store: Box<dyn GameStore>,
+    // External engine that can play instead of the built-in one, if configured
+    uci_engine: Option<uci::UciPool>,
+    // Post-game reports already worked out, by game
+    reports: Mutex<HashMap<String, Vec<MoveReport>>>,
+    // Where game clocks read the time
+    time_source: Arc<dyn TimeSource>,
+    // Registered users and who is logged in
+    accounts: Accounts,
+    // Signs and checks guest cookies
+    guests: GuestSigner,
+    // Users' ratings in every pool
+    ratings: Ratings,
+    // Open seeks and who is watching them
+    lobby: Lobby,
 }
 
-// Game state for a specific game
-struct GameState {
-    game: Game,
-    white_player: Option<String>,
-    black_player: Option<String>,
-    white_time_ms: u64,
-    black_time_ms: u64,
-    increment_ms: u64,
-    last_move_time: Option<std::time::Instant>,
-    active_player: Option<Color>,
-    game_result: Option<GameResult>,
-}
+impl AppState {
+    fn now(&self) -> std::time::Instant {
+        self.time_source.now()
+    }
 
-// Message sent from client to server
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct ClientMessage {
-    message_type: String,
-    game_id: Option<String>,
-    move_from: Option<String>,
-    move_to: Option<String>,
-    color_preference: Option<String>,
-    start_time_minutes: Option<u64>,
-    increment_seconds: Option<u64>,
-    promote_to: Option<String>,
-}
+    // Send a message to every connection in a game, optionally skipping one connection
+    fn broadcast_to_game(&self, game_id: &str, message: &ServerMessage, skip_id: Option<&str>) {
+        info!("Broadcasting message to game {}: {:?}", game_id, message);
+        
+        // Get the list of connection IDs for this game and all sessions
+        let connection_ids;
+        let sessions_copy;
+        
+        // Scope the locks to minimize lock time
+        {
+            let connections = self.connections.lock().unwrap();
+            if let Some(ids) = connections.get(game_id) {
+                connection_ids = ids.clone();
+            } else {
+                info!("No connections found for game {}", game_id);
+                return;
+            }
+            
+            let sessions = self.sessions.lock().unwrap();
+            sessions_copy = sessions.clone();
+        }
+        
+        info!("Found {} connections for game {}", connection_ids.len(), game_id);
+        
+        // Send the message to each connection in the game, which serializes it for its own protocol version
+        for connection_id in &connection_ids {
+            if skip_id == Some(connection_id.as_str()) {
+                info!("Skipping sending to {}", connection_id);
+                continue;
+            }
+            
+            if let Some(addr) = sessions_copy.get(connection_id) {
+                info!("Sending message to player {}", connection_id);
+                addr.do_send(ChessWebSocketMessage(message.clone()));
+            } else {
+                info!("Player {} not found in sessions", connection_id);
+            }
+        }
+    }
 
-// Message sent from server to client
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct ServerMessage {
-    message_type: String,
-    game_id: Option<String>,
-    fen: Option<String>,
-    color: Option<String>,
-    error: Option<String>,
-    available_moves: Option<Vec<String>>,
-    last_move: Option<LastMove>,
-    game_status: Option<String>,
-    white_time_ms: Option<u64>,
-    black_time_ms: Option<u64>,
-    increment_ms: Option<u64>,
-    active_color: Option<String>,
-}
+    // Send a message to every connection subscribed to the lobby
+    fn send_to_lobby(&self, message: &ServerMessage) {
+        let subscribers = self.lobby.subscribers();
+        let sessions = self.sessions.lock().unwrap();
+        for connection_id in &subscribers {
+            if let Some(addr) = sessions.get(connection_id) {
+                addr.do_send(ChessWebSocketMessage(message.clone()));
+            }
+        }
+    }
+
+    // Send the open seeks to the lobby after they changed
+    fn broadcast_seeks(&self) {
+        self.send_to_lobby(&ServerMessage::Seeks { seeks: self.lobby.open_seeks() });
+    }
+
+    // Whether any of these connections plays as `player_id`
+    fn is_player_connected(&self, connection_ids: &[String], player_id: &str) -> bool {
+        let players = self.players.lock().unwrap();
+        connection_ids.iter().any(|id| players.get(id).is_some_and(|player| player == player_id))
+    }
+
+    // Remove a game nobody reconnected to during the grace period
+    fn remove_abandoned_game(&self, game_id: &str, timer: u64) {
+        // Someone came back and left again since this timer was set; the later timer gives them the full grace period
+        if self.removal_timers.lock().unwrap().get(game_id) != Some(&timer) {
+            info!("A later removal is scheduled for game {}. Keeping it for now", game_id);
+            return;
+        }
+
+        // Games before connections, the order every handler takes them in
+        let mut games = self.games.lock().unwrap();
+        let reconnected = self.connections.lock().unwrap().get(game_id).is_some_and(|ids| !ids.is_empty());
+        if reconnected {
+            info!("A player reconnected to game {}. Keeping it", game_id);
+            return;
+        }
+
+        // Correspondence games wait for their players however long it takes, once
+        // both are seated; an open challenge goes like any other
+        let waits = |game_state: &GameState| {
+            game_state.is_correspondence()
+                && game_state.game_result.is_none()
+                && game_state.white_player.is_some()
+                && game_state.black_player.is_some()
+        };
+        if games.get(game_id).is_some_and(waits) {
+            info!("Keeping correspondence game {} without connections", game_id);
+            return;
+        }
+        self.removal_timers.lock().unwrap().remove(game_id);
+        if let Some(game_state) = games.remove(game_id) {
+            info!("Removed abandoned game state for {}", game_id);
+            self.record(GameEvent::Removed { game_id: game_id.to_string() });
+            
+            // Finished games were archived when they ended
+            if game_state.game_result.is_none() {
+                self.archive_game(self.game_record(game_id, &game_state, GameRecordStatus::Abandoned));
+            }
+        }
+        
+        // And stop the game's clock
+        if let Some(clock) = self.clocks.lock().unwrap().remove(game_id) {
+            clock.do_send(StopClock);
+        }
+    }
+
+    // Persist a game event; a failed write is logged but does not interrupt the game.
+    // Every event changes what the lobby lists, so it is sent the games list again.
+    fn record(&self, event: GameEvent) {
+        if let Err(e) = self.store.record(&event) {
+            warn!("Failed to store event for game {}: {}", event.game_id(), e);
+        }
+        self.lobby.games_changed();
+    }
+    
+    // Persist the result of a game that just ended, with its clock stopped, and archive it
+    fn record_finished(&self, game_id: &str, game_state: &mut GameState) {
+        if game_state.game_result.is_some() {
+            game_state.clock.stop(self.now());
+        }
+        if let Some(event) = GameEvent::finished(game_id, game_state) {
+            self.record(event);
+            self.archive_game(self.game_record(game_id, game_state, GameRecordStatus::Finished));
+            self.rate_game(game_id, game_state);
+        }
+    }
+
+    // Update both players' ratings after a rated game. Games that end before both
+    // players have moved, and games in which a seat went to a guest, count for nothing.
+    fn rate_game(&self, game_id: &str, game_state: &GameState) {
+        if !game_state.rated || game_state.history.len() < 2 {
+            return;
+        }
+        let (Some(white_id), Some(black_id)) = (self.user_id(&game_state.white_player), self.user_id(&game_state.black_player)) else {
+            return;
+        };
+        let white_score = match pgn::result_token(game_state.game_result) {
+            "1-0" => 1.0,
+            "0-1" => 0.0,
+            _ => 0.5,
+        };
+
+        let pool = Pool::of(game_state.clock.control());
+        match self.ratings.rate_game(self.store.as_ref(), game_id, pool, [&white_id, &black_id], white_score, chrono::Utc::now()) {
+            Ok([white, black]) => info!(
+                "Rated game {} ({:?}): White now {:.0}, Black now {:.0}",
+                game_id, pool, white.rating.rating, black.rating.rating
+            ),
+            Err(e) => warn!("Failed to store ratings for game {}: {}", game_id, e),
+        }
+    }
+
+    // The seat's player if that is a user, as only users have ratings
+    fn user_id(&self, seat: &Option<String>) -> Option<String> {
+        self.accounts.user_by_id(seat.as_deref()?).map(|user| user.id)
+    }
+
+    // Whether the game is rated, and its players' current ratings in its pool
+    fn player_ratings(&self, game_state: &GameState) -> PlayerRatings {
+        self.seat_ratings(game_state.rated, game_state.clock.control(), [&game_state.white_player, &game_state.black_player])
+    }
+
+    // The same for a game's seats and time control copied out of its state
+    fn seat_ratings(&self, rated: bool, time_control: &TimeControl, [white, black]: [&Option<String>; 2]) -> PlayerRatings {
+        let pool = Pool::of(time_control);
+        let rating = |seat: &Option<String>| {
+            let user_id = self.user_id(seat)?;
+            Some(self.ratings.rating(&user_id, pool, chrono::Utc::now()).rating.round() as u32)
+        };
+        PlayerRatings {
+            rated,
+            white_rating: rating(white),
+            black_rating: rating(black),
+        }
+    }
+    
+    // Snapshot of a game with its players' names, as PGN export shows them
+    fn game_record(&self, game_id: &str, game_state: &GameState, status: GameRecordStatus) -> GameRecord {
+        GameRecord {
+            white_name: lobby::player_name(self, &game_state.white_player),
+            black_name: lobby::player_name(self, &game_state.black_player),
+            ..GameRecord::from_state(game_id, game_state, status)
+        }
+    }
+
+    fn archive_game(&self, record: GameRecord) {
+        if let Err(e) = self.store.archive_game(&record) {
+            warn!("Failed to archive game {}: {}", record.game_id, e);
+        }
+        self.archive.lock().unwrap().insert(record.game_id.clone(), record);
+    }
+    
+    // Look a game up in the archive first, then among the games still being played
+    fn find_game_record(&self, game_id: &str) -> Option<GameRecord> {
+        if let Some(record) = self.archive.lock().unwrap().get(game_id) {
+            return Some(record.clone());
+        }
+        
+        let games = self.games.lock().unwrap();
+        games
+            .get(game_id)
+            .map(|game_state| self.game_record(game_id, game_state, GameRecord::live_status(game_state)))
+    }
+    
+    // Every archived game plus the games still in memory
+    fn all_game_records(&self) -> Vec<GameRecord> {
+        let mut records = self.archive.lock().unwrap().clone();
+        
+        let games = self.games.lock().unwrap();
+        for (game_id, game_state) in games.iter() {
+            records.entry(game_id.clone()).or_insert_with(|| {
+                self.game_record(game_id, game_state, GameRecord::live_status(game_state))
+            });
+        }
+        drop(games);
+        
+        records.into_values().collect()
+    }
 
-// Last move information
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct LastMove {
-    from: String,
-    to: String,
+    // Ask the game's clock to re-arm its timeout after the game state changed
+    fn reset_clock(&self, game_id: &str) {
+        if let Some(clock) = self.clocks.lock().unwrap().get(game_id) {
+            clock.do_send(ResetClock);
+        }
+    }
 }
 
-// Message type for WebSocket communication
+// Seats a connection in a game its seek was paired into by another connection
 #[derive(Message)]
 #[rtype(result = "()")]
-struct ChessWebSocketMessage(String);
+struct Paired {
+    game_id: String,
+    color: Color,
+    joined: ServerMessage,
+}
+
+impl Handler<Paired> for ChessWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: Paired, ctx: &mut Self::Context) {
+        self.leave_current_game();
+        self.game_id = msg.game_id.clone();
+        self.color = Some(msg.color);
+        self.app_state.connections.lock().unwrap().entry(msg.game_id).or_default().push(self.id.clone());
+        self.send(ctx, &msg.joined);
+    }
+}
 
 impl Handler<ChessWebSocketMessage> for ChessWebSocket {
     type Result = ();
 
     fn handle(&mut self, msg: ChessWebSocketMessage, ctx: &mut Self::Context) {
-        info!("Forwarding message to client: {}", msg.0);
-        ctx.text(msg.0);
+        info!("Forwarding message to client: {:?}", msg.0);
+        self.send(ctx, &msg.0);
+
+        // An analysis of the game being watched follows it to the new position
+        if let ServerMessage::MoveMade { game_id, .. } = &msg.0 {
+            if self.analysis.as_ref().is_some_and(|analysis| &analysis.game_id == game_id) {
+                if let Err(error) = self.run_analysis(game_id.clone(), ctx) {
+                    self.send_game_error(ctx, game_id, error);
+                }
+            }
+        }
     }
 }
 
@@ -164,20 +543,21 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ChessWebSocket {
             }
             Ok(ws::Message::Text(text)) => {
                 info!("Received text message: {}", text);
-                match serde_json::from_str::<ClientMessage>(text.as_ref()) {
+                match protocol::parse_client_message(text.as_ref()) {
                     Ok(client_msg) => {
                         info!("Parsed client message: {:?}", client_msg);
                         self.handle_message(client_msg, ctx);
                     }
                     Err(e) => {
-                        warn!("Error parsing client message: {}", e);
-                        ctx.text(format!("{{\"error\": \"Invalid message format: {}\"}}", e));
+                        warn!("Error parsing client message: {}", e.message);
+                        self.send(ctx, &e.into_message());
                     }
                 }
             }
             Ok(ws::Message::Binary(_)) => {
                 warn!("Binary messages are not supported");
-                ctx.text("{\"error\": \"Binary messages are not supported\"}");
+                let error = ChessError::UnsupportedFrame;
+                self.send(ctx, &ServerMessage::error(error, error.message(), None));
             }
             Ok(ws::Message::Close(reason)) => {
                 info!("Connection closed: {:?}", reason);
@@ -192,668 +572,488 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ChessWebSocket {
 }
 
 impl ChessWebSocket {
+    // Who this connection sits in a game's seats as: the signed-in user, the guest, or the connection itself
+    fn player_id(&self) -> &str {
+        match (&self.user, &self.guest_id) {
+            (Some(user), _) => &user.id,
+            (None, Some(guest_id)) => guest_id,
+            (None, None) => &self.id,
+        }
+    }
+
+    // Send a message to this connection in its protocol version
+    fn send(&self, ctx: &mut ws::WebsocketContext<Self>, message: &ServerMessage) {
+        if let Some(text) = protocol::encode(message, self.protocol) {
+            ctx.text(text);
+        }
+    }
+
     fn broadcast_to_game(&self, game_id: &str, message: &ServerMessage) {
-        info!("Broadcasting message to game {}: {:?}", game_id, message.message_type);
-        
-        // Get the list of connection IDs for this game and all sessions
-        let connection_ids;
-        let sessions_copy;
-        
-        // Scope the locks to minimize lock time
+        // Skip sending to self if it's the same message type as what we just sent
+        let skip_id = if matches!(message, ServerMessage::Joined { .. } | ServerMessage::GameCreated { .. }) {
+            Some(self.id.as_str())
+        } else {
+            None
+        };
+
+        self.app_state.broadcast_to_game(game_id, message, skip_id);
+    }
+
+    fn handle_create(
+        &mut self,
+        time: TimeRequest,
+        color_preference: Option<ColorPreference>,
+        start_fen: Option<String>,
+        engine: EngineRequest,
+        rated: bool,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        info!("Creating a new game for player {}", self.id);
+
+        // Ratings are for users playing each other
+        if rated && self.user.is_none() {
+            self.send_error(ctx, ChessError::LoginRequired);
+            return;
+        }
+        if rated && engine.level.is_some() {
+            self.send_error_message(ctx, ChessError::InvalidMessage, "Games against the engine cannot be rated");
+            return;
+        }
+
+        let EngineRequest { level: engine_level, kind: engine_kind } = engine;
+        if !engine_level.is_none_or(|level| (engine::MIN_LEVEL..=engine::MAX_LEVEL).contains(&level))
+            || (engine_kind.is_some() && engine_level.is_none())
         {
-            let connections = self.app_state.connections.lock().unwrap();
-            if let Some(ids) = connections.get(game_id) {
-                connection_ids = ids.clone();
-            } else {
-                info!("No connections found for game {}", game_id);
-                return;
-            }
-            
-            let sessions = self.app_state.sessions.lock().unwrap();
-            sessions_copy = sessions.clone();
+            let message = format!("Engine games need an engine_level from {} to {}", engine::MIN_LEVEL, engine::MAX_LEVEL);
+            self.send_error_message(ctx, ChessError::InvalidEngineLevel, &message);
+            return;
         }
-        
-        info!("Found {} connections for game {}", connection_ids.len(), game_id);
-        
-        // Serialize the message once
-        let msg_str = serde_json::to_string(message).unwrap();
-        
-        // Send the message to each connection in the game
-        for connection_id in &connection_ids {
-            // Skip sending to self if it's the same message type as what we just sent
-            if connection_id == &self.id && (message.message_type == "joined" || message.message_type == "game_created") {
-                info!("Skipping sending to self ({})", self.id);
-                continue;
+        let engine_kind = engine_kind.unwrap_or_default();
+        if engine_kind == EngineKind::Uci && self.app_state.uci_engine.is_none() {
+            self.send_error(ctx, ChessError::EngineUnavailable);
+            return;
+        }
+
+        // Validate a custom starting position before leaving the current game
+        let start_fen = start_fen.as_deref().map(str::trim).filter(|fen| !fen.is_empty());
+        let (start_position, start_halfmove_clock) = match start_fen {
+            Some(fen) => match parse_start_position(fen) {
+                Ok(start) => start,
+                Err(error) => {
+                    info!("Rejected starting position {}: {}", fen, error);
+                    self.send_error_message(ctx, ChessError::InvalidPosition, &error);
+                    return;
+                }
+            },
+            None => (Board::default(), 0),
+        };
+
+        let time_control = match requested_time_control(&time) {
+            Ok(time_control) => time_control,
+            Err((error, message)) => {
+                self.send_error_message(ctx, error, &message);
+                return;
             }
-            
-            if let Some(addr) = sessions_copy.get(connection_id) {
-                info!("Sending message to player {}", connection_id);
-                addr.do_send(ChessWebSocketMessage(msg_str.clone()));
-            } else {
-                info!("Player {} not found in sessions", connection_id);
+        };
+
+        // Seat the creator according to their color preference (white by default)
+        let player_color = match color_preference.unwrap_or(ColorPreference::White) {
+            ColorPreference::White => Color::White,
+            ColorPreference::Black => Color::Black,
+            ColorPreference::Random => {
+                if rand::random() {
+                    Color::White
+                } else {
+                    Color::Black
+                }
             }
-        }
-    }
+        };
+
+        // If the user is already in a game, remove them from that game first
+        self.leave_current_game();
+
+        info!("Game settings: {:?}", time_control);
 
-    fn handle_create(&mut self, msg: ClientMessage, ctx: &mut ws::WebsocketContext<Self>) {
-        info!("Creating a new game for player {}", self.id);
-        
-        // Get time settings from the message or use defaults
-        let start_time_minutes = msg.start_time_minutes.unwrap_or(15);
-        let increment_seconds = msg.increment_seconds.unwrap_or(10);
-        
-        info!("Game settings: {} minutes, {} seconds increment", start_time_minutes, increment_seconds);
-        
         // Create a new game with a unique ID
         let game_id = Uuid::new_v4().to_string();
         self.game_id = game_id.clone();
-        
-        // Set the player's color to white
-        self.color = Some(Color::White);
-        
+
+        self.color = Some(player_color);
+
         // Add the player to the connections list for this game
-        let mut connections = self.app_state.connections.lock().unwrap();
-        connections.entry(game_id.clone()).or_insert_with(Vec::new).push(self.id.clone());
-        
+        self.app_state.connections.lock().unwrap().entry(game_id.clone()).or_default().push(self.id.clone());
+
+        // Token the player can use to reclaim their seat after a dropped connection
+        let resume_token = Uuid::new_v4().to_string();
+
+        // The engine takes the other seat right away, so its games start immediately
+        let seat = |color: Color| {
+            if color == player_color {
+                Some(self.player_id().to_string())
+            } else {
+                engine_level.map(|_| engine::ENGINE_PLAYER_ID.to_string())
+            }
+        };
+
+        let mut clock = Clock::new(time_control.clone());
+        if engine_level.is_some() {
+            clock.start(start_position.side_to_move(), self.app_state.now());
+        }
+
         // Create the game state
         let mut games = self.app_state.games.lock().unwrap();
         games.insert(
             game_id.clone(),
             GameState {
-                game: Game::new(),
-                white_player: Some(self.id.clone()),
-                black_player: None,
-                white_time_ms: start_time_minutes * 60 * 1000,
-                black_time_ms: start_time_minutes * 60 * 1000,
-                increment_ms: increment_seconds * 1000,
-                last_move_time: None,
-                active_player: Some(Color::White),
-                game_result: None,
+                white_player: seat(Color::White),
+                black_player: seat(Color::Black),
+                white_resume_token: (player_color == Color::White).then(|| resume_token.clone()),
+                black_resume_token: (player_color == Color::Black).then(|| resume_token.clone()),
+                start_halfmove_clock,
+                engine_level,
+                engine_kind,
+                rated,
+                ..GameState::new(start_position, start_fen.map(str::to_string), clock)
             },
         );
-        info!("Created new game {} with player {} as white", game_id, self.id);
-        
+        info!("Created new game {} with player {} as {:?}", game_id, self.id, player_color);
+
+        self.app_state.record(GameEvent::created(&game_id, &games[&game_id]));
+        self.app_state.record(GameEvent::Seated {
+            game_id: game_id.clone(),
+            color: player_color,
+            player_id: self.player_id().to_string(),
+            resume_token: resume_token.clone(),
+            at: Some(chrono::Utc::now()),
+        });
+        if engine_level.is_some() {
+            // Nobody gets to reclaim the engine's seat, but restoring the game needs one
+            self.app_state.record(GameEvent::Seated {
+                game_id: game_id.clone(),
+                color: !player_color,
+                player_id: engine::ENGINE_PLAYER_ID.to_string(),
+                resume_token: Uuid::new_v4().to_string(),
+                at: Some(chrono::Utc::now()),
+            });
+        }
+
+        // Start the server-side clock for this game
+        let clock = GameClock::new(game_id.clone(), self.app_state.clone()).start();
+        self.app_state.clocks.lock().unwrap().insert(game_id.clone(), clock);
+
         // Determine the game status
-        let game_status = if games.get(&game_id).unwrap().black_player.is_none() {
+        let game_state = games.get(&game_id).unwrap();
+        let game_status = if game_state.white_player.is_none() || game_state.black_player.is_none() {
             "waiting_for_opponent"
         } else {
             "in_progress"
         };
-        
-        // Get the FEN string from the game
-        let fen = games.get(&game_id).unwrap().game.current_position().to_string();
-        
+
         // Send a message to the client with the game information
-        let msg = ServerMessage {
-            message_type: "game_created".to_string(),
-            game_id: Some(game_id.clone()),
-            fen: Some(fen),
-            color: Some("white".to_string()),
-            error: None,
-            available_moves: None,
-            last_move: None,
-            game_status: Some(game_status.to_string()),
-            white_time_ms: Some(start_time_minutes * 60 * 1000),
-            black_time_ms: Some(start_time_minutes * 60 * 1000),
-            increment_ms: Some(increment_seconds * 1000),
-            active_color: None,
+        let msg = ServerMessage::GameCreated {
+            game_id: game_id.clone(),
+            fen: game_state.game.current_position().to_string(),
+            color: color_to_string(player_color),
+            game_status: game_status.to_string(),
+            active_color: color_to_string(start_position.side_to_move()),
+            clock: game_state.clock_times(self.app_state.now()),
+            resume_token,
         };
-        
+
         info!("Sending game_created message to player {}", self.id);
-        ctx.text(serde_json::to_string(&msg).unwrap());
+        self.send(ctx, &msg);
+
+        drop(games);
+        start_engine_move(&self.app_state, &game_id);
     }
 
-    fn handle_join(&mut self, msg: ClientMessage, ctx: &mut ws::WebsocketContext<Self>) {
-        if let Some(game_id) = msg.game_id {
-            info!("Player {} attempting to join game {}", self.id, game_id);
-            
-            // If the user is already in a game, remove them from that game first
-            if !self.game_id.is_empty() {
-                info!("Player {} is already in game {}. Removing from that game first", self.id, self.game_id);
-                
-                // Remove from connections list
-                let mut connections = self.app_state.connections.lock().unwrap();
-                if let Some(connection_ids) = connections.get_mut(&self.game_id) {
-                    // Remove this connection from the previous game
-                    connection_ids.retain(|id| id != &self.id);
-                    info!("Removed player {} from game {}'s connections", self.id, self.game_id);
-                }
-                
-                // Remove from game state if assigned a color
-                let mut games = self.app_state.games.lock().unwrap();
-                if let Some(game_state) = games.get_mut(&self.game_id) {
-                    if game_state.white_player.as_ref() == Some(&self.id) {
-                        info!("Removing player {} as white from game {}", self.id, self.game_id);
-                        game_state.white_player = None;
-                    }
-                    if game_state.black_player.as_ref() == Some(&self.id) {
-                        info!("Removing player {} as black from game {}", self.id, self.game_id);
-                        game_state.black_player = None;
-                    }
-                }
-                
-                // Drop locks before proceeding
-                drop(connections);
-                drop(games);
-                
-                // Clear the game ID and color from this connection
-                self.game_id = String::new();
-                self.color = None;
-                info!("Reset game ID and color for player {}", self.id);
-            }
-            
-            // Check if the game exists
-            let mut games = self.app_state.games.lock().unwrap();
-            
-            // Debug: Log all available games
-            info!("Available games: {:?}", games.keys().collect::<Vec<_>>());
-            
-            if let Some(game_state) = games.get_mut(&game_id) {
-                // Determine player color
-                let player_color = if game_state.white_player.is_none() {
-                    info!("Assigning player {} as white in game {}", self.id, game_id);
-                    game_state.white_player = Some(self.id.clone());
-                    Color::White
-                } else if game_state.black_player.is_none() {
-                    info!("Assigning player {} as black in game {}", self.id, game_id);
-                    game_state.black_player = Some(self.id.clone());
-                    Color::Black
-                } else {
-                    // Game is full
-                    info!("Cannot join game {}: Game is full", game_id);
-                    let error_msg = ServerMessage {
-                        message_type: "error".to_string(),
-                        game_id: Some(game_id),
-                        fen: None,
-                        color: None,
-                        error: Some("Game is full".to_string()),
-                        available_moves: None,
-                        last_move: None,
-                        game_status: None,
-                        white_time_ms: None,
-                        black_time_ms: None,
-                        increment_ms: None,
-                        active_color: None,
-                    };
-                    ctx.text(serde_json::to_string(&error_msg).unwrap());
-                    return;
-                };
-                
-                // Update this connection's game ID and color
-                self.game_id = game_id.clone();
-                self.color = Some(player_color);
-                info!("Set player {} color to {:?} in game {}", self.id, player_color, game_id);
-                
-                // Add player to connections list for this game
-                let mut connections = self.app_state.connections.lock().unwrap();
-                if let Some(connection_ids) = connections.get_mut(&game_id) {
-                    if !connection_ids.contains(&self.id) {
-                        connection_ids.push(self.id.clone());
-                        info!("Added player {} to game {}'s connections", self.id, game_id);
-                    }
-                } else {
-                    connections.insert(game_id.clone(), vec![self.id.clone()]);
-                    info!("Created new connections entry for game {} with player {}", game_id, self.id);
-                }
-                
-                // Get current game state
-                let fen = game_state.game.current_position().to_string();
-                
-                // Update game status to in_progress since both players are now present
-                let game_status = "in_progress".to_string();
-                
-                // Set the last_move_time when the second player joins to start the clock
-                if game_state.black_player.is_some() && game_state.white_player.is_some() {
-                    game_state.last_move_time = Some(std::time::Instant::now());
-                    info!("Setting initial last_move_time as both players have joined");
-                }
-                
-                // Send joined message to the player
-                let joined_msg = ServerMessage {
-                    message_type: "joined".to_string(),
-                    game_id: Some(game_id.clone()),
-                    fen: Some(fen.clone()),
-                    color: Some(color_to_string(player_color)),
-                    error: None,
-                    available_moves: None,
-                    last_move: None,
-                    game_status: Some(game_status.clone()),
-                    white_time_ms: Some(game_state.white_time_ms),
-                    black_time_ms: Some(game_state.black_time_ms),
-                    increment_ms: Some(game_state.increment_ms),
-                    active_color: None,
-                };
-                
-                info!("Sending joined message to player {}", self.id);
-                ctx.text(serde_json::to_string(&joined_msg).unwrap());
-                
-                // Notify other players that someone joined
-                let player_joined_msg = ServerMessage {
-                    message_type: "player_joined".to_string(),
-                    game_id: Some(game_id.clone()),
-                    fen: Some(fen),
-                    color: Some(color_to_string(player_color)),
-                    error: None,
-                    available_moves: None,
-                    last_move: None,
-                    game_status: Some(game_status),
-                    white_time_ms: Some(game_state.white_time_ms),
-                    black_time_ms: Some(game_state.black_time_ms),
-                    increment_ms: Some(game_state.increment_ms),
-                    active_color: None,
-                };
-                
-                // Drop the locks before broadcasting
-                drop(games);
-                drop(connections);
-                
-                info!("Broadcasting player_joined message for game {}", game_id);
-                self.broadcast_to_game(&game_id, &player_joined_msg);
-            } else {
-                // Game not found
-                info!("Game {} not found", game_id);
-                let error_msg = ServerMessage {
-                    message_type: "error".to_string(),
-                    game_id: Some(game_id),
-                    fen: None,
-                    color: None,
-                    error: Some("Game not found".to_string()),
-                    available_moves: None,
-                    last_move: None,
-                    game_status: None,
-                    white_time_ms: None,
-                    black_time_ms: None,
-                    increment_ms: None,
-                    active_color: None,
-                };
-                ctx.text(serde_json::to_string(&error_msg).unwrap());
-            }
-        } else {
-            // No game ID provided
-            info!("Join request missing game ID");
-            let error_msg = ServerMessage {
-                message_type: "error".to_string(),
-                game_id: None,
-                fen: None,
-                color: None,
-                error: Some("Game ID is required to join a game".to_string()),
-                available_moves: None,
-                last_move: None,
-                game_status: None,
-                white_time_ms: None,
-                black_time_ms: None,
-                increment_ms: None,
-                active_color: None,
-            };
-            ctx.text(serde_json::to_string(&error_msg).unwrap());
-        }
-    }
+    fn leave_current_game(&mut self) {
+        // Whoever takes a seat stops analysing, whatever game the analysis was of, and stops seeking
+        self.stop_analysis();
+        self.cancel_seek();
 
-    fn handle_move(&mut self, msg: ClientMessage, ctx: &mut ws::WebsocketContext<Self>) {
-        info!("Processing move from player {}", self.id);
-        
         if self.game_id.is_empty() {
-            let error_msg = ServerMessage {
-                message_type: "error".to_string(),
-                game_id: None,
-                fen: None,
-                color: None,
-                error: Some("You are not in a game".to_string()),
-                available_moves: None,
-                last_move: None,
-                game_status: None,
-                white_time_ms: None,
-                black_time_ms: None,
-                increment_ms: None,
-                active_color: None,
-            };
-            ctx.text(serde_json::to_string(&error_msg).unwrap());
             return;
         }
-        
-        let from = msg.move_from.as_ref().unwrap_or(&"".to_string()).to_string();
-        let to = msg.move_to.as_ref().unwrap_or(&"".to_string()).to_string();
-        
-        if from.is_empty() || to.is_empty() {
-            let error_msg = ServerMessage {
-                message_type: "error".to_string(),
-                game_id: Some(self.game_id.clone()),
-                fen: None,
-                color: None,
-                error: Some("Invalid move format".to_string()),
-                available_moves: None,
-                last_move: None,
-                game_status: None,
-                white_time_ms: None,
-                black_time_ms: None,
-                increment_ms: None,
-                active_color: None,
-            };
-            ctx.text(serde_json::to_string(&error_msg).unwrap());
-            return;
+
+        info!("Player {} is already in game {}. Removing from that game first", self.id, self.game_id);
+
+        if let Some(spectator_count) = self.remove_spectator() {
+            self.broadcast_spectators(ServerMessage::SpectatorLeft {
+                game_id: self.game_id.clone(),
+                spectator_count,
+            });
         }
-        
+
+        // Remove from connections list, releasing the lock before the games lock is taken
+        let mut still_playing = false;
+        if let Some(connection_ids) = self.app_state.connections.lock().unwrap().get_mut(&self.game_id) {
+            // Remove this connection from the previous game
+            connection_ids.retain(|id| id != &self.id);
+            info!("Removed player {} from game {}'s connections", self.id, self.game_id);
+            // The player may still be at the board in another tab
+            still_playing = self.app_state.is_player_connected(connection_ids, self.player_id());
+        }
+
+        // Remove from game state if assigned a color
         let mut games = self.app_state.games.lock().unwrap();
-        
-        if let Some(game_state) = games.get_mut(&self.game_id) {
-            let game = &mut game_state.game;
-            
-            // Check if the game has already ended due to timeout or other reasons
-            if game_state.game_result.is_some() {
-                let error_msg = ServerMessage {
-                    message_type: "error".to_string(),
-                    game_id: Some(self.game_id.clone()),
-                    fen: None,
-                    color: None,
-                    error: Some("Game has already ended".to_string()),
-                    available_moves: None,
-                    last_move: None,
-                    game_status: None,
-                    white_time_ms: None,
-                    black_time_ms: None,
-                    increment_ms: None,
-                    active_color: None,
-                };
-                ctx.text(serde_json::to_string(&error_msg).unwrap());
-                return;
+        if let Some(game_state) = games.get_mut(&self.game_id).filter(|_| !still_playing) {
+            if game_state.white_player.as_deref() == Some(self.player_id()) {
+                info!("Removing player {} as white from game {}", self.id, self.game_id);
+                game_state.white_player = None;
+                game_state.white_resume_token = None;
+                self.app_state.record(GameEvent::Unseated { game_id: self.game_id.clone(), color: Color::White });
             }
-            
-            // Check if it's the player's turn
-            let current_turn = game.side_to_move();
-            let player_color = if game_state.white_player.as_ref() == Some(&self.id) {
-                Some(Color::White)
-            } else if game_state.black_player.as_ref() == Some(&self.id) {
-                Some(Color::Black)
+            if game_state.black_player.as_deref() == Some(self.player_id()) {
+                info!("Removing player {} as black from game {}", self.id, self.game_id);
+                game_state.black_player = None;
+                game_state.black_resume_token = None;
+                self.app_state.record(GameEvent::Unseated { game_id: self.game_id.clone(), color: Color::Black });
+            }
+        }
+
+        // Drop the lock before proceeding
+        drop(games);
+
+        // Clear the game ID and color from this connection
+        self.game_id = String::new();
+        self.color = None;
+        info!("Reset game ID and color for player {}", self.id);
+    }
+
+    fn handle_join(&mut self, game_id: String, ctx: &mut ws::WebsocketContext<Self>) {
+        if let Err(error) = validation::check_game_id(&game_id) {
+            self.send_error(ctx, error);
+            return;
+        }
+
+        info!("Player {} attempting to join game {}", self.id, game_id);
+
+        // If the user is already in a game, remove them from that game first
+        self.leave_current_game();
+
+        // Check if the game exists
+        let mut games = self.app_state.games.lock().unwrap();
+
+        // Debug: Log all available games
+        info!("Available games: {:?}", games.keys().collect::<Vec<_>>());
+
+        if let Some(game_state) = games.get_mut(&game_id) {
+            // A player joining a game they already play in, say from another tab, gets their own
+            // seat back rather than the other one
+            let player_id = self.player_id().to_string();
+            let seat_token = if game_state.white_player.as_deref() == Some(player_id.as_str()) {
+                game_state.white_resume_token.clone()
+            } else if game_state.black_player.as_deref() == Some(player_id.as_str()) {
+                game_state.black_resume_token.clone()
             } else {
                 None
             };
-            
-            if player_color != Some(current_turn) {
-                let error_msg = ServerMessage {
-                    message_type: "error".to_string(),
-                    game_id: Some(self.game_id.clone()),
-                    fen: None,
-                    color: None,
-                    error: Some("It's not your turn".to_string()),
-                    available_moves: None,
-                    last_move: None,
-                    game_status: None,
-                    white_time_ms: None,
-                    black_time_ms: None,
-                    increment_ms: None,
-                    active_color: None,
-                };
-                ctx.text(serde_json::to_string(&error_msg).unwrap());
+            if let Some(resume_token) = seat_token {
+                info!("Player {} already has a seat in game {}", player_id, game_id);
+                drop(games);
+                self.handle_rejoin(game_id, resume_token, ctx);
                 return;
             }
-            
-            // Parse the move
-            let from_square = Square::from_str(&from).unwrap();
-            let to_square = Square::from_str(&to).unwrap();
-            
-            // Check if the piece belongs to the player
-            if let Some(_piece) = game.current_position().piece_on(from_square) {
-                // Check if this is a pawn promotion move
-                let promotion_piece = match msg.promote_to {
-                    Some(ref piece) => {
-                        match piece.as_str() {
-                            "queen" => Some(Piece::Queen),
-                            "rook" => Some(Piece::Rook),
-                            "bishop" => Some(Piece::Bishop),
-                            "knight" => Some(Piece::Knight),
-                            _ => None,
-                        }
-                    },
-                    None => None,
-                };
-                
-                // Try to make the move
-                let chess_move = ChessMove::new(from_square, to_square, promotion_piece);
-                
-                if game.make_move(chess_move) {
-                    // Update timers
-                    let now = std::time::Instant::now();
-                    
-                    // If this is not the first move, update the time for the player who just moved
-                    if let Some(last_move_time) = game_state.last_move_time {
-                        let elapsed = now.duration_since(last_move_time).as_millis() as u64;
-                        
-                        // Update the time for the player who just moved
-                        match player_color {
-                            Some(Color::White) => {
-                                if game_state.white_time_ms > elapsed {
-                                    game_state.white_time_ms -= elapsed;
-                                    // Add increment after the move
-                                    game_state.white_time_ms += game_state.increment_ms;
-                                } else {
-                                    game_state.white_time_ms = 0;
-                                    // Player lost on time - check for insufficient material
-                                    if has_insufficient_material(&game.current_position()) {
-                                        info!("White lost on time but opponent has insufficient material - draw");
-                                        // Set game result to draw
-                                        game_state.game_result = Some(GameResult::DrawDeclared);
-                                    } else {
-                                        info!("White lost on time");
-                                        // Set game result to black wins
-                                        game_state.game_result = Some(GameResult::WhiteResigns);
-                                    }
-                                }
-                            },
-                            Some(Color::Black) => {
-                                if game_state.black_time_ms > elapsed {
-                                    game_state.black_time_ms -= elapsed;
-                                    // Add increment after the move
-                                    game_state.black_time_ms += game_state.increment_ms;
-                                } else {
-                                    game_state.black_time_ms = 0;
-                                    // Player lost on time - check for insufficient material
-                                    if has_insufficient_material(&game.current_position()) {
-                                        info!("Black lost on time but opponent has insufficient material - draw");
-                                        // Set game result to draw
-                                        game_state.game_result = Some(GameResult::DrawDeclared);
-                                    } else {
-                                        info!("Black lost on time");
-                                        // Set game result to white wins
-                                        game_state.game_result = Some(GameResult::BlackResigns);
-                                    }
-                                }
-                            },
-                            None => {}
-                        }
-                    }
-                    
-                    // Update the last move time and active player
-                    game_state.last_move_time = Some(now);
-                    game_state.active_player = Some(game.side_to_move());
-                    
-                    // Log the active player for debugging
-                    info!("Active player after move: {:?}", game_state.active_player);
-                    
-                    // Create the last move info
-                    let last_move = LastMove {
-                        from,
-                        to,
-                    };
-                    
-                    // Get the updated game status
-                    let game_status = get_game_status(game, game_state.game_result);
-                    
-                    // Create the message to broadcast
-                    let msg = ServerMessage {
-                        message_type: "move_made".to_string(),
-                        game_id: Some(self.game_id.clone()),
-                        fen: Some(game.current_position().to_string()),
-                        color: None,
-                        error: None,
-                        available_moves: None,
-                        last_move: Some(last_move),
-                        game_status: Some(game_status),
-                        white_time_ms: Some(game_state.white_time_ms),
-                        black_time_ms: Some(game_state.black_time_ms),
-                        increment_ms: Some(game_state.increment_ms),
-                        active_color: None,
-                    };
-                    
-                    self.broadcast_to_game(&self.game_id, &msg);
-                } else {
-                    // Move was invalid
-                    let error_msg = ServerMessage {
-                        message_type: "error".to_string(),
-                        game_id: Some(self.game_id.clone()),
-                        fen: None,
-                        color: None,
-                        error: Some("Invalid move".to_string()),
-                        available_moves: None,
-                        last_move: None,
-                        game_status: None,
-                        white_time_ms: None,
-                        black_time_ms: None,
-                        increment_ms: None,
-                        active_color: None,
-                    };
-                    ctx.text(serde_json::to_string(&error_msg).unwrap());
+            if game_state.rated && self.user.is_none() {
+                drop(games);
+                self.send_game_error(ctx, &game_id, ChessError::LoginRequired);
+                return;
+            }
+
+            // Token the player can use to reclaim their seat after a dropped connection
+            let resume_token = Uuid::new_v4().to_string();
+
+            // Determine player color
+            let player_color = if game_state.white_player.is_none() {
+                info!("Assigning player {} as white in game {}", self.id, game_id);
+                game_state.white_player = Some(player_id.clone());
+                game_state.white_resume_token = Some(resume_token.clone());
+                Color::White
+            } else if game_state.black_player.is_none() {
+                info!("Assigning player {} as black in game {}", self.id, game_id);
+                game_state.black_player = Some(player_id.clone());
+                game_state.black_resume_token = Some(resume_token.clone());
+                Color::Black
+            } else {
+                // Game is full
+                info!("Cannot join game {}: Game is full", game_id);
+                drop(games);
+                self.send_game_error(ctx, &game_id, ChessError::GameFull);
+                return;
+            };
+
+            // Update this connection's game ID and color
+            self.game_id = game_id.clone();
+            self.color = Some(player_color);
+            info!("Set player {} color to {:?} in game {}", self.id, player_color, game_id);
+
+            self.app_state.record(GameEvent::Seated {
+                game_id: game_id.clone(),
+                color: player_color,
+                player_id,
+                resume_token: resume_token.clone(),
+                at: Some(chrono::Utc::now()),
+            });
+
+            // Add player to connections list for this game
+            let mut connections = self.app_state.connections.lock().unwrap();
+            if let Some(connection_ids) = connections.get_mut(&game_id) {
+                if !connection_ids.contains(&self.id) {
+                    connection_ids.push(self.id.clone());
+                    info!("Added player {} to game {}'s connections", self.id, game_id);
                 }
             } else {
-                let error_msg = ServerMessage {
-                    message_type: "error".to_string(),
-                    game_id: Some(self.game_id.clone()),
-                    fen: None,
-                    color: None,
-                    error: Some("No piece at the selected square".to_string()),
-                    available_moves: None,
-                    last_move: None,
-                    game_status: None,
-                    white_time_ms: None,
-                    black_time_ms: None,
-                    increment_ms: None,
-                    active_color: None,
-                };
-                ctx.text(serde_json::to_string(&error_msg).unwrap());
+                connections.insert(game_id.clone(), vec![self.id.clone()]);
+                info!("Created new connections entry for game {} with player {}", game_id, self.id);
             }
-        } else {
-            let error_msg = ServerMessage {
-                message_type: "error".to_string(),
-                game_id: Some(self.game_id.clone()),
-                fen: None,
-                color: None,
-                error: Some("Game not found".to_string()),
-                available_moves: None,
-                last_move: None,
-                game_status: None,
-                white_time_ms: None,
-                black_time_ms: None,
-                increment_ms: None,
-                active_color: None,
+
+            // Get current game state
+            let fen = game_state.game.current_position().to_string();
+
+            // Update game status to in_progress since both players are now present
+            let game_status = "in_progress".to_string();
+
+            // Start the clock when the second player joins
+            if game_state.black_player.is_some() && game_state.white_player.is_some() {
+                let side_to_move = game_state.game.side_to_move();
+                game_state.clock.start(side_to_move, self.app_state.now());
+                info!("Starting the clock as both players have joined");
+            }
+
+            // Send joined message to the player
+            let joined_msg = ServerMessage::Joined {
+                game_id: game_id.clone(),
+                fen: fen.clone(),
+                color: color_to_string(player_color),
+                game_status: game_status.clone(),
+                active_color: color_to_string(game_state.game.side_to_move()),
+                clock: game_state.clock_times(self.app_state.now()),
+                resume_token,
+                spectator_count: game_state.spectators.len(),
+                history: game_state.history.clone(),
+                ratings: self.app_state.player_ratings(game_state),
+            };
+
+            info!("Sending joined message to player {}", self.id);
+            self.send(ctx, &joined_msg);
+
+            // Notify other players that someone joined
+            let player_joined_msg = ServerMessage::PlayerJoined {
+                game_id: game_id.clone(),
+                fen,
+                color: color_to_string(player_color),
+                game_status,
+                clock: game_state.clock_times(self.app_state.now()),
+                spectator_count: game_state.spectators.len(),
+                ratings: self.app_state.player_ratings(game_state),
             };
-            ctx.text(serde_json::to_string(&error_msg).unwrap());
+
+            // Drop the locks before broadcasting
+            drop(games);
+            drop(connections);
+
+            info!("Broadcasting player_joined message for game {}", game_id);
+            self.broadcast_to_game(&game_id, &player_joined_msg);
+
+            // The clock starts running once both players are present
+            self.app_state.reset_clock(&game_id);
+        } else {
+            // Game not found
+            info!("Game {} not found", game_id);
+            drop(games);
+            self.send_game_error(ctx, &game_id, ChessError::GameNotFound);
         }
     }
 
-    fn handle_get_moves(&mut self, msg: ClientMessage, ctx: &mut ws::WebsocketContext<Self>) {
+    fn handle_move(&mut self, requested: MoveRequest, ctx: &mut ws::WebsocketContext<Self>) {
+        info!("Processing move from player {}", self.id);
+
         if self.game_id.is_empty() {
-            let error_msg = ServerMessage {
-                message_type: "error".to_string(),
-                game_id: None,
-                fen: None,
-                color: None,
-                error: Some("Not in a game".to_string()),
-                available_moves: None,
-                last_move: None,
-                game_status: None,
-                white_time_ms: None,
-                black_time_ms: None,
-                increment_ms: None,
-                active_color: None,
-            };
-            ctx.text(serde_json::to_string(&error_msg).unwrap());
+            self.send_error(ctx, ChessError::NotInGame);
             return;
         }
 
-        let from = match msg.move_from {
-            Some(from) => from,
-            None => {
-                let error_msg = ServerMessage {
-                    message_type: "error".to_string(),
-                    game_id: Some(self.game_id.clone()),
-                    fen: None,
-                    color: None,
-                    error: Some("No from square provided".to_string()),
-                    available_moves: None,
-                    last_move: None,
-                    game_status: None,
-                    white_time_ms: None,
-                    black_time_ms: None,
-                    increment_ms: None,
-                    active_color: None,
-                };
-                ctx.text(serde_json::to_string(&error_msg).unwrap());
+        if self.color.is_none() {
+            self.send_error_message(ctx, ChessError::NotAPlayer, "Spectators cannot make moves");
+            return;
+        }
+
+        // Parse the move before touching the game; only SAN needs the board
+        let requested = match requested_move(requested) {
+            Ok(requested) => requested,
+            Err((error, message)) => {
+                self.send_error_message(ctx, error, &message);
                 return;
             }
         };
-        
+
+        if let Err((error, message)) = play_move(&self.app_state, &self.game_id, self.player_id(), requested) {
+            self.send_error_message(ctx, error, &message);
+        }
+    }
+
+    fn handle_get_moves(&mut self, from: String, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.game_id.is_empty() {
+            self.send_error(ctx, ChessError::NotInGame);
+            return;
+        }
+
+        if self.color.is_none() {
+            self.send_error_message(ctx, ChessError::NotAPlayer, "Spectators cannot request moves");
+            return;
+        }
+
+        let from_square = match validation::parse_square(&from) {
+            Ok(square) => square,
+            Err(error) => {
+                self.send_error_message(ctx, error, &format!("Invalid square {:?}", from));
+                return;
+            }
+        };
+
         let mut games = self.app_state.games.lock().unwrap();
-        
+
         if let Some(game_state) = games.get_mut(&self.game_id) {
-            // Parse the from square
-            let from_square = Square::from_str(&from.to_lowercase()).unwrap();
+            // Finished games are locked
+            if game_state.game_result.is_some() {
+                drop(games);
+                self.send_error(ctx, ChessError::GameOver);
+                return;
+            }
+
             let board = game_state.game.current_position();
-            
+
             // Check if there's a piece at the square
             if let Some(piece) = board.piece_on(from_square) {
                 // Check if it's the player's turn
                 let current_turn = game_state.game.side_to_move();
-                let player_color = if game_state.white_player.as_ref() == Some(&self.id) {
+                let player_color = if game_state.white_player.as_deref() == Some(self.player_id()) {
                     Some(Color::White)
-                } else if game_state.black_player.as_ref() == Some(&self.id) {
+                } else if game_state.black_player.as_deref() == Some(self.player_id()) {
                     Some(Color::Black)
                 } else {
                     None
                 };
-                
-                info!("Turn check: current_turn={:?}, player_color={:?}, player_id={}, white_player={:?}, black_player={:?}", 
+
+                info!("Turn check: current_turn={:?}, player_color={:?}, player_id={}, white_player={:?}, black_player={:?}",
                       current_turn, player_color, self.id, game_state.white_player, game_state.black_player);
-                
+
                 if player_color != Some(current_turn) {
-                    let error_msg = ServerMessage {
-                        message_type: "error".to_string(),
-                        game_id: Some(self.game_id.clone()),
-                        fen: None,
-                        color: None,
-                        error: Some("Not your turn".to_string()),
-                        available_moves: None,
-                        last_move: None,
-                        game_status: None,
-                        white_time_ms: None,
-                        black_time_ms: None,
-                        increment_ms: None,
-                        active_color: None,
-                    };
-                    ctx.text(serde_json::to_string(&error_msg).unwrap());
+                    drop(games);
+                    self.send_error(ctx, ChessError::NotYourTurn);
                     return;
                 }
-                
+
                 // Check if the piece belongs to the player
                 let piece_color = board.color_on(from_square).unwrap();
-                
-                info!("Piece color check: piece={:?}, piece_color={:?}, player_color={:?}, self.color={:?}", 
+
+                info!("Piece color check: piece={:?}, piece_color={:?}, player_color={:?}, self.color={:?}",
                       piece, piece_color, player_color, self.color);
-                
+
                 if player_color != Some(piece_color) {
-                    let error_msg = ServerMessage {
-                        message_type: "error".to_string(),
-                        game_id: Some(self.game_id.clone()),
-                        fen: None,
-                        color: None,
-                        error: Some("Not your piece".to_string()),
-                        available_moves: None,
-                        last_move: None,
-                        game_status: None,
-                        white_time_ms: None,
-                        black_time_ms: None,
-                        increment_ms: None,
-                        active_color: None,
-                    };
-                    ctx.text(serde_json::to_string(&error_msg).unwrap());
+                    drop(games);
+                    self.send_error(ctx, ChessError::NotYourPiece);
                     return;
                 }
 
@@ -866,268 +1066,1623 @@ impl ChessWebSocket {
                     }
                 }
 
-                let msg = ServerMessage {
-                    message_type: "available_moves".to_string(),
-                    game_id: Some(self.game_id.clone()),
-                    fen: None,
-                    color: None,
-                    error: None,
-                    available_moves: Some(valid_moves),
-                    last_move: None,
-                    game_status: None,
-                    white_time_ms: None,
-                    black_time_ms: None,
-                    increment_ms: None,
-                    active_color: None,
-                };
-                ctx.text(serde_json::to_string(&msg).unwrap());
+                drop(games);
+                self.send(ctx, &ServerMessage::AvailableMoves {
+                    game_id: self.game_id.clone(),
+                    available_moves: valid_moves,
+                });
             } else {
-                let error_msg = ServerMessage {
-                    message_type: "error".to_string(),
-                    game_id: Some(self.game_id.clone()),
-                    fen: None,
-                    color: None,
-                    error: Some("No piece at that square".to_string()),
-                    available_moves: None,
-                    last_move: None,
-                    game_status: None,
-                    white_time_ms: None,
-                    black_time_ms: None,
-                    increment_ms: None,
-                    active_color: None,
-                };
-                ctx.text(serde_json::to_string(&error_msg).unwrap());
+                drop(games);
+                self.send_error(ctx, ChessError::NoPiece);
             }
         } else {
-            let error_msg = ServerMessage {
-                message_type: "error".to_string(),
-                game_id: Some(self.game_id.clone()),
-                fen: None,
-                color: None,
-                error: Some("Game not found".to_string()),
-                available_moves: None,
-                last_move: None,
-                game_status: None,
-                white_time_ms: None,
-                black_time_ms: None,
-                increment_ms: None,
-                active_color: None,
-            };
-            ctx.text(serde_json::to_string(&error_msg).unwrap());
+            drop(games);
+            self.send_error(ctx, ChessError::GameNotFound);
         }
     }
 
-    fn handle_time_sync(&mut self, msg: ClientMessage, ctx: &mut ws::WebsocketContext<Self>) {
+    fn handle_time_sync(&mut self, game_id: Option<String>, ctx: &mut ws::WebsocketContext<Self>) {
         info!("Time sync request received from player {}", self.id);
-        
+
         // Get the game ID from the message
-        let game_id = match msg.game_id {
+        let game_id = match game_id {
             Some(id) => id,
             None => {
                 info!("Time sync request missing game ID");
-                let error_msg = ServerMessage {
-                    message_type: "error".to_string(),
-                    game_id: None,
-                    fen: None,
-                    color: None,
-                    error: Some("Game ID is required".to_string()),
-                    available_moves: None,
-                    last_move: None,
-                    game_status: None,
-                    white_time_ms: None,
-                    black_time_ms: None,
-                    increment_ms: None,
-                    active_color: None,
-                };
-                ctx.text(serde_json::to_string(&error_msg).unwrap());
+                self.send_error_message(ctx, ChessError::InvalidMessage, "Game ID is required");
                 return;
             }
         };
-        
+        if let Err(error) = validation::check_game_id(&game_id) {
+            self.send_error(ctx, error);
+            return;
+        }
+
         // Get the game state
         let mut games = self.app_state.games.lock().unwrap();
         if let Some(game_state) = games.get_mut(&game_id) {
-            // Update the time for the active player if a move has been made
-            if let Some(last_move_time) = game_state.last_move_time {
-                let now = std::time::Instant::now();
-                let elapsed = now.duration_since(last_move_time).as_millis() as u64;
-                
-                // Only update the time if the game is in progress
-                if game_state.white_player.is_some() && game_state.black_player.is_some() {
-                    // Update the time for the active player
-                    match game_state.active_player {
-                        Some(Color::White) => {
-                            if game_state.white_time_ms > elapsed {
-                                game_state.white_time_ms -= elapsed;
-                            } else {
-                                game_state.white_time_ms = 0;
-                                // Player lost on time - check for insufficient material
-                                if has_insufficient_material(&game_state.game.current_position()) {
-                                    info!("White lost on time but opponent has insufficient material - draw");
-                                    // Set game result to draw
-                                    game_state.game_result = Some(GameResult::DrawDeclared);
-                                } else {
-                                    info!("White lost on time");
-                                    // Set game result to black wins
-                                    game_state.game_result = Some(GameResult::WhiteResigns);
-                                }
-                            }
-                        },
-                        Some(Color::Black) => {
-                            if game_state.black_time_ms > elapsed {
-                                game_state.black_time_ms -= elapsed;
-                            } else {
-                                game_state.black_time_ms = 0;
-                                // Player lost on time - check for insufficient material
-                                if has_insufficient_material(&game_state.game.current_position()) {
-                                    info!("Black lost on time but opponent has insufficient material - draw");
-                                    // Set game result to draw
-                                    game_state.game_result = Some(GameResult::DrawDeclared);
-                                } else {
-                                    info!("Black lost on time");
-                                    // Set game result to white wins
-                                    game_state.game_result = Some(GameResult::BlackResigns);
-                                }
-                            }
-                        },
-                        None => {}
-                    }
-                    
-                    // Update the last move time
-                    game_state.last_move_time = Some(now);
+            // End the game if the side to move has run out of time; correspondence
+            // games are left to the sweeper
+            let now = self.app_state.now();
+            if game_state.game_result.is_none() && !game_state.is_correspondence() {
+                if let Some(color) = game_state.clock.flagged(now) {
+                    clock::flag_player(game_state, color);
+                    self.app_state.record_finished(&game_id, game_state);
                 }
             }
-            
-            // Get the active color from the current position
-            let active_color = match game_state.game.side_to_move() {
-                Color::White => "white",
-                Color::Black => "black",
-            };
-            
-            // Get the game status
-            let game_status = get_game_status(&game_state.game, game_state.game_result);
-            
+
             // Send the time sync response
-            let time_sync_msg = ServerMessage {
-                message_type: "time_sync".to_string(),
-                game_id: Some(game_id.clone()),
-                fen: Some(game_state.game.current_position().to_string()),
-                color: None,
-                error: None,
-                available_moves: None,
-                last_move: None,
-                game_status: Some(game_status),
-                white_time_ms: Some(game_state.white_time_ms),
-                black_time_ms: Some(game_state.black_time_ms),
-                increment_ms: Some(game_state.increment_ms),
-                active_color: Some(active_color.to_string()),
+            let time_sync_msg = ServerMessage::TimeSync {
+                game_id: game_id.clone(),
+                fen: game_state.game.current_position().to_string(),
+                game_status: get_game_status(&game_state.game, game_state.game_result),
+                active_color: color_to_string(game_state.game.side_to_move()),
+                clock: game_state.clock_times(now),
+                termination: game_state.termination.map(|termination| termination.as_str().to_string()),
             };
-            
+
             // Drop the lock before broadcasting
             drop(games);
-            
+
             // Broadcast the time sync response to all players in the game
             self.broadcast_to_game(&game_id, &time_sync_msg);
+            self.app_state.reset_clock(&game_id);
         } else {
             // Game not found
             info!("Game {} not found for time sync", game_id);
-            let error_msg = ServerMessage {
-                message_type: "error".to_string(),
-                game_id: Some(game_id),
-                fen: None,
-                color: None,
-                error: Some("Game not found".to_string()),
-                available_moves: None,
-                last_move: None,
-                game_status: None,
-                white_time_ms: None,
-                black_time_ms: None,
-                increment_ms: None,
-                active_color: None,
-            };
-            ctx.text(serde_json::to_string(&error_msg).unwrap());
+            drop(games);
+            self.send_game_error(ctx, &game_id, ChessError::GameNotFound);
         }
     }
 
-    fn handle_message(&mut self, msg: ClientMessage, ctx: &mut ws::WebsocketContext<Self>) {
-        match msg.message_type.as_str() {
-            "create" => self.handle_create(msg, ctx),
-            "join" => self.handle_join(msg, ctx),
-            "move" => self.handle_move(msg, ctx),
-            "get_moves" => self.handle_get_moves(msg, ctx),
-            "time_sync" => self.handle_time_sync(msg, ctx),
-            _ => {
-                info!("Unknown message type: {}", msg.message_type);
-                ctx.text(format!("{{\"error\": \"Unknown message type: {}\"}}", msg.message_type));
+    fn handle_rejoin(&mut self, game_id: String, resume_token: String, ctx: &mut ws::WebsocketContext<Self>) {
+        if let Err(error) = validation::check_game_id(&game_id).and(validation::check_resume_token(&resume_token)) {
+            self.send_error(ctx, error);
+            return;
+        }
+
+        info!("Player {} attempting to rejoin game {}", self.id, game_id);
+
+        // If the user is in another game, remove them from that game first
+        if self.game_id != game_id {
+            self.leave_current_game();
+        }
+
+        let mut games = self.app_state.games.lock().unwrap();
+        let game_state = match games.get_mut(&game_id) {
+            Some(game_state) => game_state,
+            None => {
+                info!("Game {} not found for rejoin", game_id);
+                drop(games);
+                self.send_game_error(ctx, &game_id, ChessError::GameNotFound);
+                return;
             }
+        };
+
+        // Reclaim the seat the token was issued for
+        let player_color = if game_state.white_resume_token.as_ref() == Some(&resume_token) {
+            game_state.white_player = Some(self.player_id().to_string());
+            Color::White
+        } else if game_state.black_resume_token.as_ref() == Some(&resume_token) {
+            game_state.black_player = Some(self.player_id().to_string());
+            Color::Black
+        } else {
+            info!("Invalid resume token for game {}", game_id);
+            drop(games);
+            self.send_game_error(ctx, &game_id, ChessError::InvalidResumeToken);
+            return;
+        };
+
+        self.game_id = game_id.clone();
+        self.color = Some(player_color);
+        info!("Player {} reclaimed {:?} in game {}", self.id, player_color, game_id);
+
+        // Add player to connections list for this game
+        let mut connections = self.app_state.connections.lock().unwrap();
+        let connection_ids = connections.entry(game_id.clone()).or_default();
+        if !connection_ids.contains(&self.id) {
+            connection_ids.push(self.id.clone());
         }
-    }
-}
 
-// WebSocket connection handler
-async fn ws_index(req: HttpRequest, stream: web::Payload, app_state: web::Data<AppState>) -> Result<HttpResponse, Error> {
-    info!("New WebSocket connection request");
-    
-    // Create a unique ID for this connection
-    let id = Uuid::new_v4().to_string();
-    info!("Generated WebSocket ID: {}", id);
-    
-    // Initialize the WebSocket actor
-    let ws = ChessWebSocket {
-        id: id.clone(),
-        app_state: app_state.clone(),
-        game_id: String::new(),
-        color: None,
-    };
-    
-    // Start the WebSocket actor
-    ws::start(ws, &req, stream)
-}
+        // A restored game's clock starts again once both players are back
+        let both_connected = [&game_state.white_player, &game_state.black_player]
+            .iter()
+            .all(|player| {
+                player.as_ref().is_some_and(|id| id == engine::ENGINE_PLAYER_ID || self.app_state.is_player_connected(connection_ids, id))
+            });
+        let resumed = game_state.is_paused() && both_connected;
+        if resumed {
+            info!("Both players are back in game {}. Resuming the clock", game_id);
+            let side_to_move = game_state.game.side_to_move();
+            game_state.clock.start(side_to_move, self.app_state.now());
+        }
+        drop(connections);
 
-// HTTP handlers
-async fn index() -> impl Responder {
-    fs::NamedFile::open_async("./static/index.html").await.unwrap()
-}
+        // Replay the moves played so far so the client can rebuild its view
+        let (moves, last_move) = move_history(&game_state.game);
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    // Initialize logger
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
-    
-    info!("Starting chess web app server at http://127.0.0.1:8080");
-    
-    // Create shared application state
-    let app_state = web::Data::new(AppState {
-        games: Mutex::new(HashMap::new()),
-        connections: Mutex::new(HashMap::new()),
-        sessions: Mutex::new(HashMap::new()),
-    });
-    
-    // Start HTTP server
-    HttpServer::new(move || {
-        App::new()
-            .app_data(app_state.clone())
-            .service(web::resource("/").to(index))
-            .service(web::resource("/ws").route(web::get().to(ws_index)))
-            .service(fs::Files::new("/static", "./static"))
-    })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
-}
+        let game_status = if game_state.white_player.is_none() || game_state.black_player.is_none() {
+            "waiting_for_opponent".to_string()
+        } else if game_state.is_paused() {
+            "paused".to_string()
+        } else {
+            get_game_status(&game_state.game, game_state.game_result)
+        };
 
-fn color_to_string(color: Color) -> String {
-    match color {
-        Color::White => "white".to_string(),
-        Color::Black => "black".to_string(),
-    }
-}
+        let rejoined_msg = ServerMessage::Rejoined {
+            game_id: game_id.clone(),
+            fen: game_state.game.current_position().to_string(),
+            color: color_to_string(player_color),
+            game_status,
+            active_color: color_to_string(game_state.game.side_to_move()),
+            clock: game_state.clock_times(self.app_state.now()),
+            resume_token,
+            moves,
+            last_move,
+            history: game_state.history.clone(),
+        };
+        self.send(ctx, &rejoined_msg);
 
-fn get_game_status(game: &Game, game_result: Option<GameResult>) -> String {
-    match game_result {
-        Some(GameResult::WhiteCheckmates) => "white_wins".to_string(),
-        Some(GameResult::BlackCheckmates) => "black_wins".to_string(),
-        Some(GameResult::WhiteResigns) => "black_wins".to_string(),
+        // In a correspondence game the opponent may have moved while the player was away
+        if let Some(opponent_moved_msg) = correspondence::opponent_moved(&game_id, game_state, player_color, self.app_state.now()) {
+            self.send(ctx, &opponent_moved_msg);
+        }
+
+        // Let the opponent know the player is back
+        let player_rejoined_msg = ServerMessage::PlayerRejoined {
+            game_id: game_id.clone(),
+            color: color_to_string(player_color),
+            // Tells the opponent a paused game is running again
+            game_status: resumed.then(|| get_game_status(&game_state.game, game_state.game_result)),
+            clock: game_state.clock_times(self.app_state.now()),
+        };
+
+        // Drop the lock before broadcasting
+        drop(games);
+
+        self.app_state.broadcast_to_game(&game_id, &player_rejoined_msg, Some(&self.id));
+
+        if resumed {
+            self.app_state.reset_clock(&game_id);
+            start_engine_move(&self.app_state, &game_id);
+        }
+    }
+
+    fn handle_watch(&mut self, game_id: String, ctx: &mut ws::WebsocketContext<Self>) {
+        if let Err(error) = validation::check_game_id(&game_id) {
+            self.send_error(ctx, error);
+            return;
+        }
+
+        info!("Player {} attempting to watch game {}", self.id, game_id);
+
+        // If the user is already in a game, remove them from that game first
+        self.leave_current_game();
+
+        let mut games = self.app_state.games.lock().unwrap();
+        let game_state = match games.get_mut(&game_id) {
+            Some(game_state) => game_state,
+            None => {
+                info!("Game {} not found for watching", game_id);
+                drop(games);
+                self.send_game_error(ctx, &game_id, ChessError::GameNotFound);
+                return;
+            }
+        };
+
+        // Spectators join the game's connections without taking a color
+        game_state.spectators.push(self.id.clone());
+        // The lobby shows how many are watching
+        self.app_state.lobby.games_changed();
+        self.game_id = game_id.clone();
+        self.color = None;
+
+        let mut connections = self.app_state.connections.lock().unwrap();
+        let connection_ids = connections.entry(game_id.clone()).or_default();
+        if !connection_ids.contains(&self.id) {
+            connection_ids.push(self.id.clone());
+        }
+        drop(connections);
+
+        let spectator_count = game_state.spectators.len();
+        info!("Player {} is watching game {} ({} spectators)", self.id, game_id, spectator_count);
+
+        let (moves, last_move) = move_history(&game_state.game);
+        let game_status = if game_state.white_player.is_none() || game_state.black_player.is_none() {
+            "waiting_for_opponent".to_string()
+        } else {
+            get_game_status(&game_state.game, game_state.game_result)
+        };
+
+        let watching_msg = ServerMessage::Watching {
+            game_id: game_id.clone(),
+            fen: game_state.game.current_position().to_string(),
+            game_status,
+            active_color: color_to_string(game_state.game.side_to_move()),
+            clock: game_state.clock_times(self.app_state.now()),
+            moves,
+            last_move,
+            spectator_count,
+            history: game_state.history.clone(),
+        };
+        self.send(ctx, &watching_msg);
+
+        // Drop the lock before broadcasting
+        drop(games);
+
+        self.broadcast_spectators(ServerMessage::SpectatorJoined { game_id, spectator_count });
+    }
+
+    // Remove this connection from the current game's spectators, returning the new count
+    fn remove_spectator(&self) -> Option<usize> {
+        let mut games = self.app_state.games.lock().unwrap();
+        let game_state = games.get_mut(&self.game_id)?;
+        let position = game_state.spectators.iter().position(|id| id == &self.id)?;
+        game_state.spectators.remove(position);
+        self.app_state.lobby.games_changed();
+        info!("Player {} stopped watching game {}", self.id, self.game_id);
+        Some(game_state.spectators.len())
+    }
+
+    // Tell everyone else in the current game that the spectator count changed
+    fn broadcast_spectators(&self, msg: ServerMessage) {
+        self.app_state.broadcast_to_game(&self.game_id, &msg, Some(&self.id));
+    }
+
+    fn send_error(&self, ctx: &mut ws::WebsocketContext<Self>, error: ChessError) {
+        self.send_error_message(ctx, error, error.message());
+    }
+
+    // Report an error with a more specific message than the code's default
+    fn send_error_message(&self, ctx: &mut ws::WebsocketContext<Self>, error: ChessError, message: &str) {
+        let game_id = (!self.game_id.is_empty()).then(|| self.game_id.clone());
+        self.send(ctx, &ServerMessage::error(error, message, game_id));
+    }
+
+    // Report an error about `game_id`, which may not be the game this connection is in
+    fn send_game_error(&self, ctx: &mut ws::WebsocketContext<Self>, game_id: &str, error: ChessError) {
+        self.send(ctx, &ServerMessage::error(error, error.message(), Some(game_id.to_string())));
+    }
+
+    // Shared checks for resign and draw messages: the sender must be seated in a running game
+    fn check_can_act(&self, game_state: &GameState) -> Result<Color, ChessError> {
+        let player_color = if game_state.white_player.as_deref() == Some(self.player_id()) {
+            Color::White
+        } else if game_state.black_player.as_deref() == Some(self.player_id()) {
+            Color::Black
+        } else {
+            return Err(ChessError::NotAPlayer);
+        };
+
+        if game_state.game_result.is_some() {
+            return Err(ChessError::GameOver);
+        }
+
+        if game_state.white_player.is_none() || game_state.black_player.is_none() {
+            return Err(ChessError::GameNotStarted);
+        }
+
+        Ok(player_color)
+    }
+
+    fn handle_resign(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.game_id.is_empty() {
+            self.send_error(ctx, ChessError::NotInGame);
+            return;
+        }
+
+        let mut games = self.app_state.games.lock().unwrap();
+        let game_state = match games.get_mut(&self.game_id) {
+            Some(game_state) => game_state,
+            None => {
+                drop(games);
+                self.send_error(ctx, ChessError::GameNotFound);
+                return;
+            }
+        };
+
+        let player_color = match self.check_can_act(game_state) {
+            Ok(color) => color,
+            Err(error) => {
+                drop(games);
+                self.send_error(ctx, error);
+                return;
+            }
+        };
+
+        info!("Player {} ({:?}) resigned game {}", self.id, player_color, self.game_id);
+        game_state.game_result = Some(match player_color {
+            Color::White => GameResult::WhiteResigns,
+            Color::Black => GameResult::BlackResigns,
+        });
+        game_state.termination = Some(Termination::Resignation);
+        game_state.draw_offer = None;
+
+        self.app_state.record_finished(&self.game_id, game_state);
+        let msg = game_over_message(&self.game_id, game_state, Some(player_color), self.app_state.now());
+
+        // Drop the lock before broadcasting
+        drop(games);
+
+        self.broadcast_to_game(&self.game_id, &msg);
+    }
+
+    fn handle_offer_draw(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.game_id.is_empty() {
+            self.send_error(ctx, ChessError::NotInGame);
+            return;
+        }
+
+        let mut games = self.app_state.games.lock().unwrap();
+        let game_state = match games.get_mut(&self.game_id) {
+            Some(game_state) => game_state,
+            None => {
+                drop(games);
+                self.send_error(ctx, ChessError::GameNotFound);
+                return;
+            }
+        };
+
+        let player_color = match self.check_can_act(game_state) {
+            Ok(color) => color,
+            Err(error) => {
+                drop(games);
+                self.send_error(ctx, error);
+                return;
+            }
+        };
+
+        // Offering a draw while the opponent's offer is pending accepts it
+        if game_state.draw_offer == Some(!player_color) {
+            drop(games);
+            self.handle_accept_draw(ctx);
+            return;
+        }
+
+        if game_state.draw_offer == Some(player_color) {
+            drop(games);
+            self.send_error(ctx, ChessError::DrawAlreadyOffered);
+            return;
+        }
+
+        info!("Player {} ({:?}) offered a draw in game {}", self.id, player_color, self.game_id);
+        game_state.draw_offer = Some(player_color);
+        drop(games);
+
+        self.broadcast_to_game(&self.game_id, &ServerMessage::DrawOffered {
+            game_id: self.game_id.clone(),
+            color: color_to_string(player_color),
+        });
+    }
+
+    fn handle_accept_draw(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.game_id.is_empty() {
+            self.send_error(ctx, ChessError::NotInGame);
+            return;
+        }
+
+        let mut games = self.app_state.games.lock().unwrap();
+        let game_state = match games.get_mut(&self.game_id) {
+            Some(game_state) => game_state,
+            None => {
+                drop(games);
+                self.send_error(ctx, ChessError::GameNotFound);
+                return;
+            }
+        };
+
+        let player_color = match self.check_can_act(game_state) {
+            Ok(color) => color,
+            Err(error) => {
+                drop(games);
+                self.send_error(ctx, error);
+                return;
+            }
+        };
+
+        if game_state.draw_offer != Some(!player_color) {
+            drop(games);
+            self.send_error_message(ctx, ChessError::NoDrawOffer, "There is no draw offer to accept");
+            return;
+        }
+
+        info!("Player {} ({:?}) accepted a draw in game {}", self.id, player_color, self.game_id);
+        game_state.game_result = Some(GameResult::DrawAccepted);
+        game_state.termination = Some(Termination::DrawAgreement);
+        game_state.draw_offer = None;
+
+        self.app_state.record_finished(&self.game_id, game_state);
+        let msg = game_over_message(&self.game_id, game_state, None, self.app_state.now());
+
+        // Drop the lock before broadcasting
+        drop(games);
+
+        self.broadcast_to_game(&self.game_id, &msg);
+    }
+
+    fn handle_decline_draw(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.game_id.is_empty() {
+            self.send_error(ctx, ChessError::NotInGame);
+            return;
+        }
+
+        let mut games = self.app_state.games.lock().unwrap();
+        let game_state = match games.get_mut(&self.game_id) {
+            Some(game_state) => game_state,
+            None => {
+                drop(games);
+                self.send_error(ctx, ChessError::GameNotFound);
+                return;
+            }
+        };
+
+        let player_color = match self.check_can_act(game_state) {
+            Ok(color) => color,
+            Err(error) => {
+                drop(games);
+                self.send_error(ctx, error);
+                return;
+            }
+        };
+
+        if game_state.draw_offer != Some(!player_color) {
+            drop(games);
+            self.send_error_message(ctx, ChessError::NoDrawOffer, "There is no draw offer to decline");
+            return;
+        }
+
+        info!("Player {} ({:?}) declined a draw in game {}", self.id, player_color, self.game_id);
+        game_state.draw_offer = None;
+        drop(games);
+
+        self.broadcast_to_game(&self.game_id, &ServerMessage::DrawDeclined {
+            game_id: self.game_id.clone(),
+            color: color_to_string(player_color),
+        });
+    }
+
+    fn handle_claim_draw(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.game_id.is_empty() {
+            self.send_error(ctx, ChessError::NotInGame);
+            return;
+        }
+
+        let mut games = self.app_state.games.lock().unwrap();
+        let game_state = match games.get_mut(&self.game_id) {
+            Some(game_state) => game_state,
+            None => {
+                drop(games);
+                self.send_error(ctx, ChessError::GameNotFound);
+                return;
+            }
+        };
+
+        let player_color = match self.check_can_act(game_state) {
+            Ok(color) => color,
+            Err(error) => {
+                drop(games);
+                self.send_error(ctx, error);
+                return;
+            }
+        };
+
+        let termination = match DrawRules::from_game(game_state.start_position, game_state.start_halfmove_clock, &game_state.game).claimable_draw() {
+            Some(termination) => termination,
+            None => {
+                drop(games);
+                self.send_error(ctx, ChessError::NoDrawToClaim);
+                return;
+            }
+        };
+
+        info!("Player {} ({:?}) claimed a draw by {} in game {}", self.id, player_color, termination.as_str(), self.game_id);
+        game_state.game_result = Some(GameResult::DrawDeclared);
+        game_state.termination = Some(termination);
+        game_state.draw_offer = None;
+
+        self.app_state.record_finished(&self.game_id, game_state);
+        let msg = game_over_message(&self.game_id, game_state, Some(player_color), self.app_state.now());
+
+        // Drop the lock before broadcasting
+        drop(games);
+
+        self.broadcast_to_game(&self.game_id, &msg);
+    }
+
+    // The game a request names, or the connection's own game; reports the error if there is neither
+    fn requested_game_id(&self, game_id: Option<String>, ctx: &mut ws::WebsocketContext<Self>) -> Option<String> {
+        let game_id = match game_id {
+            Some(game_id) => game_id,
+            None if !self.game_id.is_empty() => self.game_id.clone(),
+            None => {
+                self.send_error_message(ctx, ChessError::InvalidMessage, "Game ID is required");
+                return None;
+            }
+        };
+        if let Err(error) = validation::check_game_id(&game_id) {
+            self.send_error(ctx, error);
+            return None;
+        }
+        Some(game_id)
+    }
+
+    fn handle_get_pgn(&mut self, game_id: Option<String>, ctx: &mut ws::WebsocketContext<Self>) {
+        // Any game can be exported, not just the one this connection is in
+        let game_id = match self.requested_game_id(game_id, ctx) {
+            Some(game_id) => game_id,
+            None => return,
+        };
+
+        let pgn = match self.app_state.find_game_record(&game_id) {
+            Some(record) => pgn::game_to_pgn(&record),
+            None => {
+                self.send_game_error(ctx, &game_id, ChessError::GameNotFound);
+                return;
+            }
+        };
+
+        self.send(ctx, &ServerMessage::Pgn { game_id, pgn });
+    }
+
+    fn handle_get_history(&mut self, game_id: Option<String>, ctx: &mut ws::WebsocketContext<Self>) {
+        // Like PGN export, the history of any game can be requested
+        let game_id = match self.requested_game_id(game_id, ctx) {
+            Some(game_id) => game_id,
+            None => return,
+        };
+
+        let record = match self.app_state.find_game_record(&game_id) {
+            Some(record) => record,
+            None => {
+                self.send_game_error(ctx, &game_id, ChessError::GameNotFound);
+                return;
+            }
+        };
+
+        self.send(ctx, &ServerMessage::History {
+            game_id,
+            start_fen: record.start_fen.unwrap_or_else(|| Board::default().to_string()),
+            history: record.history,
+        });
+    }
+
+    // Whether this connection is seated in a game that has not ended
+    fn is_playing(&self) -> bool {
+        self.color.is_some()
+            && self
+                .app_state
+                .games
+                .lock()
+                .unwrap()
+                .get(&self.game_id)
+                .is_some_and(|game_state| game_state.game_result.is_none())
+    }
+
+    // Whether this player has a seat in a game still being played, which they
+    // may be asking about from another tab than the one they play in
+    fn plays_in(&self, record: &GameRecord) -> bool {
+        matches!(record.status, GameRecordStatus::WaitingForOpponent | GameRecordStatus::InProgress)
+            && record.has_player(self.player_id())
+    }
+
+    fn handle_start_analysis(&mut self, game_id: Option<String>, ctx: &mut ws::WebsocketContext<Self>) {
+        // The engine would otherwise be a way to cheat
+        if self.is_playing() {
+            self.send_error(ctx, ChessError::AnalysisNotAllowed);
+            return;
+        }
+
+        let game_id = match self.requested_game_id(game_id, ctx) {
+            Some(game_id) => game_id,
+            None => return,
+        };
+        if let Err(error) = self.run_analysis(game_id.clone(), ctx) {
+            self.send_game_error(ctx, &game_id, error);
+        }
+    }
+
+    fn stop_analysis(&mut self) {
+        if let Some(analysis) = self.analysis.take() {
+            analysis.stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // Analyse the current position of a game in the background, sending this
+    // connection an `analysis` message for every depth searched
+    fn run_analysis(&mut self, game_id: String, ctx: &mut ws::WebsocketContext<Self>) -> Result<(), ChessError> {
+        self.stop_analysis();
+
+        let record = self.app_state.find_game_record(&game_id).ok_or(ChessError::GameNotFound)?;
+        if self.plays_in(&record) {
+            return Err(ChessError::AnalysisNotAllowed);
+        }
+        let (start_position, moves) = record.replay().ok_or(ChessError::GameNotFound)?;
+        let mut game = Game::new_with_board(start_position);
+        for chess_move in &moves {
+            game.make_move(*chess_move);
+        }
+        let board = game.current_position();
+        let positions = engine::positions_played(start_position, &game);
+        let position = uci::position_command(record.start_fen.as_deref(), &game);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        self.analysis = Some(RunningAnalysis {
+            game_id: game_id.clone(),
+            stop: stop.clone(),
+        });
+
+        info!("Player {} is analysing game {}", self.id, game_id);
+        let mut updates = AnalysisUpdates {
+            addr: ctx.address(),
+            game_id,
+            board,
+            stop: stop.clone(),
+            last: None,
+        };
+        let app_state = self.app_state.clone();
+        actix::spawn(async move {
+            // A configured UCI engine analyses in place of the built-in one,
+            // which takes over if it fails
+            if let Some(uci_engine) = &app_state.uci_engine {
+                let game_id = updates.game_id.clone();
+                let search = uci_engine.search(&game_id, &position, analysis::ANALYSIS_LIMITS, None, &stop, |info| {
+                    if let Some(line) = analysis::uci_line(&board, &info) {
+                        updates.line(&line);
+                    }
+                });
+                match search.await {
+                    Ok(_) => return updates.finish(),
+                    Err(e) => warn!("UCI engine failed analysing game {}, using the built-in engine: {}", game_id, e),
+                }
+            }
+
+            let search = web::block(move || {
+                engine::analyse(&board, &positions, analysis::ANALYSIS_LIMITS, &stop, |line| updates.line(&line));
+                updates.finish();
+            });
+            if let Err(e) = search.await {
+                warn!("Analysis failed: {}", e);
+            }
+        });
+        Ok(())
+    }
+
+    fn handle_get_report(&mut self, game_id: Option<String>, ctx: &mut ws::WebsocketContext<Self>) {
+        let game_id = match self.requested_game_id(game_id, ctx) {
+            Some(game_id) => game_id,
+            None => return,
+        };
+
+        let record = match self.app_state.find_game_record(&game_id) {
+            Some(record) => record,
+            None => {
+                self.send_game_error(ctx, &game_id, ChessError::GameNotFound);
+                return;
+            }
+        };
+        if self.plays_in(&record) {
+            self.send_game_error(ctx, &game_id, ChessError::AnalysisNotAllowed);
+            return;
+        }
+        if matches!(record.status, GameRecordStatus::WaitingForOpponent | GameRecordStatus::InProgress) {
+            self.send_game_error(ctx, &game_id, ChessError::GameNotOver);
+            return;
+        }
+
+        let cached = self.app_state.reports.lock().unwrap().get(&game_id).cloned();
+        if let Some(moves) = cached {
+            self.send(ctx, &ServerMessage::Report { game_id, moves });
+            return;
+        }
+
+        let (start_position, moves) = match record.replay() {
+            Some(replay) => replay,
+            None => {
+                self.send_game_error(ctx, &game_id, ChessError::GameNotFound);
+                return;
+            }
+        };
+
+        // Searching every position takes a while, so the report is sent when it is ready
+        info!("Preparing the report for game {}", game_id);
+        let app_state = self.app_state.clone();
+        let addr = ctx.address();
+        actix::spawn(async move {
+            let moves = match web::block(move || analysis::report(start_position, &moves)).await {
+                Ok(moves) => moves,
+                Err(e) => {
+                    warn!("Report for game {} failed: {}", game_id, e);
+                    return;
+                }
+            };
+            app_state.reports.lock().unwrap().insert(game_id.clone(), moves.clone());
+            addr.do_send(ChessWebSocketMessage(ServerMessage::Report { game_id, moves }));
+        });
+    }
+
+    fn handle_seek(&mut self, time: TimeRequest, request: SeekRequest, ctx: &mut ws::WebsocketContext<Self>) {
+        if request.rated && self.user.is_none() {
+            self.send_error(ctx, ChessError::LoginRequired);
+            return;
+        }
+        if request.min_rating.zip(request.max_rating).is_some_and(|(min, max)| min > max) {
+            self.send_error_message(ctx, ChessError::InvalidMessage, "min_rating is above max_rating");
+            return;
+        }
+        let time_control = match requested_time_control(&time) {
+            Ok(time_control) => time_control,
+            Err((error, message)) => {
+                self.send_error_message(ctx, error, &message);
+                return;
+            }
+        };
+
+        // Seeking takes the player out of their current game, as creating one would
+        self.leave_current_game();
+
+        let pool = Pool::of(&time_control);
+        let seek = Seek {
+            seek_id: Uuid::new_v4().to_string(),
+            connection_id: self.id.clone(),
+            player_id: self.player_id().to_string(),
+            username: self.user.as_ref().map(|user| user.username.clone()),
+            rating: self
+                .user
+                .as_ref()
+                .map(|user| self.app_state.ratings.rating(&user.id, pool, chrono::Utc::now()).rating.round() as u32),
+            time_control,
+            rated: request.rated,
+            color_preference: request.color_preference.unwrap_or(ColorPreference::Random),
+            min_rating: request.min_rating,
+            max_rating: request.max_rating,
+            created_at: chrono::Utc::now(),
+        };
+
+        while let Some(opponent) = self.app_state.lobby.seek(seek.clone()) {
+            let addr = self.app_state.sessions.lock().unwrap().get(&opponent.connection_id).cloned();
+            match addr {
+                Some(addr) => {
+                    self.start_paired_game(seek, opponent, addr, ctx);
+                    self.app_state.broadcast_seeks();
+                    return;
+                }
+                // The opponent's connection closed just as they were paired; try the next seek
+                None => info!("Seek {} was paired after its connection closed", opponent.seek_id),
+            }
+        }
+
+        info!("Player {} is seeking a game in the lobby", self.id);
+        self.send(ctx, &ServerMessage::SeekCreated { seek_id: seek.seek_id });
+        self.app_state.broadcast_seeks();
+    }
+
+    // Withdraw this connection's seek, if it has one, and tell the lobby
+    fn cancel_seek(&self) -> Option<Seek> {
+        let seek = self.app_state.lobby.cancel(&self.id)?;
+        self.app_state.broadcast_seeks();
+        Some(seek)
+    }
+
+    // Seat this connection's player and the opponent whose seek matched theirs in a new
+    // game, with the clock running, and send both of them `joined`
+    fn start_paired_game(&mut self, seek: Seek, opponent: Seek, opponent_addr: Addr<ChessWebSocket>, ctx: &mut ws::WebsocketContext<Self>) {
+        let game_id = Uuid::new_v4().to_string();
+        let player_color = seek.color_against(&opponent);
+        let resume_token = Uuid::new_v4().to_string();
+        let opponent_resume_token = Uuid::new_v4().to_string();
+        let (white, black) = match player_color {
+            Color::White => ((&seek, &resume_token), (&opponent, &opponent_resume_token)),
+            Color::Black => ((&opponent, &opponent_resume_token), (&seek, &resume_token)),
+        };
+
+        let mut game_state = GameState {
+            white_player: Some(white.0.player_id.clone()),
+            black_player: Some(black.0.player_id.clone()),
+            white_resume_token: Some(white.1.clone()),
+            black_resume_token: Some(black.1.clone()),
+            rated: seek.rated,
+            ..GameState::new(Board::default(), None, Clock::new(seek.time_control.clone()))
+        };
+        game_state.clock.start(Color::White, self.app_state.now());
+        info!("Paired seeks {} and {} in game {}", seek.seek_id, opponent.seek_id, game_id);
+
+        self.app_state.record(GameEvent::created(&game_id, &game_state));
+        for (color, (seat, token)) in [(Color::White, white), (Color::Black, black)] {
+            self.app_state.record(GameEvent::Seated {
+                game_id: game_id.clone(),
+                color,
+                player_id: seat.player_id.clone(),
+                resume_token: token.clone(),
+                at: Some(chrono::Utc::now()),
+            });
+        }
+
+        let joined = |color: Color, resume_token: String| ServerMessage::Joined {
+            game_id: game_id.clone(),
+            fen: game_state.game.current_position().to_string(),
+            color: color_to_string(color),
+            game_status: "in_progress".to_string(),
+            active_color: color_to_string(Color::White),
+            clock: game_state.clock_times(self.app_state.now()),
+            resume_token,
+            spectator_count: 0,
+            history: Vec::new(),
+            ratings: self.app_state.player_ratings(&game_state),
+        };
+        let message = joined(player_color, resume_token);
+        let opponent_message = joined(!player_color, opponent_resume_token);
+
+        self.app_state.games.lock().unwrap().insert(game_id.clone(), game_state);
+        self.app_state.connections.lock().unwrap().insert(game_id.clone(), vec![self.id.clone()]);
+        let clock = GameClock::new(game_id.clone(), self.app_state.clone()).start();
+        self.app_state.clocks.lock().unwrap().insert(game_id.clone(), clock);
+
+        self.game_id = game_id.clone();
+        self.color = Some(player_color);
+        self.send(ctx, &message);
+        opponent_addr.do_send(Paired {
+            game_id,
+            color: !player_color,
+            joined: opponent_message,
+        });
+    }
+
+    fn handle_message(&mut self, msg: ClientMessage, ctx: &mut ws::WebsocketContext<Self>) {
+        match msg {
+            ClientMessage::Create { start_time_minutes, increment_seconds, color_preference, start_fen, engine_level, engine, time_control, days_per_move, rated } => {
+                let time = TimeRequest { start_time_minutes, increment_seconds, time_control, days_per_move };
+                let engine = EngineRequest { level: engine_level, kind: engine };
+                self.handle_create(time, color_preference, start_fen, engine, rated, ctx)
+            }
+            ClientMessage::Join { game_id } => self.handle_join(game_id, ctx),
+            ClientMessage::Move { from, to, promote_to, uci, san } => {
+                self.handle_move(MoveRequest { from, to, promote_to, uci, san }, ctx)
+            }
+            ClientMessage::GetMoves { from } => self.handle_get_moves(from, ctx),
+            ClientMessage::TimeSync { game_id } => self.handle_time_sync(game_id, ctx),
+            ClientMessage::Rejoin { game_id, resume_token } => self.handle_rejoin(game_id, resume_token, ctx),
+            ClientMessage::Watch { game_id } => self.handle_watch(game_id, ctx),
+            ClientMessage::Resign => self.handle_resign(ctx),
+            ClientMessage::OfferDraw => self.handle_offer_draw(ctx),
+            ClientMessage::AcceptDraw => self.handle_accept_draw(ctx),
+            ClientMessage::DeclineDraw => self.handle_decline_draw(ctx),
+            ClientMessage::ClaimDraw => self.handle_claim_draw(ctx),
+            ClientMessage::GetPgn { game_id } => self.handle_get_pgn(game_id, ctx),
+            ClientMessage::GetHistory { game_id } => self.handle_get_history(game_id, ctx),
+            ClientMessage::StartAnalysis { game_id } => self.handle_start_analysis(game_id, ctx),
+            ClientMessage::StopAnalysis => self.stop_analysis(),
+            ClientMessage::GetReport { game_id } => self.handle_get_report(game_id, ctx),
+            ClientMessage::Seek { start_time_minutes, increment_seconds, time_control, days_per_move, color_preference, rated, min_rating, max_rating } => {
+                let time = TimeRequest { start_time_minutes, increment_seconds, time_control, days_per_move };
+                let seek = SeekRequest { color_preference, rated, min_rating, max_rating };
+                self.handle_seek(time, seek, ctx)
+            }
+            ClientMessage::CancelSeek => {
+                if let Some(seek) = self.cancel_seek() {
+                    self.send(ctx, &ServerMessage::SeekCancelled { seek_id: seek.seek_id });
+                }
+            }
+            ClientMessage::SubscribeLobby => {
+                self.app_state.lobby.subscribe(&self.id);
+                self.send(ctx, &ServerMessage::Seeks { seeks: self.app_state.lobby.open_seeks() });
+                self.send(ctx, &ServerMessage::LobbyGames { games: lobby::list_games(&self.app_state) });
+            }
+            ClientMessage::UnsubscribeLobby => self.app_state.lobby.unsubscribe(&self.id),
+        }
+    }
+}
+
+// WebSocket connection handler, `/ws?protocol=2` selects the protocol version
+async fn ws_index(
+    req: HttpRequest,
+    stream: web::Payload,
+    query: web::Query<ConnectQuery>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    info!("New WebSocket connection request");
+    
+    let protocol = match query.protocol.map(ProtocolVersion::from_number) {
+        None => ProtocolVersion::V1,
+        Some(Some(protocol)) => protocol,
+        Some(None) => {
+            info!("Rejected unsupported protocol version {:?}", query.protocol);
+            return Ok(HttpResponse::BadRequest().body(format!(
+                "Unsupported protocol version, this server speaks 1 and {}",
+                PROTOCOL_VERSION
+            )));
+        }
+    };
+    
+    // Create a unique ID for this connection
+    let id = Uuid::new_v4().to_string();
+    info!("Generated WebSocket ID: {} ({:?})", id, protocol);
+    
+    // Browsers send the session cookie with the upgrade request, so a logged in user plays as themselves
+    let user = app_state.accounts.user_for_request(&req);
+    if let Some(user) = &user {
+        info!("WebSocket {} belongs to user {} ({})", id, user.username, user.id);
+    }
+    
+    // Anyone else is a guest, the same one in every tab of their browser
+    let guest_id = user.is_none().then(|| {
+        app_state.guests.guest_for_request(&req).unwrap_or_else(|| {
+            let guest_id = GuestSigner::new_guest();
+            info!("WebSocket {} is new guest {}", id, guest_id);
+            guest_id
+        })
+    });
+    
+    // Initialize the WebSocket actor
+    let ws = ChessWebSocket {
+        id: id.clone(),
+        user,
+        guest_id: guest_id.clone(),
+        app_state: app_state.clone(),
+        game_id: String::new(),
+        color: None,
+        protocol,
+        analysis: None,
+    };
+    
+    // Start the WebSocket actor, handing guests their cookie (again, so it does not expire)
+    let mut response = ws::start(ws, &req, stream)?;
+    if let Some(guest_id) = guest_id {
+        response.add_cookie(&app_state.guests.cookie(&guest_id))?;
+    }
+    Ok(response)
+}
+
+// HTTP handlers
+async fn index() -> impl Responder {
+    fs::NamedFile::open_async("./static/index.html").await.unwrap()
+}
+
+// Create an account and log it in
+async fn register(credentials: web::Json<Credentials>, app_state: web::Data<AppState>) -> HttpResponse {
+    // Password hashing takes a while, so it runs on the blocking thread pool
+    let accounts = app_state.clone();
+    let registered = web::block(move || accounts.accounts.register(accounts.store.as_ref(), &credentials)).await;
+    match registered {
+        Ok(Ok(user)) => {
+            info!("Registered user {} ({})", user.username, user.id);
+            let token = app_state.accounts.start_session(user.clone());
+            session_response(HttpResponse::Created(), user, token)
+        }
+        Ok(Err(error)) => account_error_response(error),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// Check a user's password and start a session
+async fn log_in(credentials: web::Json<Credentials>, app_state: web::Data<AppState>) -> HttpResponse {
+    let accounts = app_state.clone();
+    match web::block(move || accounts.accounts.log_in(&credentials)).await {
+        Ok(Ok((user, token))) => {
+            info!("User {} logged in", user.username);
+            session_response(HttpResponse::Ok(), user, token)
+        }
+        Ok(Err(error)) => account_error_response(error),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// End the request's session and clear its cookie
+async fn log_out(req: HttpRequest, app_state: web::Data<AppState>) -> HttpResponse {
+    if let Some(token) = accounts::session_token(&req) {
+        app_state.accounts.log_out(&token);
+    }
+    let mut cookie = Cookie::new(accounts::SESSION_COOKIE, "");
+    cookie.set_path("/");
+    cookie.make_removal();
+    HttpResponse::NoContent().cookie(cookie).finish()
+}
+
+// The logged in user, so the page knows who it is playing as
+async fn current_user(req: HttpRequest, app_state: web::Data<AppState>) -> HttpResponse {
+    match app_state.accounts.user_for_request(&req) {
+        Some(user) => HttpResponse::Ok().json(user),
+        None => HttpResponse::Unauthorized().body("Not logged in"),
+    }
+}
+
+// The user with their session token, which is also set as the session cookie
+fn session_response(mut response: actix_web::HttpResponseBuilder, user: User, token: String) -> HttpResponse {
+    let cookie = Cookie::build(accounts::SESSION_COOKIE, token.clone())
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(actix_web::cookie::time::Duration::days(accounts::SESSION_DAYS))
+        .finish();
+    response.cookie(cookie).json(serde_json::json!({
+        "id": user.id,
+        "username": user.username,
+        "token": token,
+    }))
+}
+
+fn account_error_response(error: accounts::AccountError) -> HttpResponse {
+    use accounts::AccountError;
+    let mut response = match error {
+        AccountError::InvalidUsername | AccountError::InvalidPassword => HttpResponse::BadRequest(),
+        AccountError::UsernameTaken => HttpResponse::Conflict(),
+        AccountError::WrongCredentials => HttpResponse::Unauthorized(),
+        AccountError::Storage => HttpResponse::InternalServerError(),
+    };
+    response.body(error.message())
+}
+
+// Download a game as PGN
+async fn game_pgn(path: web::Path<String>, app_state: web::Data<AppState>) -> HttpResponse {
+    let game_id = path.into_inner();
+    match app_state.find_game_record(&game_id) {
+        Some(record) => HttpResponse::Ok()
+            .content_type("application/x-chess-pgn")
+            .insert_header(("Content-Disposition", format!("attachment; filename=\"{}.pgn\"", game_id)))
+            .body(pgn::game_to_pgn(&record)),
+        None => HttpResponse::NotFound().body("Game not found"),
+    }
+}
+
+// Page through live and archived games, e.g. `/games?status=finished&player=...`
+async fn list_games(query: web::Query<GameQuery>, app_state: web::Data<AppState>) -> HttpResponse {
+    // Users are looked up by username; the engine and guests match by id
+    let mut query = query.into_inner();
+    if let Some(user) = query.player.as_deref().and_then(|player| app_state.accounts.user_by_username(player)) {
+        query.player = Some(user.id);
+    }
+    HttpResponse::Ok().json(query.page(app_state.all_game_records()))
+}
+
+// A single game with its full move list for replay
+async fn get_game(path: web::Path<String>, app_state: web::Data<AppState>) -> HttpResponse {
+    match app_state.find_game_record(&path.into_inner()) {
+        Some(record) => HttpResponse::Ok().json(record),
+        None => HttpResponse::NotFound().body("Game not found"),
+    }
+}
+
+// Games waiting for an opponent and games in progress, for joining or watching
+async fn api_games(app_state: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(lobby::list_games(&app_state))
+}
+
+// A user's current rating in each pool they have played in, and every change so far
+async fn user_ratings(path: web::Path<String>, app_state: web::Data<AppState>) -> HttpResponse {
+    let Some(user) = app_state.accounts.user_by_username(&path.into_inner()) else {
+        return HttpResponse::NotFound().body("User not found");
+    };
+    let ratings: HashMap<Pool, serde_json::Value> = app_state
+        .ratings
+        .ratings_of(&user.id, chrono::Utc::now())
+        .into_iter()
+        .map(|(pool, rating)| {
+            let value = serde_json::json!({
+                "rating": rating.rating.round(),
+                "deviation": rating.deviation.round(),
+                "volatility": rating.volatility,
+                "provisional": rating.is_provisional(),
+            });
+            (pool, value)
+        })
+        .collect();
+    let history = app_state.ratings.history(&user.id);
+    HttpResponse::Ok().json(serde_json::json!({
+        "user": user,
+        "ratings": ratings,
+        "history": history,
+    }))
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    // Initialize logger
+    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    
+    info!("Starting chess web app server at http://127.0.0.1:8080");
+    
+    // Games without any connected player are removed after this many seconds
+    let abandoned_game_grace_secs = std::env::var("ABANDONED_GAME_GRACE_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(300);
+    info!("Abandoned games are removed after {} seconds", abandoned_game_grace_secs);
+    
+    // Fivefold repetition and the 75-move rule end games automatically unless disabled
+    let automatic_draws = std::env::var("AUTOMATIC_DRAWS")
+        .map(|value| value != "0" && value != "false")
+        .unwrap_or(true);
+    info!("Automatic fivefold/75-move draws: {}", automatic_draws);
+    
+    // Correspondence games are checked for timeouts this often
+    let correspondence_sweep_secs = std::env::var("CORRESPONDENCE_SWEEP_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(60);
+    info!("Correspondence games are checked for timeouts every {} seconds", correspondence_sweep_secs);
+    
+    // Games are persisted under this directory and restored on startup
+    let data_dir = std::env::var("DATA_DIR").unwrap_or_else(|_| "data".to_string());
+
+    // An external UCI engine, such as Stockfish, for games created with `"engine": "uci"`
+    let uci_engine = std::env::var_os("UCI_ENGINE").map(|program| {
+        info!("Using UCI engine {:?}", program);
+        uci::UciPool::new(uci::EngineCommand { program: program.into(), args: Vec::new() })
+    });
+    let store = FileGameStore::open(std::path::Path::new(&data_dir))?;
+    let time_source: Arc<dyn TimeSource> = Arc::new(RealTime);
+    let games = storage::restore_games(store.load()?, time_source.now());
+    let accounts = Accounts::new(store.load_accounts()?);
+    let guests = GuestSigner::load_or_create(std::path::Path::new(&data_dir))?;
+    let ratings = Ratings::new(store.load_ratings()?);
+    let archive = store
+        .load_archive()?
+        .into_iter()
+        .map(|record| (record.game_id.clone(), record))
+        .collect();
+    
+    // Create shared application state
+    let app_state = web::Data::new(AppState {
+        games: Mutex::new(games),
+        connections: Mutex::new(HashMap::new()),
+        sessions: Mutex::new(HashMap::new()),
+        players: Mutex::new(HashMap::new()),
+        clocks: Mutex::new(HashMap::new()),
+        archive: Mutex::new(archive),
+        abandoned_game_grace: Duration::from_secs(abandoned_game_grace_secs),
+        removal_timers: Mutex::new(HashMap::new()),
+        automatic_draws,
+        store: Box::new(store),
+        uci_engine,
+        reports: Mutex::new(HashMap::new()),
+        time_source,
+        accounts,
+        guests,
+        ratings,
+        lobby: Lobby::default(),
+    });
+    
+    // Restored games get their clocks back (paused until the players reconnect) and,
+    // like any game without connections, are removed if nobody comes back in time
+    let restored_game_ids: Vec<String> = app_state.games.lock().unwrap().keys().cloned().collect();
+    for game_id in restored_game_ids {
+        let clock = GameClock::new(game_id.clone(), app_state.clone()).start();
+        app_state.clocks.lock().unwrap().insert(game_id.clone(), clock);
+        schedule_abandoned_game_removal(app_state.clone(), game_id);
+    }
+    correspondence::start_sweeper(app_state.clone(), Duration::from_secs(correspondence_sweep_secs));
+    lobby::start_games_updates(app_state.clone(), lobby::GAMES_UPDATE_INTERVAL);
+    
+    // Start HTTP server
+    HttpServer::new(move || {
+        App::new()
+            .app_data(app_state.clone())
+            .service(web::resource("/").to(index))
+            .service(web::resource("/ws").route(web::get().to(ws_index)))
+            .service(web::resource("/register").route(web::post().to(register)))
+            .service(web::resource("/login").route(web::post().to(log_in)))
+            .service(web::resource("/logout").route(web::post().to(log_out)))
+            .service(web::resource("/me").route(web::get().to(current_user)))
+            .service(web::resource("/users/{username}/ratings").route(web::get().to(user_ratings)))
+            .service(web::resource("/games").route(web::get().to(list_games)))
+            .service(web::resource("/games/{id}").route(web::get().to(get_game)))
+            .service(web::resource("/games/{id}/pgn").route(web::get().to(game_pgn)))
+            .service(web::resource("/api/games").route(web::get().to(api_games)))
+            .service(fs::Files::new("/static", "./static"))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+// Remove a game after the grace period unless someone has reconnected to it by then.
+// Any timer set for the game before this one no longer removes it.
+fn schedule_abandoned_game_removal(app_state: web::Data<AppState>, game_id: String) {
+    let timer = {
+        let mut removal_timers = app_state.removal_timers.lock().unwrap();
+        let timer = removal_timers.entry(game_id.clone()).or_default();
+        *timer += 1;
+        *timer
+    };
+    actix::spawn(async move {
+        actix::clock::sleep(app_state.abandoned_game_grace).await;
+        app_state.remove_abandoned_game(&game_id, timer);
+    });
+}
+
+// Play a move for `player_id` and tell everyone in the game about it.
+// Moves from connections and from the built-in engine both go through here.
+fn play_move(
+    app_state: &web::Data<AppState>,
+    game_id: &str,
+    player_id: &str,
+    requested: RequestedMove,
+) -> Result<(), (ChessError, String)> {
+    let mut games = app_state.games.lock().unwrap();
+
+    if let Some(game_state) = games.get_mut(game_id) {
+        // A game restored after a restart waits for both players before play continues
+        if game_state.is_paused() {
+            return Err(default_error(ChessError::GamePaused));
+        }
+
+        // Check if the game has already ended due to timeout or other reasons
+        if game_state.game_result.is_some() {
+            return Err(default_error(ChessError::GameOver));
+        }
+
+        // A player whose time ran out before the clock noticed loses on time instead of getting the move
+        let now = app_state.now();
+        if let Some(color) = game_state.clock.flagged(now) {
+            clock::flag_player(game_state, color);
+            app_state.record_finished(game_id, game_state);
+            let msg = game_over_message(game_id, game_state, Some(color), now);
+
+            // Drop the lock before broadcasting
+            drop(games);
+
+            app_state.broadcast_to_game(game_id, &msg, None);
+            return Err(default_error(ChessError::GameOver));
+        }
+
+        // Check if it's the player's turn
+        let game = &mut game_state.game;
+        let current_turn = game.side_to_move();
+        let player_color = if game_state.white_player.as_deref() == Some(player_id) {
+            Some(Color::White)
+        } else if game_state.black_player.as_deref() == Some(player_id) {
+            Some(Color::Black)
+        } else {
+            None
+        };
+
+        if player_color != Some(current_turn) {
+            return Err(default_error(ChessError::NotYourTurn));
+        }
+
+        let board = game.current_position();
+        let chess_move = match requested {
+            RequestedMove::Move(chess_move) => chess_move,
+            RequestedMove::San(san) => match pgn::parse_san(&board, &san) {
+                Ok(chess_move) => chess_move,
+                Err(error) => return Err(san_error(&san, error)),
+            },
+        };
+
+        // Check if the piece belongs to the player
+        if let Some(_piece) = board.piece_on(chess_move.get_source()) {
+            // Try to make the move
+            if game.make_move(chess_move) {
+                // Charge the mover for the move and start the opponent's clock
+                if let Err(color) = game_state.clock.punch(now) {
+                    clock::flag_player(game_state, color);
+                }
+
+                // A move by the opponent of whoever offered a draw declines the offer
+                if let Some(mover) = player_color {
+                    if game_state.draw_offer == Some(!mover) {
+                        info!("Draw offer in game {} expired", game_id);
+                        game_state.draw_offer = None;
+                    }
+                }
+
+                // Record checkmate and stalemate so the finished game is locked
+                if game_state.game_result.is_none() {
+                    if let Some(result) = game_state.game.result() {
+                        game_state.game_result = Some(result);
+                        game_state.termination = Some(if result == GameResult::Stalemate {
+                            Termination::Stalemate
+                        } else {
+                            Termination::Checkmate
+                        });
+                    }
+                }
+
+                // Repetition and fifty-move rule draws
+                let mut draw_claim = None;
+                if game_state.game_result.is_none() {
+                    let draw_rules = DrawRules::from_game(game_state.start_position, game_state.start_halfmove_clock, &game_state.game);
+                    match draw_rules.automatic_draw() {
+                        Some(termination) if app_state.automatic_draws => {
+                            info!("Game {} drawn by {}", game_id, termination.as_str());
+                            game_state.game_result = Some(GameResult::DrawDeclared);
+                            game_state.termination = Some(termination);
+                        }
+                        _ => draw_claim = draw_rules.claimable_draw(),
+                    }
+                }
+
+                let entry = game_state.push_history(&board, chess_move, chrono::Utc::now()).clone();
+                app_state.record(GameEvent::Moved {
+                    game_id: game_id.to_string(),
+                    uci: entry.uci.clone(),
+                    white_time_ms: entry.white_time_ms,
+                    black_time_ms: entry.black_time_ms,
+                    at: entry.played_at,
+                });
+                app_state.record_finished(game_id, game_state);
+
+                // Update the active player
+                let game = &game_state.game;
+                game_state.active_player = Some(game.side_to_move());
+
+                // Log the active player for debugging
+                info!("Active player after move: {:?}", game_state.active_player);
+
+                // Create the message to broadcast
+                let msg = ServerMessage::MoveMade {
+                    game_id: game_id.to_string(),
+                    fen: game.current_position().to_string(),
+                    last_move: LastMove {
+                        from: chess_move.get_source().to_string(),
+                        to: chess_move.get_dest().to_string(),
+                    },
+                    san: entry.san,
+                    uci: entry.uci,
+                    game_status: get_game_status(game, game_state.game_result),
+                    clock: game_state.clock_times(now),
+                    termination: game_state.termination.map(|termination| termination.as_str().to_string()),
+                    draw_claim: draw_claim.map(|termination| termination.as_str().to_string()),
+                };
+
+                // Drop the lock before broadcasting
+                drop(games);
+
+                app_state.broadcast_to_game(game_id, &msg, None);
+                app_state.reset_clock(game_id);
+                start_engine_move(app_state, game_id);
+                Ok(())
+            } else {
+                // Move was invalid
+                Err(default_error(ChessError::IllegalMove))
+            }
+        } else {
+            Err(default_error(ChessError::NoPiece))
+        }
+    } else {
+        Err(default_error(ChessError::GameNotFound))
+    }
+}
+
+// Let the engine work out its move in the background if it is to move
+fn start_engine_move(app_state: &web::Data<AppState>, game_id: &str) {
+    let (board, positions, position, limits, level, engine_kind) = {
+        let games = app_state.games.lock().unwrap();
+        let game_state = match games.get(game_id) {
+            Some(game_state) => game_state,
+            None => return,
+        };
+        let level = match game_state.engine_level {
+            Some(level) => level,
+            None => return,
+        };
+
+        let side_to_move = game_state.game.side_to_move();
+        if game_state.game_result.is_some() || game_state.is_paused() || game_state.engine_color() != Some(side_to_move) {
+            return;
+        }
+
+        let time_left_ms = game_state.clock.time_left_ms(side_to_move, app_state.now());
+        (
+            game_state.game.current_position(),
+            engine::positions_played(game_state.start_position, &game_state.game),
+            uci::position_command(game_state.start_fen.as_deref(), &game_state.game),
+            engine::SearchLimits::for_level(level, time_left_ms, game_state.clock.increment_ms(side_to_move)),
+            level,
+            game_state.engine_kind,
+        )
+    };
+
+    info!("Engine is thinking in game {} with {:?}", game_id, limits);
+    let app_state = app_state.clone();
+    let game_id = game_id.to_string();
+    actix::spawn(async move {
+        // A UCI engine that crashes, hangs or answers with an illegal move is
+        // replaced by the built-in one for this move, so the game carries on
+        let uci_move = match (engine_kind, &app_state.uci_engine) {
+            (EngineKind::Uci, Some(uci_engine)) => {
+                match uci_engine.search(&game_id, &position, limits, Some(level), &AtomicBool::new(false), |_| {}).await {
+                    Ok(None) => return,
+                    Ok(Some(chess_move)) if board.legal(chess_move) => Some(chess_move),
+                    Ok(Some(chess_move)) => {
+                        warn!("UCI engine played the illegal move {} in game {}", chess_move, game_id);
+                        None
+                    }
+                    Err(e) => {
+                        warn!("UCI engine failed in game {}, using the built-in engine: {}", game_id, e);
+                        None
+                    }
+                }
+            }
+            (EngineKind::Uci, None) => {
+                warn!("Game {} was created with a UCI engine, but none is configured", game_id);
+                None
+            }
+            (EngineKind::Builtin, _) => None,
+        };
+
+        let chess_move = match uci_move {
+            Some(chess_move) => chess_move,
+            None => match web::block(move || engine::best_move(&board, &positions, limits)).await {
+                Ok(Some(chess_move)) => chess_move,
+                Ok(None) => return,
+                Err(e) => {
+                    warn!("Engine search for game {} failed: {}", game_id, e);
+                    return;
+                }
+            },
+        };
+
+        info!("Engine plays {} in game {}", chess_move, game_id);
+        // The game may have ended by resignation or on time while the engine was thinking
+        if let Err((error, message)) = play_move(&app_state, &game_id, engine::ENGINE_PLAYER_ID, RequestedMove::Move(chess_move)) {
+            info!("Engine move {} in game {} was refused ({}): {}", chess_move, game_id, error, message);
+        }
+    });
+}
+
+// The error code with its default message
+fn default_error(error: ChessError) -> (ChessError, String) {
+    (error, error.message().to_string())
+}
+
+// Time fields of a `create` or `seek` message; days per move or a time control with stages replace the base time and increment
+struct TimeRequest {
+    start_time_minutes: Option<u64>,
+    increment_seconds: Option<u64>,
+    time_control: Option<TimeControlRequest>,
+    days_per_move: Option<u32>,
+}
+
+// The time control a `create` or `seek` asks for; days per move or stages replace the base
+// time and increment, and games last 15 minutes with 10 seconds a move if nothing is given
+fn requested_time_control(time: &TimeRequest) -> Result<TimeControl, (ChessError, String)> {
+    match (time.days_per_move, &time.time_control) {
+        (Some(days_per_move), _) => validation::correspondence_time_control(days_per_move).map_err(|error| {
+            let message = format!("Correspondence games have 1 to {} days per move", validation::MAX_DAYS_PER_MOVE);
+            (error, message)
+        }),
+        (None, Some(request)) => validation::staged_time_control(request).map_err(|error| {
+            let message = format!(
+                "A time control has 1 to {} stages of 1 to {} moves each, except the last which may last the rest of the game, \
+                 with at most {} minutes and an increment or delay of at most {} seconds per stage",
+                validation::MAX_STAGES,
+                validation::MAX_STAGE_MOVES,
+                validation::MAX_START_TIME_MINUTES,
+                validation::MAX_INCREMENT_SECONDS
+            );
+            (error, message)
+        }),
+        (None, None) => validation::time_control(time.start_time_minutes.unwrap_or(15), time.increment_seconds.unwrap_or(10))
+            .map_err(|error| {
+                let message = format!(
+                    "Games last between 1 and {} minutes with an increment of at most {} seconds",
+                    validation::MAX_START_TIME_MINUTES,
+                    validation::MAX_INCREMENT_SECONDS
+                );
+                (error, message)
+            }),
+    }
+}
+
+// Fields of a `seek` message other than the time control
+struct SeekRequest {
+    color_preference: Option<ColorPreference>,
+    rated: bool,
+    min_rating: Option<u32>,
+    max_rating: Option<u32>,
+}
+
+// Engine fields of a `create` message; the kind is only given with a level
+struct EngineRequest {
+    level: Option<u8>,
+    kind: Option<EngineKind>,
+}
+
+// Fields of a `move` message; exactly one way of giving the move is used
+struct MoveRequest {
+    from: Option<String>,
+    to: Option<String>,
+    promote_to: Option<String>,
+    uci: Option<String>,
+    san: Option<String>,
+}
+
+// A move as far as it can be read without the board
+enum RequestedMove {
+    Move(ChessMove),
+    San(String),
+}
+
+fn requested_move(request: MoveRequest) -> Result<RequestedMove, (ChessError, String)> {
+    match request {
+        MoveRequest { from: Some(from), to: Some(to), promote_to, uci: None, san: None } => {
+            let (from_square, to_square) = match (validation::parse_square(&from), validation::parse_square(&to)) {
+                (Ok(from_square), Ok(to_square)) => (from_square, to_square),
+                (Err(error), _) | (_, Err(error)) => {
+                    return Err((error, format!("Invalid move from {:?} to {:?}", from, to)));
+                }
+            };
+            let promotion_piece = validation::parse_promotion(promote_to.as_deref())
+                .map_err(|error| (error, "Pawns promote to a queen, rook, bishop or knight".to_string()))?;
+            Ok(RequestedMove::Move(ChessMove::new(from_square, to_square, promotion_piece)))
+        }
+        MoveRequest { from: None, to: None, promote_to: None, uci: Some(uci), san: None } => validation::parse_uci(&uci)
+            .map(RequestedMove::Move)
+            .map_err(|error| (error, format!("{:?} is not a UCI move", uci))),
+        MoveRequest { from: None, to: None, promote_to: None, uci: None, san: Some(san) } => Ok(RequestedMove::San(san)),
+        _ => Err((
+            ChessError::InvalidMessage,
+            "A move needs either from and to (with an optional promote_to), uci or san".to_string(),
+        )),
+    }
+}
+
+// The error code and message for a SAN move that does not fit the position
+fn san_error(san: &str, error: pgn::SanError) -> (ChessError, String) {
+    match error {
+        pgn::SanError::Invalid => (ChessError::InvalidNotation, format!("{:?} is not a SAN move", san)),
+        pgn::SanError::Illegal => (ChessError::IllegalMove, format!("{} is not a legal move", san)),
+        pgn::SanError::MissingPromotion => (
+            ChessError::InvalidPromotion,
+            format!("{} promotes a pawn; say which piece, e.g. {}=Q", san, san),
+        ),
+        pgn::SanError::Ambiguous(candidates) => (
+            ChessError::AmbiguousMove,
+            format!("{} is ambiguous: it could be {}", san, candidates.join(" or ")),
+        ),
+    }
+}
+
+// Parse a custom starting position and its halfmove clock, rejecting boards that are not legal chess positions
+fn parse_start_position(fen: &str) -> Result<(Board, u32), String> {
+    validation::check_fen_layout(fen).map_err(|error| format!("Invalid starting position: {}", error))?;
+    
+    let builder = chess::BoardBuilder::from_str(fen)
+        .map_err(|_| format!("Invalid starting position: {} is not a valid FEN", fen))?;
+    
+    // Board::try_from looks up both kings before its own sanity checks, so check them first
+    for color in [Color::White, Color::Black] {
+        let kings = chess::ALL_SQUARES
+            .iter()
+            .filter(|square| builder[**square] == Some((Piece::King, color)))
+            .count();
+        if kings != 1 {
+            return Err(format!("Invalid starting position: {} must have exactly one king", color_to_string(color)));
+        }
+    }
+    
+    // Pawns can never stand on the first or last rank
+    let back_rank_pawn = chess::ALL_SQUARES.iter().any(|square| {
+        matches!(builder[*square], Some((Piece::Pawn, _)))
+            && (square.get_rank() == chess::Rank::First || square.get_rank() == chess::Rank::Eighth)
+    });
+    if back_rank_pawn {
+        return Err("Invalid starting position: pawns cannot be on the first or last rank".to_string());
+    }
+    
+    let board = Board::try_from(&builder).map_err(|_| {
+        "Invalid starting position: the side not to move is in check, or castling or en passant rights do not match the board".to_string()
+    })?;
+    
+    if board.status() != chess::BoardStatus::Ongoing {
+        return Err("Invalid starting position: the side to move has no legal moves".to_string());
+    }
+    
+    Ok((board, validation::fen_halfmove_clock(fen)))
+}
+
+fn color_to_string(color: Color) -> String {
+    match color {
+        Color::White => "white".to_string(),
+        Color::Black => "black".to_string(),
+    }
+}
+
+fn get_game_status(game: &Game, game_result: Option<GameResult>) -> String {
+    match game_result {
+        Some(GameResult::WhiteCheckmates) => "white_wins".to_string(),
+        Some(GameResult::BlackCheckmates) => "black_wins".to_string(),
+        Some(GameResult::WhiteResigns) => "black_wins".to_string(),
         Some(GameResult::BlackResigns) => "white_wins".to_string(),
         Some(GameResult::Stalemate) => "draw".to_string(),
         Some(GameResult::DrawAccepted) => "draw".to_string(),
@@ -1144,6 +2699,35 @@ fn get_game_status(game: &Game, game_result: Option<GameResult>) -> String {
     }
 }
 
+// UCI notation of every move played so far, plus the last one for highlighting
+fn move_history(game: &Game) -> (Vec<String>, Option<LastMove>) {
+    let mut moves = Vec::new();
+    let mut last_move = None;
+    for action in game.actions() {
+        if let chess::Action::MakeMove(chess_move) = action {
+            moves.push(chess_move.to_string());
+            last_move = Some(LastMove {
+                from: chess_move.get_source().to_string(),
+                to: chess_move.get_dest().to_string(),
+            });
+        }
+    }
+    (moves, last_move)
+}
+
+// Final position, clocks and result sent to everyone when a game ends
+fn game_over_message(game_id: &str, game_state: &GameState, color: Option<Color>, now: std::time::Instant) -> ServerMessage {
+    ServerMessage::GameOver {
+        game_id: game_id.to_string(),
+        fen: game_state.game.current_position().to_string(),
+        color: color.map(color_to_string),
+        game_status: get_game_status(&game_state.game, game_state.game_result),
+        active_color: color_to_string(game_state.game.side_to_move()),
+        clock: game_state.clock_times(now),
+        termination: game_state.termination.map(|termination| termination.as_str().to_string()),
+    }
+}
+
 fn has_insufficient_material(board: &chess::Board) -> bool {
     let mut white_pawns = 0;
     let mut white_knights = 0;
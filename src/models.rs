@@ -1,2 +0,0 @@
-// This file is intentionally empty for now.
-// We're using the structs defined in main.rs, but we can move them here later for better organization.
@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Why a client message was refused.
+///
+/// Sent as the `code` of an `error` message. The codes are stable so clients
+/// can react to them; the accompanying `message` is for humans and may change.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChessError {
+    /// The message was not valid JSON
+    InvalidJson,
+    /// The message `type` is not one the server knows
+    UnknownMessageType,
+    /// A known message with missing or malformed fields
+    InvalidMessage,
+    /// Only text frames are accepted
+    UnsupportedFrame,
+    /// A game ID that is not a UUID
+    InvalidGameId,
+    GameNotFound,
+    GameFull,
+    /// The connection has not created, joined or started watching a game
+    NotInGame,
+    /// Spectators cannot move, resign or offer draws
+    NotAPlayer,
+    /// Both seats must be taken first
+    GameNotStarted,
+    /// A restored game waits for both players to reconnect
+    GamePaused,
+    GameOver,
+    /// Post-game reports wait for the game to end
+    GameNotOver,
+    /// Players cannot use the engine while their game is in progress
+    AnalysisNotAllowed,
+    NotYourTurn,
+    NotYourPiece,
+    /// The square to move from is empty
+    NoPiece,
+    InvalidSquare,
+    /// Pawns promote to a queen, rook, bishop or knight
+    InvalidPromotion,
+    IllegalMove,
+    /// A move in UCI or SAN that cannot be read
+    InvalidNotation,
+    /// A SAN move that more than one piece could make
+    AmbiguousMove,
+    /// A custom starting position that is not a legal chess position
+    InvalidPosition,
+    /// Base time or increment out of range
+    InvalidTimeControl,
+    /// Engine strength outside the supported levels
+    InvalidEngineLevel,
+    /// The server has no UCI engine configured
+    EngineUnavailable,
+    /// Rated games are only for logged in users
+    LoginRequired,
+    InvalidResumeToken,
+    DrawAlreadyOffered,
+    NoDrawOffer,
+    NoDrawToClaim,
+}
+
+impl ChessError {
+    /// Default human readable description
+    pub fn message(self) -> &'static str {
+        match self {
+            ChessError::InvalidJson => "Invalid JSON",
+            ChessError::UnknownMessageType => "Unknown message type",
+            ChessError::InvalidMessage => "Invalid message",
+            ChessError::UnsupportedFrame => "Binary messages are not supported",
+            ChessError::InvalidGameId => "Invalid game ID",
+            ChessError::GameNotFound => "Game not found",
+            ChessError::GameFull => "Game is full",
+            ChessError::NotInGame => "You are not in a game",
+            ChessError::NotAPlayer => "Only players can do that",
+            ChessError::GameNotStarted => "Game has not started yet",
+            ChessError::GamePaused => "The game is paused until both players reconnect",
+            ChessError::GameOver => "Game has already ended",
+            ChessError::GameNotOver => "Game has not ended yet",
+            ChessError::AnalysisNotAllowed => "Analysis is not available while you are playing",
+            ChessError::NotYourTurn => "It's not your turn",
+            ChessError::NotYourPiece => "Not your piece",
+            ChessError::NoPiece => "No piece at that square",
+            ChessError::InvalidSquare => "Invalid square",
+            ChessError::InvalidPromotion => "Invalid promotion piece",
+            ChessError::IllegalMove => "Illegal move",
+            ChessError::InvalidNotation => "Invalid move notation",
+            ChessError::AmbiguousMove => "Ambiguous move",
+            ChessError::InvalidPosition => "Invalid starting position",
+            ChessError::InvalidTimeControl => "Invalid time control",
+            ChessError::InvalidEngineLevel => "Invalid engine level",
+            ChessError::EngineUnavailable => "No UCI engine is available",
+            ChessError::LoginRequired => "Log in to play rated games",
+            ChessError::InvalidResumeToken => "Invalid resume token",
+            ChessError::DrawAlreadyOffered => "You already offered a draw",
+            ChessError::NoDrawOffer => "There is no draw offer",
+            ChessError::NoDrawToClaim => "No draw can be claimed in this position",
+        }
+    }
+}
+
+impl fmt::Display for ChessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
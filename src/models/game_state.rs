@@ -1,15 +1,154 @@
-use chess::{Game, Color, GameResult};
+use chess::{Board, ChessMove, Game, Color, GameResult};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use std::time::Instant;
 
+use super::messages::{ClockTimes, EngineKind, HistoryEntry};
+use crate::time_control::Clock;
+use crate::{engine, pgn};
+
 /// Game state for a specific game
 pub struct GameState {
     pub game: Game,
+    /// Board the game was created with, needed to replay its moves
+    pub start_position: Board,
+    /// FEN the game was created from, if not the standard starting position
+    pub start_fen: Option<String>,
+    /// Halfmove clock in that FEN, which the fifty and 75-move rules count on from
+    pub start_halfmove_clock: u32,
     pub white_player: Option<String>,
     pub black_player: Option<String>,
-    pub white_time_ms: u64,
-    pub black_time_ms: u64,
-    pub increment_ms: u64,
-    pub last_move_time: Option<Instant>,
+    /// Both players' time, stopped until the game starts
+    pub clock: Clock,
     pub active_player: Option<Color>,
     pub game_result: Option<GameResult>,
+    /// Secret that lets the white player reclaim their seat after a dropped connection
+    pub white_resume_token: Option<String>,
+    /// Secret that lets the black player reclaim their seat after a dropped connection
+    pub black_resume_token: Option<String>,
+    /// Connections watching the game without a color
+    pub spectators: Vec<String>,
+    /// Why the game ended, next to `game_result` which only says who won
+    pub termination: Option<Termination>,
+    /// Player with a pending draw offer
+    pub draw_offer: Option<Color>,
+    pub created_at: DateTime<Utc>,
+    /// Every move played so far, oldest first
+    pub history: Vec<HistoryEntry>,
+    /// Strength of the engine when it plays one side
+    pub engine_level: Option<u8>,
+    /// Which engine that is
+    pub engine_kind: EngineKind,
+    /// Whether the result changes the players' ratings
+    pub rated: bool,
+}
+
+impl GameState {
+    /// A game nobody is seated in yet, against no engine and unrated
+    pub fn new(start_position: Board, start_fen: Option<String>, clock: Clock) -> Self {
+        GameState {
+            game: Game::new_with_board(start_position),
+            start_position,
+            start_fen,
+            start_halfmove_clock: 0,
+            white_player: None,
+            black_player: None,
+            clock,
+            // The side to move in the starting position is the one whose clock starts
+            active_player: Some(start_position.side_to_move()),
+            game_result: None,
+            white_resume_token: None,
+            black_resume_token: None,
+            spectators: Vec::new(),
+            termination: None,
+            draw_offer: None,
+            created_at: Utc::now(),
+            history: Vec::new(),
+            engine_level: None,
+            engine_kind: EngineKind::default(),
+            rated: false,
+        }
+    }
+
+    /// Both seats are taken but the clock is stopped, which only happens to a
+    /// game restored after a restart until both players have reconnected
+    pub fn is_paused(&self) -> bool {
+        self.white_player.is_some()
+            && self.black_player.is_some()
+            && !self.clock.is_running()
+            && self.game_result.is_none()
+    }
+
+    /// Days-per-move games, which carry on while nobody is connected
+    pub fn is_correspondence(&self) -> bool {
+        self.clock.control().is_correspondence()
+    }
+
+    /// Side the engine plays, if it is seated
+    pub fn engine_color(&self) -> Option<Color> {
+        if self.white_player.as_deref() == Some(engine::ENGINE_PLAYER_ID) {
+            Some(Color::White)
+        } else if self.black_player.as_deref() == Some(engine::ENGINE_PLAYER_ID) {
+            Some(Color::Black)
+        } else {
+            None
+        }
+    }
+
+    /// Add a move that was just made to the history. `before` is the position
+    /// it was played in, and the clocks must already include the move.
+    pub fn push_history(&mut self, before: &Board, chess_move: ChessMove, played_at: DateTime<Utc>) -> &HistoryEntry {
+        self.history.push(HistoryEntry {
+            san: pgn::san(before, chess_move),
+            uci: chess_move.to_string(),
+            fen: self.game.current_position().to_string(),
+            white_time_ms: self.clock.time_ms(Color::White),
+            black_time_ms: self.clock.time_ms(Color::Black),
+            played_at,
+        });
+        &self.history[self.history.len() - 1]
+    }
+
+    /// Both clocks at `now` and the side to move's increment (or delay), as sent to clients
+    pub fn clock_times(&self, now: Instant) -> ClockTimes {
+        ClockTimes {
+            white_time_ms: self.clock.time_left_ms(Color::White, now),
+            black_time_ms: self.clock.time_left_ms(Color::Black, now),
+            increment_ms: self.clock.increment_ms(self.game.side_to_move()),
+        }
+    }
+}
+
+/// Reason a game ended.
+///
+/// `GameResult` can only express checkmate, resignation and draws, so timeouts
+/// are recorded with the matching resignation or draw result and this reason.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Termination {
+    Checkmate,
+    Stalemate,
+    Timeout,
+    Resignation,
+    DrawAgreement,
+    ThreefoldRepetition,
+    FivefoldRepetition,
+    FiftyMoveRule,
+    SeventyFiveMoveRule,
+}
+
+impl Termination {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Termination::Checkmate => "checkmate",
+            Termination::Stalemate => "stalemate",
+            Termination::Timeout => "timeout",
+            Termination::Resignation => "resignation",
+            Termination::DrawAgreement => "draw_agreement",
+            Termination::ThreefoldRepetition => "threefold_repetition",
+            Termination::FivefoldRepetition => "fivefold_repetition",
+            Termination::FiftyMoveRule => "fifty_move_rule",
+            Termination::SeventyFiveMoveRule => "seventy_five_move_rule",
+        }
+    }
 }
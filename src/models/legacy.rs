@@ -0,0 +1,367 @@
+//! Protocol version 1: flat JSON objects keyed by `message_type`.
+//!
+//! Kept so clients written against the original protocol keep working.
+//! Incoming messages are converted to `ClientMessage` and outgoing
+//! `ServerMessage`s are flattened back into the old shape.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::lobby::{LiveGame, Seek, WaitingGame};
+use super::errors::ChessError;
+use super::messages::{ClientMessage, ColorPreference, EngineKind, Evaluation, HistoryEntry, LastMove, MoveReport, PlayerRatings, ServerMessage, TimeControlRequest};
+
+/// Message sent from a version 1 client
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LegacyClientMessage {
+    pub message_type: String,
+    pub game_id: Option<String>,
+    pub move_from: Option<String>,
+    pub move_to: Option<String>,
+    pub color_preference: Option<String>,
+    pub start_time_minutes: Option<u64>,
+    pub increment_seconds: Option<u64>,
+    pub promote_to: Option<String>,
+    pub uci: Option<String>,
+    pub san: Option<String>,
+    pub resume_token: Option<String>,
+    pub start_fen: Option<String>,
+    pub engine_level: Option<u8>,
+    pub engine: Option<String>,
+    pub time_control: Option<TimeControlRequest>,
+    pub days_per_move: Option<u32>,
+    pub rated: Option<bool>,
+    pub min_rating: Option<u32>,
+    pub max_rating: Option<u32>,
+}
+
+impl TryFrom<LegacyClientMessage> for ClientMessage {
+    type Error = String;
+
+    fn try_from(msg: LegacyClientMessage) -> Result<Self, Self::Error> {
+        let required = |field: Option<String>, name: &str| {
+            field.ok_or_else(|| format!("{} requires {}", msg.message_type, name))
+        };
+
+        let color_preference = || match msg.color_preference.as_deref() {
+            None => Ok(None),
+            Some("white") => Ok(Some(ColorPreference::White)),
+            Some("black") => Ok(Some(ColorPreference::Black)),
+            Some("random") => Ok(Some(ColorPreference::Random)),
+            Some(_) => Err("color_preference must be white, black or random".to_string()),
+        };
+
+        Ok(match msg.message_type.as_str() {
+            "create" => ClientMessage::Create {
+                start_time_minutes: msg.start_time_minutes,
+                increment_seconds: msg.increment_seconds,
+                color_preference: color_preference()?,
+                start_fen: msg.start_fen.clone(),
+                engine_level: msg.engine_level,
+                engine: match msg.engine.as_deref() {
+                    None => None,
+                    Some("builtin") => Some(EngineKind::Builtin),
+                    Some("uci") => Some(EngineKind::Uci),
+                    Some(_) => return Err("engine must be builtin or uci".to_string()),
+                },
+                time_control: msg.time_control.clone(),
+                days_per_move: msg.days_per_move,
+                rated: msg.rated.unwrap_or(false),
+            },
+            "join" => ClientMessage::Join {
+                game_id: required(msg.game_id.clone(), "game_id")?,
+            },
+            "move" => ClientMessage::Move {
+                from: msg.move_from.clone(),
+                to: msg.move_to.clone(),
+                promote_to: msg.promote_to.clone(),
+                uci: msg.uci.clone(),
+                san: msg.san.clone(),
+            },
+            "get_moves" => ClientMessage::GetMoves {
+                from: required(msg.move_from.clone(), "move_from")?,
+            },
+            "time_sync" => ClientMessage::TimeSync {
+                game_id: msg.game_id.clone(),
+            },
+            "rejoin" => ClientMessage::Rejoin {
+                game_id: required(msg.game_id.clone(), "game_id")?,
+                resume_token: required(msg.resume_token.clone(), "resume_token")?,
+            },
+            "watch" => ClientMessage::Watch {
+                game_id: required(msg.game_id.clone(), "game_id")?,
+            },
+            "resign" => ClientMessage::Resign,
+            "offer_draw" => ClientMessage::OfferDraw,
+            "accept_draw" => ClientMessage::AcceptDraw,
+            "decline_draw" => ClientMessage::DeclineDraw,
+            "claim_draw" => ClientMessage::ClaimDraw,
+            "get_pgn" => ClientMessage::GetPgn {
+                game_id: msg.game_id.clone(),
+            },
+            "get_history" => ClientMessage::GetHistory {
+                game_id: msg.game_id.clone(),
+            },
+            "start_analysis" => ClientMessage::StartAnalysis {
+                game_id: msg.game_id.clone(),
+            },
+            "stop_analysis" => ClientMessage::StopAnalysis,
+            "get_report" => ClientMessage::GetReport {
+                game_id: msg.game_id.clone(),
+            },
+            "seek" => ClientMessage::Seek {
+                start_time_minutes: msg.start_time_minutes,
+                increment_seconds: msg.increment_seconds,
+                time_control: msg.time_control.clone(),
+                days_per_move: msg.days_per_move,
+                color_preference: color_preference()?,
+                rated: msg.rated.unwrap_or(false),
+                min_rating: msg.min_rating,
+                max_rating: msg.max_rating,
+            },
+            "cancel_seek" => ClientMessage::CancelSeek,
+            "subscribe_lobby" => ClientMessage::SubscribeLobby,
+            "unsubscribe_lobby" => ClientMessage::UnsubscribeLobby,
+            other => return Err(format!("Unknown message type: {}", other)),
+        })
+    }
+}
+
+/// Message sent to a version 1 client
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LegacyServerMessage {
+    pub message_type: String,
+    pub game_id: Option<String>,
+    pub fen: Option<String>,
+    pub color: Option<String>,
+    pub error: Option<String>,
+    /// Machine readable kind of `error`
+    pub code: Option<ChessError>,
+    pub available_moves: Option<Vec<String>>,
+    pub last_move: Option<LastMove>,
+    pub san: Option<String>,
+    pub uci: Option<String>,
+    pub game_status: Option<String>,
+    pub white_time_ms: Option<u64>,
+    pub black_time_ms: Option<u64>,
+    pub increment_ms: Option<u64>,
+    pub active_color: Option<String>,
+    pub resume_token: Option<String>,
+    pub moves: Option<Vec<String>>,
+    pub spectator_count: Option<usize>,
+    pub termination: Option<String>,
+    pub draw_claim: Option<String>,
+    pub pgn: Option<String>,
+    pub start_fen: Option<String>,
+    pub history: Option<Vec<HistoryEntry>>,
+    pub depth: Option<u8>,
+    pub evaluation: Option<Evaluation>,
+    pub pv: Option<Vec<String>>,
+    pub done: Option<bool>,
+    pub report: Option<Vec<MoveReport>>,
+    pub played_at: Option<DateTime<Utc>>,
+    pub move_deadline: Option<DateTime<Utc>>,
+    pub rated: Option<bool>,
+    pub white_rating: Option<u32>,
+    pub black_rating: Option<u32>,
+    pub seek_id: Option<String>,
+    pub seeks: Option<Vec<Seek>>,
+    pub waiting: Option<Vec<WaitingGame>>,
+    pub in_progress: Option<Vec<LiveGame>>,
+}
+
+impl LegacyServerMessage {
+    fn new(message_type: &str, game_id: &str) -> Self {
+        LegacyServerMessage {
+            message_type: message_type.to_string(),
+            game_id: Some(game_id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn with_clock(mut self, white_time_ms: u64, black_time_ms: u64, increment_ms: u64) -> Self {
+        self.white_time_ms = Some(white_time_ms);
+        self.black_time_ms = Some(black_time_ms);
+        self.increment_ms = Some(increment_ms);
+        self
+    }
+
+    fn with_ratings(mut self, ratings: PlayerRatings) -> Self {
+        self.rated = Some(ratings.rated);
+        self.white_rating = ratings.white_rating;
+        self.black_rating = ratings.black_rating;
+        self
+    }
+
+    /// The version 1 form of `msg`, or `None` if version 1 has no such message
+    pub fn from_message(msg: &ServerMessage) -> Option<Self> {
+        let msg = msg.clone();
+        Some(match msg {
+            ServerMessage::Hello { .. } => return None,
+            ServerMessage::GameCreated { game_id, fen, color, game_status, active_color, clock, resume_token } => LegacyServerMessage {
+                fen: Some(fen),
+                color: Some(color),
+                game_status: Some(game_status),
+                active_color: Some(active_color),
+                resume_token: Some(resume_token),
+                ..Self::new("game_created", &game_id)
+            }
+            .with_clock(clock.white_time_ms, clock.black_time_ms, clock.increment_ms),
+            ServerMessage::Joined { game_id, fen, color, game_status, active_color, clock, resume_token, spectator_count, history, ratings } => LegacyServerMessage {
+                fen: Some(fen),
+                color: Some(color),
+                game_status: Some(game_status),
+                active_color: Some(active_color),
+                resume_token: Some(resume_token),
+                spectator_count: Some(spectator_count),
+                history: Some(history),
+                ..Self::new("joined", &game_id)
+            }
+            .with_clock(clock.white_time_ms, clock.black_time_ms, clock.increment_ms)
+            .with_ratings(ratings),
+            ServerMessage::PlayerJoined { game_id, fen, color, game_status, clock, spectator_count, ratings } => LegacyServerMessage {
+                fen: Some(fen),
+                color: Some(color),
+                game_status: Some(game_status),
+                spectator_count: Some(spectator_count),
+                ..Self::new("player_joined", &game_id)
+            }
+            .with_clock(clock.white_time_ms, clock.black_time_ms, clock.increment_ms)
+            .with_ratings(ratings),
+            ServerMessage::MoveMade { game_id, fen, last_move, san, uci, game_status, clock, termination, draw_claim } => LegacyServerMessage {
+                fen: Some(fen),
+                last_move: Some(last_move),
+                san: Some(san),
+                uci: Some(uci),
+                game_status: Some(game_status),
+                termination,
+                draw_claim,
+                ..Self::new("move_made", &game_id)
+            }
+            .with_clock(clock.white_time_ms, clock.black_time_ms, clock.increment_ms),
+            ServerMessage::AvailableMoves { game_id, available_moves } => LegacyServerMessage {
+                available_moves: Some(available_moves),
+                ..Self::new("available_moves", &game_id)
+            },
+            ServerMessage::TimeSync { game_id, fen, game_status, active_color, clock, termination } => LegacyServerMessage {
+                fen: Some(fen),
+                game_status: Some(game_status),
+                active_color: Some(active_color),
+                termination,
+                ..Self::new("time_sync", &game_id)
+            }
+            .with_clock(clock.white_time_ms, clock.black_time_ms, clock.increment_ms),
+            ServerMessage::Rejoined { game_id, fen, color, game_status, active_color, clock, resume_token, moves, last_move, history } => LegacyServerMessage {
+                fen: Some(fen),
+                color: Some(color),
+                game_status: Some(game_status),
+                active_color: Some(active_color),
+                resume_token: Some(resume_token),
+                moves: Some(moves),
+                last_move,
+                history: Some(history),
+                ..Self::new("rejoined", &game_id)
+            }
+            .with_clock(clock.white_time_ms, clock.black_time_ms, clock.increment_ms),
+            ServerMessage::PlayerRejoined { game_id, color, game_status, clock } => LegacyServerMessage {
+                color: Some(color),
+                game_status,
+                ..Self::new("player_rejoined", &game_id)
+            }
+            .with_clock(clock.white_time_ms, clock.black_time_ms, clock.increment_ms),
+            ServerMessage::Watching { game_id, fen, game_status, active_color, clock, moves, last_move, spectator_count, history } => LegacyServerMessage {
+                fen: Some(fen),
+                game_status: Some(game_status),
+                active_color: Some(active_color),
+                moves: Some(moves),
+                last_move,
+                spectator_count: Some(spectator_count),
+                history: Some(history),
+                ..Self::new("watching", &game_id)
+            }
+            .with_clock(clock.white_time_ms, clock.black_time_ms, clock.increment_ms),
+            ServerMessage::SpectatorJoined { game_id, spectator_count } => LegacyServerMessage {
+                spectator_count: Some(spectator_count),
+                ..Self::new("spectator_joined", &game_id)
+            },
+            ServerMessage::SpectatorLeft { game_id, spectator_count } => LegacyServerMessage {
+                spectator_count: Some(spectator_count),
+                ..Self::new("spectator_left", &game_id)
+            },
+            ServerMessage::DrawOffered { game_id, color } => LegacyServerMessage {
+                color: Some(color),
+                ..Self::new("draw_offered", &game_id)
+            },
+            ServerMessage::DrawDeclined { game_id, color } => LegacyServerMessage {
+                color: Some(color),
+                ..Self::new("draw_declined", &game_id)
+            },
+            ServerMessage::GameOver { game_id, fen, color, game_status, active_color, clock, termination } => LegacyServerMessage {
+                fen: Some(fen),
+                color,
+                game_status: Some(game_status),
+                active_color: Some(active_color),
+                termination,
+                ..Self::new("game_over", &game_id)
+            }
+            .with_clock(clock.white_time_ms, clock.black_time_ms, clock.increment_ms),
+            ServerMessage::OpponentMoved { game_id, san, uci, fen, played_at, move_deadline } => LegacyServerMessage {
+                san: Some(san),
+                uci: Some(uci),
+                fen: Some(fen),
+                played_at: Some(played_at),
+                move_deadline: Some(move_deadline),
+                ..Self::new("opponent_moved", &game_id)
+            },
+            ServerMessage::Pgn { game_id, pgn } => LegacyServerMessage {
+                pgn: Some(pgn),
+                ..Self::new("pgn", &game_id)
+            },
+            ServerMessage::History { game_id, start_fen, history } => LegacyServerMessage {
+                start_fen: Some(start_fen),
+                history: Some(history),
+                ..Self::new("history", &game_id)
+            },
+            ServerMessage::Analysis { game_id, fen, depth, evaluation, pv, done } => LegacyServerMessage {
+                fen: Some(fen),
+                depth: Some(depth),
+                evaluation,
+                pv: Some(pv),
+                done: Some(done),
+                ..Self::new("analysis", &game_id)
+            },
+            ServerMessage::Report { game_id, moves } => LegacyServerMessage {
+                report: Some(moves),
+                ..Self::new("report", &game_id)
+            },
+            ServerMessage::SeekCreated { seek_id } => LegacyServerMessage {
+                message_type: "seek_created".to_string(),
+                seek_id: Some(seek_id),
+                ..Default::default()
+            },
+            ServerMessage::SeekCancelled { seek_id } => LegacyServerMessage {
+                message_type: "seek_cancelled".to_string(),
+                seek_id: Some(seek_id),
+                ..Default::default()
+            },
+            ServerMessage::Seeks { seeks } => LegacyServerMessage {
+                message_type: "seeks".to_string(),
+                seeks: Some(seeks),
+                ..Default::default()
+            },
+            ServerMessage::LobbyGames { games } => LegacyServerMessage {
+                message_type: "lobby_games".to_string(),
+                waiting: Some(games.waiting),
+                in_progress: Some(games.in_progress),
+                ..Default::default()
+            },
+            ServerMessage::Error { code, message, game_id } => LegacyServerMessage {
+                message_type: "error".to_string(),
+                game_id,
+                error: Some(message),
+                code: Some(code),
+                ..Default::default()
+            },
+        })
+    }
+}
@@ -1,34 +1,434 @@
 use actix::Message;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use super::errors::ChessError;
+use crate::lobby::{LobbyGames, Seek};
+use crate::time_control::ClockMode;
+
+/// Current version of the WebSocket protocol.
+///
+/// Version 1 is the original flat `message_type` format, still spoken by
+/// connections that do not ask for a version (see `models::legacy`).
+pub const PROTOCOL_VERSION: u32 = 2;
+
 /// Message sent from client to server
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct ClientMessage {
-    pub message_type: String,
-    pub game_id: Option<String>,
-    pub move_from: Option<String>,
-    pub move_to: Option<String>,
-    pub color_preference: Option<String>,
-    pub start_time_minutes: Option<u64>,
-    pub increment_seconds: Option<u64>,
-    pub promote_to: Option<String>,
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Create {
+        start_time_minutes: Option<u64>,
+        increment_seconds: Option<u64>,
+        color_preference: Option<ColorPreference>,
+        /// Position to start from instead of the standard one
+        start_fen: Option<String>,
+        /// Play against an engine at this strength (1 to 8)
+        engine_level: Option<u8>,
+        /// Which engine plays, the built-in one by default
+        engine: Option<EngineKind>,
+        /// Stages and a clock mode, instead of the base time and increment
+        time_control: Option<TimeControlRequest>,
+        /// Make it a correspondence game with this many days for every move
+        days_per_move: Option<u32>,
+        /// Let the result change both players' ratings
+        #[serde(default)]
+        rated: bool,
+    },
+    Join {
+        game_id: String,
+    },
+    /// A move given as `from`/`to` squares (plus `promote_to`), as `uci`
+    /// (`e7e8q`) or as `san` (`Nf3`)
+    Move {
+        from: Option<String>,
+        to: Option<String>,
+        promote_to: Option<String>,
+        uci: Option<String>,
+        san: Option<String>,
+    },
+    GetMoves {
+        from: String,
+    },
+    TimeSync {
+        game_id: Option<String>,
+    },
+    Rejoin {
+        game_id: String,
+        resume_token: String,
+    },
+    Watch {
+        game_id: String,
+    },
+    Resign,
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
+    ClaimDraw,
+    GetPgn {
+        game_id: Option<String>,
+    },
+    /// Every move of a game with the positions and clocks after it; the
+    /// connection's own game unless `game_id` is given
+    GetHistory {
+        game_id: Option<String>,
+    },
+    /// Stream the engine's evaluation of a game's current position; the
+    /// connection's own game unless `game_id` is given. Players cannot
+    /// analyse while their game is in progress.
+    StartAnalysis {
+        game_id: Option<String>,
+    },
+    StopAnalysis,
+    /// How good each move of a finished game was
+    GetReport {
+        game_id: Option<String>,
+    },
+    /// Look for an opponent in the lobby, with the time fields of `create`.
+    /// Replaces the connection's previous seek.
+    Seek {
+        start_time_minutes: Option<u64>,
+        increment_seconds: Option<u64>,
+        time_control: Option<TimeControlRequest>,
+        days_per_move: Option<u32>,
+        color_preference: Option<ColorPreference>,
+        #[serde(default)]
+        rated: bool,
+        /// Only play opponents rated at least this much
+        min_rating: Option<u32>,
+        /// Only play opponents rated at most this much
+        max_rating: Option<u32>,
+    },
+    CancelSeek,
+    /// Be sent the open seeks now and whenever they change
+    SubscribeLobby,
+    UnsubscribeLobby,
+}
+
+impl ClientMessage {
+    /// Every `type` a client may send
+    pub const TYPES: &'static [&'static str] = &[
+        "create",
+        "join",
+        "move",
+        "get_moves",
+        "time_sync",
+        "rejoin",
+        "watch",
+        "resign",
+        "offer_draw",
+        "accept_draw",
+        "decline_draw",
+        "claim_draw",
+        "get_pgn",
+        "get_history",
+        "start_analysis",
+        "stop_analysis",
+        "get_report",
+        "seek",
+        "cancel_seek",
+        "subscribe_lobby",
+        "unsubscribe_lobby",
+    ];
+}
+
+/// Color the creator of a game wants to play
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorPreference {
+    White,
+    Black,
+    Random,
+}
+
+/// Engine that plays one side of a game
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EngineKind {
+    /// The search built into the server
+    #[default]
+    Builtin,
+    /// The external UCI engine the server is configured with
+    Uci,
+}
+
+/// A time control as clients ask for it, such as 40 moves in 90 minutes
+/// and then 30 minutes, with a 30 second increment throughout
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TimeControlRequest {
+    #[serde(default)]
+    pub mode: ClockMode,
+    pub stages: Vec<StageRequest>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StageRequest {
+    /// Moves in the stage, left out for the last stage unless it repeats
+    pub moves: Option<u32>,
+    pub minutes: u64,
+    /// The increment, or the delay in the delay modes
+    #[serde(default)]
+    pub increment_seconds: u64,
 }
 
 /// Message sent from server to client
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct ServerMessage {
-    pub message_type: String,
-    pub game_id: Option<String>,
-    pub fen: Option<String>,
-    pub color: Option<String>,
-    pub error: Option<String>,
-    pub available_moves: Option<Vec<String>>,
-    pub last_move: Option<LastMove>,
-    pub game_status: Option<String>,
-    pub white_time_ms: Option<u64>,
-    pub black_time_ms: Option<u64>,
-    pub increment_ms: Option<u64>,
-    pub active_color: Option<String>,
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// First message on every connection speaking protocol version 2 or later
+    Hello {
+        protocol_version: u32,
+    },
+    GameCreated {
+        game_id: String,
+        fen: String,
+        color: String,
+        game_status: String,
+        active_color: String,
+        #[serde(flatten)]
+        clock: ClockTimes,
+        resume_token: String,
+    },
+    Joined {
+        game_id: String,
+        fen: String,
+        color: String,
+        game_status: String,
+        active_color: String,
+        #[serde(flatten)]
+        clock: ClockTimes,
+        resume_token: String,
+        spectator_count: usize,
+        history: Vec<HistoryEntry>,
+        #[serde(flatten)]
+        ratings: PlayerRatings,
+    },
+    PlayerJoined {
+        game_id: String,
+        fen: String,
+        color: String,
+        game_status: String,
+        #[serde(flatten)]
+        clock: ClockTimes,
+        spectator_count: usize,
+        #[serde(flatten)]
+        ratings: PlayerRatings,
+    },
+    MoveMade {
+        game_id: String,
+        fen: String,
+        last_move: LastMove,
+        /// The move played, in SAN and UCI
+        san: String,
+        uci: String,
+        game_status: String,
+        #[serde(flatten)]
+        clock: ClockTimes,
+        termination: Option<String>,
+        /// Draw the next player to move may claim
+        draw_claim: Option<String>,
+    },
+    AvailableMoves {
+        game_id: String,
+        available_moves: Vec<String>,
+    },
+    TimeSync {
+        game_id: String,
+        fen: String,
+        game_status: String,
+        active_color: String,
+        #[serde(flatten)]
+        clock: ClockTimes,
+        termination: Option<String>,
+    },
+    Rejoined {
+        game_id: String,
+        fen: String,
+        color: String,
+        game_status: String,
+        active_color: String,
+        #[serde(flatten)]
+        clock: ClockTimes,
+        resume_token: String,
+        /// Moves played so far, in UCI notation
+        moves: Vec<String>,
+        last_move: Option<LastMove>,
+        history: Vec<HistoryEntry>,
+    },
+    /// Sent on reconnecting to a correspondence game in which the opponent
+    /// moved while the player was away and it is now the player's move
+    OpponentMoved {
+        game_id: String,
+        san: String,
+        uci: String,
+        fen: String,
+        played_at: DateTime<Utc>,
+        /// When the player runs out of time for their reply
+        move_deadline: DateTime<Utc>,
+    },
+    PlayerRejoined {
+        game_id: String,
+        color: String,
+        /// Set when the game was paused and is running again
+        game_status: Option<String>,
+        #[serde(flatten)]
+        clock: ClockTimes,
+    },
+    Watching {
+        game_id: String,
+        fen: String,
+        game_status: String,
+        active_color: String,
+        #[serde(flatten)]
+        clock: ClockTimes,
+        moves: Vec<String>,
+        last_move: Option<LastMove>,
+        spectator_count: usize,
+        history: Vec<HistoryEntry>,
+    },
+    SpectatorJoined {
+        game_id: String,
+        spectator_count: usize,
+    },
+    SpectatorLeft {
+        game_id: String,
+        spectator_count: usize,
+    },
+    DrawOffered {
+        game_id: String,
+        color: String,
+    },
+    DrawDeclined {
+        game_id: String,
+        color: String,
+    },
+    GameOver {
+        game_id: String,
+        fen: String,
+        /// Player whose action (or clock) ended the game, if any
+        color: Option<String>,
+        game_status: String,
+        active_color: String,
+        #[serde(flatten)]
+        clock: ClockTimes,
+        termination: Option<String>,
+    },
+    Pgn {
+        game_id: String,
+        pgn: String,
+    },
+    History {
+        game_id: String,
+        /// Position before the first move
+        start_fen: String,
+        history: Vec<HistoryEntry>,
+    },
+    /// The analysis of `fen` after another depth was searched
+    Analysis {
+        game_id: String,
+        fen: String,
+        depth: u8,
+        /// `None` when the side to move has no legal moves
+        evaluation: Option<Evaluation>,
+        /// Best line in SAN, starting with the best move
+        pv: Vec<String>,
+        /// The last update for this position
+        done: bool,
+    },
+    Report {
+        game_id: String,
+        moves: Vec<MoveReport>,
+    },
+    /// The seek is open in the lobby; pairing it sends `joined`
+    SeekCreated {
+        seek_id: String,
+    },
+    SeekCancelled {
+        seek_id: String,
+    },
+    /// Every open seek, oldest first, sent to lobby subscribers
+    Seeks {
+        seeks: Vec<Seek>,
+    },
+    /// Games waiting for an opponent and games in progress, sent to lobby subscribers
+    LobbyGames {
+        #[serde(flatten)]
+        games: LobbyGames,
+    },
+    Error {
+        code: ChessError,
+        /// Human readable description, more specific than the code's default
+        message: String,
+        game_id: Option<String>,
+    },
+}
+
+impl ServerMessage {
+    pub fn error(code: ChessError, message: impl Into<String>, game_id: Option<String>) -> Self {
+        ServerMessage::Error {
+            code,
+            message: message.into(),
+            game_id,
+        }
+    }
+}
+
+/// Both players' remaining time and the increment
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockTimes {
+    pub white_time_ms: u64,
+    pub black_time_ms: u64,
+    pub increment_ms: u64,
+}
+
+/// Whether a game is rated, and both players' ratings in its pool. Only
+/// users have ratings; guests and the engine have none.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PlayerRatings {
+    pub rated: bool,
+    pub white_rating: Option<u32>,
+    pub black_rating: Option<u32>,
+}
+
+/// One move in a game's history, with the position and clocks right after it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub san: String,
+    pub uci: String,
+    pub fen: String,
+    pub white_time_ms: u64,
+    pub black_time_ms: u64,
+    pub played_at: DateTime<Utc>,
+}
+
+/// An engine evaluation from White's point of view
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Evaluation {
+    /// Advantage in hundredths of a pawn
+    Cp(i32),
+    /// Mate in this many moves, negative when Black mates
+    Mate(i32),
+}
+
+/// One move of a post-game report
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MoveReport {
+    pub san: String,
+    /// Evaluation after the move, `None` once the game is over on the board
+    pub evaluation: Option<Evaluation>,
+    /// The engine's choice in the position before the move
+    pub best_move: Option<String>,
+    /// Centipawns the move gave away compared to the engine's choice
+    pub loss: u32,
+    pub judgement: Option<Judgement>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Judgement {
+    Inaccuracy,
+    Mistake,
+    Blunder,
 }
 
 /// Last move information
@@ -41,4 +441,4 @@ pub struct LastMove {
 /// Message type for WebSocket communication
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct ChessWebSocketMessage(pub String);
+pub struct ChessWebSocketMessage(pub ServerMessage);
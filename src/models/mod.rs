@@ -1,6 +1,9 @@
+pub mod errors;
 pub mod game_state;
+pub mod legacy;
 pub mod messages;
 
 // Re-export important types
+pub use errors::*;
 pub use game_state::*;
 pub use messages::*;
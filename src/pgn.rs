@@ -0,0 +1,353 @@
+use chess::{Action, Board, BoardStatus, ChessMove, Color, GameResult, MoveGen, Piece, Square};
+
+use crate::archive::GameRecord;
+use crate::models::Termination;
+use crate::validation;
+
+/// Standard Algebraic Notation for `chess_move` played in `board`
+pub fn san(board: &Board, chess_move: ChessMove) -> String {
+    let source = chess_move.get_source();
+    let dest = chess_move.get_dest();
+    let piece = board.piece_on(source).unwrap_or(Piece::Pawn);
+
+    let mut san = String::new();
+
+    // Castling is the only king move that jumps two files
+    if is_castling(board, chess_move) {
+        if dest.get_file().to_index() > source.get_file().to_index() {
+            san.push_str("O-O");
+        } else {
+            san.push_str("O-O-O");
+        }
+    } else {
+        let capture = is_capture(board, chess_move);
+
+        if piece == Piece::Pawn {
+            if capture {
+                san.push(file_char(source));
+            }
+        } else {
+            san.push_str(&piece.to_string(Color::White));
+
+            // Disambiguate between identical pieces that can reach the same square
+            let others: Vec<ChessMove> = MoveGen::new_legal(board)
+                .filter(|other| {
+                    other.get_dest() == dest
+                        && other.get_source() != source
+                        && board.piece_on(other.get_source()) == Some(piece)
+                })
+                .collect();
+
+            if !others.is_empty() {
+                let same_file = others.iter().any(|other| other.get_source().get_file() == source.get_file());
+                let same_rank = others.iter().any(|other| other.get_source().get_rank() == source.get_rank());
+
+                if !same_file {
+                    san.push(file_char(source));
+                } else if !same_rank {
+                    san.push(rank_char(source));
+                } else {
+                    san.push(file_char(source));
+                    san.push(rank_char(source));
+                }
+            }
+        }
+
+        if capture {
+            san.push('x');
+        }
+        san.push_str(&dest.to_string());
+
+        if let Some(promotion) = chess_move.get_promotion() {
+            san.push('=');
+            san.push_str(&promotion.to_string(Color::White));
+        }
+    }
+
+    let after = board.make_move_new(chess_move);
+    match after.status() {
+        BoardStatus::Checkmate => san.push('#'),
+        _ if after.checkers().popcnt() > 0 => san.push('+'),
+        _ => {}
+    }
+
+    san
+}
+
+// A pawn moving to another file always captures, even en passant onto an empty square
+fn is_capture(board: &Board, chess_move: ChessMove) -> bool {
+    board.piece_on(chess_move.get_dest()).is_some()
+        || (board.piece_on(chess_move.get_source()) == Some(Piece::Pawn)
+            && chess_move.get_source().get_file() != chess_move.get_dest().get_file())
+}
+
+fn is_castling(board: &Board, chess_move: ChessMove) -> bool {
+    board.piece_on(chess_move.get_source()) == Some(Piece::King)
+        && chess_move.get_source().get_file().to_index().abs_diff(chess_move.get_dest().get_file().to_index()) == 2
+}
+
+fn file_char(square: chess::Square) -> char {
+    (b'a' + square.get_file().to_index() as u8) as char
+}
+
+fn rank_char(square: chess::Square) -> char {
+    (b'1' + square.get_rank().to_index() as u8) as char
+}
+
+/// Why a SAN move could not be matched to a legal move
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SanError {
+    /// Not SAN at all
+    Invalid,
+    /// Well formed, but no legal move fits
+    Illegal,
+    /// A pawn reaching the last rank without saying what it promotes to
+    MissingPromotion,
+    /// More than one legal move fits; holds the SAN of each
+    Ambiguous(Vec<String>),
+}
+
+/// What a SAN move says about the move it stands for
+struct SanPattern {
+    piece: Piece,
+    from_file: Option<usize>,
+    from_rank: Option<usize>,
+    capture: bool,
+    dest: Square,
+    promotion: Option<Piece>,
+}
+
+impl SanPattern {
+    fn parse(san: &str) -> Option<Self> {
+        let (piece, rest) = match san.as_bytes() {
+            [b'K', rest @ ..] => (Piece::King, rest),
+            [b'Q', rest @ ..] => (Piece::Queen, rest),
+            [b'R', rest @ ..] => (Piece::Rook, rest),
+            [b'B', rest @ ..] => (Piece::Bishop, rest),
+            [b'N', rest @ ..] => (Piece::Knight, rest),
+            rest => (Piece::Pawn, rest),
+        };
+
+        // Promotions are usually written `e8=Q`, sometimes `e8Q`
+        let (rest, promotion) = match rest {
+            [rest @ .., letter @ (b'Q' | b'R' | b'B' | b'N')] if piece == Piece::Pawn => {
+                let promotion = match letter {
+                    b'Q' => Piece::Queen,
+                    b'R' => Piece::Rook,
+                    b'B' => Piece::Bishop,
+                    _ => Piece::Knight,
+                };
+                (rest.strip_suffix(b"=").unwrap_or(rest), Some(promotion))
+            }
+            rest => (rest, None),
+        };
+
+        let (rest, dest) = match rest {
+            [rest @ .., file, rank] => (rest, validation::parse_square(std::str::from_utf8(&[*file, *rank]).ok()?).ok()?),
+            _ => return None,
+        };
+
+        let (rest, capture) = match rest {
+            [rest @ .., b'x'] => (rest, true),
+            rest => (rest, false),
+        };
+
+        let file_index = |file: u8| (file - b'a') as usize;
+        let rank_index = |rank: u8| (rank - b'1') as usize;
+        let (from_file, from_rank) = match rest {
+            [] => (None, None),
+            [file @ b'a'..=b'h'] => (Some(file_index(*file)), None),
+            [rank @ b'1'..=b'8'] => (None, Some(rank_index(*rank))),
+            [file @ b'a'..=b'h', rank @ b'1'..=b'8'] => (Some(file_index(*file)), Some(rank_index(*rank))),
+            _ => return None,
+        };
+
+        Some(SanPattern {
+            piece,
+            from_file,
+            from_rank,
+            capture,
+            dest,
+            promotion,
+        })
+    }
+
+    /// Whether `chess_move` fits, leaving the promotion piece aside
+    fn fits(&self, board: &Board, chess_move: ChessMove) -> bool {
+        let source = chess_move.get_source();
+        board.piece_on(source) == Some(self.piece)
+            && chess_move.get_dest() == self.dest
+            && !is_castling(board, chess_move)
+            && self.from_file.is_none_or(|file| source.get_file().to_index() == file)
+            && self.from_rank.is_none_or(|rank| source.get_rank().to_index() == rank)
+            && (!self.capture || is_capture(board, chess_move))
+    }
+}
+
+/// The legal move in `board` that a SAN move such as `Nf3`, `exd5`, `e8=Q`
+/// or `O-O` stands for.
+///
+/// Check marks and annotations are ignored, castling may be written with
+/// zeros and a capture may leave out the `x`.
+pub fn parse_san(board: &Board, san: &str) -> Result<ChessMove, SanError> {
+    let san = san.trim().trim_end_matches(['+', '#', '!', '?']);
+    let san = san.strip_suffix("e.p.").unwrap_or(san).trim_end();
+
+    let legal_moves = MoveGen::new_legal(board);
+    let (fitting, promotion): (Vec<ChessMove>, Option<Piece>) = match san {
+        "O-O" | "0-0" | "O-O-O" | "0-0-0" => {
+            let kingside = san.len() == 3;
+            let fitting = legal_moves
+                .filter(|chess_move| {
+                    is_castling(board, *chess_move)
+                        && (chess_move.get_dest().get_file() > chess_move.get_source().get_file()) == kingside
+                })
+                .collect();
+            (fitting, None)
+        }
+        _ => {
+            let pattern = SanPattern::parse(san).ok_or(SanError::Invalid)?;
+            (legal_moves.filter(|chess_move| pattern.fits(board, *chess_move)).collect(), pattern.promotion)
+        }
+    };
+
+    let matching: Vec<ChessMove> = fitting
+        .iter()
+        .copied()
+        .filter(|chess_move| chess_move.get_promotion() == promotion)
+        .collect();
+
+    match matching.as_slice() {
+        [chess_move] => Ok(*chess_move),
+        [] if promotion.is_none() && !fitting.is_empty() => Err(SanError::MissingPromotion),
+        [] => Err(SanError::Illegal),
+        candidates => Err(SanError::Ambiguous(candidates.iter().map(|chess_move| self::san(board, *chess_move)).collect())),
+    }
+}
+
+/// SAN of every move played in the game, in order
+pub fn san_moves(start_position: Board, actions: &[Action]) -> Vec<String> {
+    let mut board = start_position;
+    let mut moves = Vec::new();
+    for action in actions {
+        if let Action::MakeMove(chess_move) = action {
+            moves.push(san(&board, *chess_move));
+            board = board.make_move_new(*chess_move);
+        }
+    }
+    moves
+}
+
+/// PGN result token for a game
+pub fn result_token(game_result: Option<GameResult>) -> &'static str {
+    match game_result {
+        Some(GameResult::WhiteCheckmates) | Some(GameResult::BlackResigns) => "1-0",
+        Some(GameResult::BlackCheckmates) | Some(GameResult::WhiteResigns) => "0-1",
+        Some(GameResult::Stalemate) | Some(GameResult::DrawAccepted) | Some(GameResult::DrawDeclared) => "1/2-1/2",
+        None => "*",
+    }
+}
+
+/// Value of the PGN `Termination` tag
+fn termination_tag(termination: Option<Termination>) -> &'static str {
+    match termination {
+        Some(Termination::Timeout) => "time forfeit",
+        Some(_) => "normal",
+        None => "unterminated",
+    }
+}
+
+/// Fullmove number field of a FEN, which `Board` does not keep
+fn fullmove_number(fen: &str) -> usize {
+    fen.split_whitespace()
+        .nth(5)
+        .and_then(|number| number.parse().ok())
+        .filter(|number| *number > 0)
+        .unwrap_or(1)
+}
+
+/// Human readable reason a game ended, added as a final comment
+fn termination_comment(termination: Termination) -> &'static str {
+    match termination {
+        Termination::Checkmate => "Checkmate",
+        Termination::Stalemate => "Stalemate",
+        Termination::Timeout => "Lost on time",
+        Termination::Resignation => "Resignation",
+        Termination::DrawAgreement => "Draw by agreement",
+        Termination::ThreefoldRepetition => "Draw by threefold repetition",
+        Termination::FivefoldRepetition => "Draw by fivefold repetition",
+        Termination::FiftyMoveRule => "Draw by the fifty-move rule",
+        Termination::SeventyFiveMoveRule => "Draw by the 75-move rule",
+    }
+}
+
+/// Export a game, finished or in progress, as PGN
+pub fn game_to_pgn(record: &GameRecord) -> String {
+    let result = record.result.as_deref().unwrap_or("*");
+    let time_control = if record.initial_time_ms == 0 && record.increment_ms == 0 {
+        "-".to_string()
+    } else {
+        record.time_control().pgn_tag()
+    };
+
+    let mut pgn = String::new();
+
+    // Seven Tag Roster first, then the supplemental tags
+    let mut tags = vec![
+        ("Event", "Casual game".to_string()),
+        ("Site", "?".to_string()),
+        ("Date", record.created_at.format("%Y.%m.%d").to_string()),
+        ("Round", "-".to_string()),
+        ("White", record.white_name.clone().unwrap_or_else(|| "?".to_string())),
+        ("Black", record.black_name.clone().unwrap_or_else(|| "?".to_string())),
+        ("Result", result.to_string()),
+        ("GameId", record.game_id.clone()),
+        ("TimeControl", time_control),
+        ("Termination", termination_tag(record.termination).to_string()),
+    ];
+    if let Some(fen) = &record.start_fen {
+        tags.push(("SetUp", "1".to_string()));
+        tags.push(("FEN", fen.clone()));
+    }
+    for (name, value) in tags.iter() {
+        pgn.push_str(&format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")));
+    }
+    pgn.push('\n');
+
+    // Movetext
+    let mut tokens = Vec::new();
+    let first_move_number = record.start_fen.as_deref().map_or(1, fullmove_number);
+    // Ply 0 is always a white move, so a game starting with black skips it
+    let first_ply = if record.start_fen.as_deref().and_then(|fen| fen.split_whitespace().nth(1)) == Some("b") { 1 } else { 0 };
+    for (index, san) in record.san.iter().enumerate() {
+        let ply = first_ply + index;
+        let move_number = first_move_number + ply / 2;
+        if ply % 2 == 0 {
+            tokens.push(format!("{}.", move_number));
+        } else if index == 0 {
+            tokens.push(format!("{}...", move_number));
+        }
+        tokens.push(san.clone());
+    }
+    if let Some(termination) = record.termination {
+        tokens.push(format!("{{{}}}", termination_comment(termination)));
+    }
+    tokens.push(result.to_string());
+
+    // Keep lines under 80 characters
+    let mut line_length = 0;
+    for token in tokens {
+        if line_length > 0 && line_length + 1 + token.len() > 79 {
+            pgn.push('\n');
+            line_length = 0;
+        } else if line_length > 0 {
+            pgn.push(' ');
+            line_length += 1;
+        }
+        line_length += token.len();
+        pgn.push_str(&token);
+    }
+    pgn.push('\n');
+
+    pgn
+}
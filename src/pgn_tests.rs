@@ -0,0 +1,152 @@
+//! Tests for move notation: moves sent in UCI and SAN, the errors for ones
+//! that do not fit the position, the history both are reported in, and PGN
+//! export.
+
+use serde_json::json;
+
+use crate::accounts::Credentials;
+use crate::protocol::ProtocolVersion;
+use crate::test_support::{exchange, pump, test_app_state, NullStore, TestClient};
+
+#[actix_rt::test]
+async fn moves_in_uci_and_san() {
+    let app_state = test_app_state();
+    let mut clients = vec![
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+    ];
+    pump(&mut clients);
+
+    // Knights on b1 and f1 can both reach d2, and the pawn on a7 is about to promote
+    let start_fen = "4k3/P7/8/8/8/8/8/1N2KN2 w - - 0 1";
+    let reply = exchange(&mut clients, 0, json!({"type": "create", "start_fen": start_fen, "color_preference": "white"}));
+    let game_id = reply["game_id"].as_str().unwrap().to_string();
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+
+    let cases = [
+        (json!({"type": "move", "san": "Nd2"}), "ambiguous_move"),
+        (json!({"type": "move", "san": "Nd3"}), "illegal_move"),
+        (json!({"type": "move", "san": "Nxd2"}), "illegal_move"),
+        (json!({"type": "move", "san": "a8"}), "invalid_promotion"),
+        (json!({"type": "move", "san": "N?d2"}), "invalid_notation"),
+        (json!({"type": "move", "uci": "a7a8x"}), "invalid_promotion"),
+        (json!({"type": "move", "uci": "b1d"}), "invalid_notation"),
+        (json!({"type": "move", "uci": "b1d2", "san": "Nbd2"}), "invalid_message"),
+        (json!({"type": "move", "from": "b1"}), "invalid_message"),
+    ];
+    for (message, code) in cases {
+        let reply = exchange(&mut clients, 0, message.clone());
+        assert_eq!(reply["code"], code, "{} answered with {}", message, reply);
+    }
+
+    let reply = exchange(&mut clients, 0, json!({"type": "move", "san": "Nbd2"}));
+    assert_eq!(reply["type"], "move_made", "{}", reply);
+    assert_eq!((reply["san"].as_str(), reply["uci"].as_str()), (Some("Nbd2"), Some("b1d2")));
+
+    let reply = exchange(&mut clients, 1, json!({"type": "move", "uci": "e8d7"}));
+    assert_eq!((reply["san"].as_str(), reply["uci"].as_str()), (Some("Kd7"), Some("e8d7")));
+
+    let reply = exchange(&mut clients, 0, json!({"type": "move", "uci": "a7a8n"}));
+    assert_eq!((reply["san"].as_str(), reply["uci"].as_str()), (Some("a8=N"), Some("a7a8n")));
+    let fen = reply["fen"].clone();
+
+    // The history has every move with the position after it
+    let reply = exchange(&mut clients, 1, json!({"type": "get_history"}));
+    assert_eq!(reply["type"], "history", "{}", reply);
+    assert_eq!(reply["start_fen"], start_fen);
+    let history = reply["history"].as_array().unwrap();
+    let sans: Vec<_> = history.iter().map(|entry| entry["san"].as_str().unwrap()).collect();
+    assert_eq!(sans, ["Nbd2", "Kd7", "a8=N"]);
+    assert_eq!(history[2]["fen"], fen);
+
+    // and is sent to anyone who arrives late
+    clients.push(TestClient::connect(&app_state, ProtocolVersion::V2));
+    pump(&mut clients);
+    let reply = exchange(&mut clients, 2, json!({"type": "watch", "game_id": game_id}));
+    assert_eq!(reply["type"], "watching", "{}", reply);
+    assert_eq!(reply["history"].as_array().unwrap().len(), 3);
+}
+
+/// PGN of `game_id` as `get_pgn` returns it, without the `Date` tag
+fn pgn_without_date(clients: &mut [TestClient], game_id: &str) -> String {
+    let reply = exchange(clients, 0, json!({"type": "get_pgn", "game_id": game_id}));
+    assert_eq!(reply["type"], "pgn", "{}", reply);
+    let pgn = reply["pgn"].as_str().unwrap();
+    assert!(pgn.lines().all(|line| line.len() < 80), "{}", pgn);
+    pgn.lines().filter(|line| !line.starts_with("[Date ")).map(|line| format!("{}\n", line)).collect()
+}
+
+#[actix_rt::test]
+async fn games_export_as_pgn() {
+    let app_state = test_app_state();
+    let mut clients = vec![
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+    ];
+    pump(&mut clients);
+
+    // A game set up with Black to move numbers its moves on from the FEN
+    let start_fen = "4k3/8/8/8/8/8/P7/4K3 b - - 3 12";
+    let create = json!({"type": "create", "color_preference": "white", "start_fen": start_fen, "start_time_minutes": 3, "increment_seconds": 2});
+    let game_id = exchange(&mut clients, 0, create)["game_id"].as_str().unwrap().to_string();
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    exchange(&mut clients, 1, json!({"type": "move", "uci": "e8d7"}));
+    exchange(&mut clients, 0, json!({"type": "move", "uci": "a2a4"}));
+    exchange(&mut clients, 1, json!({"type": "move", "uci": "d7c6"}));
+    assert!(pgn_without_date(&mut clients, &game_id).ends_with("[Termination \"unterminated\"]\n[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/P7/4K3 b - - 3 12\"]\n\n12... Kd7 13. a4 Kc6 *\n"));
+    exchange(&mut clients, 0, json!({"type": "resign"}));
+
+    let expected = format!(
+        "[Event \"Casual game\"]\n[Site \"?\"]\n[Round \"-\"]\n[White \"?\"]\n[Black \"?\"]\n[Result \"0-1\"]\n[GameId \"{}\"]\n\
+         [TimeControl \"180+2\"]\n[Termination \"normal\"]\n[SetUp \"1\"]\n[FEN \"{}\"]\n\n12... Kd7 13. a4 Kc6 {{Resignation}} 0-1\n",
+        game_id, start_fen
+    );
+    assert_eq!(pgn_without_date(&mut clients, &game_id), expected);
+
+    // Long movetext is wrapped, and a game from the standard position has no FEN
+    let game_id = exchange(&mut clients, 0, json!({"type": "create", "color_preference": "white"}))["game_id"].as_str().unwrap().to_string();
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    for _ in 0..4 {
+        for (player, uci) in [(0, "g1f3"), (1, "g8f6"), (0, "f3g1"), (1, "f6g8")] {
+            exchange(&mut clients, player, json!({"type": "move", "uci": uci}));
+        }
+    }
+    let pgn = pgn_without_date(&mut clients, &game_id);
+    assert!(!pgn.contains("[FEN "), "{}", pgn);
+    let movetext = pgn.split("\n\n").nth(1).unwrap();
+    assert_eq!(
+        movetext,
+        "1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3 Nf6 4. Ng1 Ng8 5. Nf3 Nf6 6. Ng1 Ng8 7. Nf3 Nf6 8.\n\
+         Ng1 Ng8 {Draw by fivefold repetition} 1/2-1/2\n"
+    );
+}
+
+#[actix_rt::test]
+async fn pgn_names_users_and_the_engine() {
+    let app_state = test_app_state();
+    let credentials = Credentials {
+        username: "capablanca".to_string(),
+        password: "havana1888".to_string(),
+    };
+    let user = app_state.accounts.register(&NullStore, &credentials).unwrap();
+    let mut clients = vec![
+        TestClient::connect_as(&app_state, ProtocolVersion::V2, Some(user), None),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+    ];
+    pump(&mut clients);
+
+    // Guests stay anonymous
+    let game_id = exchange(&mut clients, 0, json!({"type": "create", "color_preference": "black"}))["game_id"].as_str().unwrap().to_string();
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    let pgn = pgn_without_date(&mut clients, &game_id);
+    assert!(pgn.contains("[White \"?\"]\n[Black \"capablanca\"]\n"), "{}", pgn);
+
+    // and the names stay in the archive after the game
+    exchange(&mut clients, 1, json!({"type": "resign"}));
+    let pgn = pgn_without_date(&mut clients, &game_id);
+    assert!(pgn.contains("[White \"?\"]\n[Black \"capablanca\"]\n[Result \"0-1\"]\n"), "{}", pgn);
+
+    let game_id = exchange(&mut clients, 0, json!({"type": "create", "color_preference": "white", "engine_level": 1}))["game_id"].as_str().unwrap().to_string();
+    let pgn = pgn_without_date(&mut clients, &game_id);
+    assert!(pgn.contains("[White \"capablanca\"]\n[Black \"engine\"]\n"), "{}", pgn);
+}
@@ -0,0 +1,110 @@
+use log::warn;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::models::legacy::{LegacyClientMessage, LegacyServerMessage};
+use crate::models::{ClientMessage, ChessError, ServerMessage, PROTOCOL_VERSION};
+
+/// WebSocket protocol spoken by one connection, picked with `/ws?protocol=N`.
+///
+/// Connections that do not ask for a version get version 1 so existing
+/// clients keep working unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    /// Flat objects keyed by `message_type` (see `models::legacy`)
+    V1,
+    /// Tagged objects keyed by `type`, with structured errors
+    V2,
+}
+
+impl ProtocolVersion {
+    pub fn from_number(version: u32) -> Option<Self> {
+        match version {
+            1 => Some(ProtocolVersion::V1),
+            PROTOCOL_VERSION => Some(ProtocolVersion::V2),
+            _ => None,
+        }
+    }
+}
+
+/// Query string of `/ws`
+#[derive(Deserialize, Debug)]
+pub struct ConnectQuery {
+    pub protocol: Option<u32>,
+}
+
+/// Why an incoming message could not be turned into a `ClientMessage`
+#[derive(Debug, Clone)]
+pub struct ProtocolError {
+    pub code: ChessError,
+    pub message: String,
+}
+
+impl ProtocolError {
+    fn new(code: ChessError, message: impl Into<String>) -> Self {
+        ProtocolError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn into_message(self) -> ServerMessage {
+        ServerMessage::error(self.code, self.message, None)
+    }
+}
+
+/// Parse a text frame from a client.
+///
+/// Both message formats are accepted on every connection: a `type` key means
+/// version 2, a `message_type` key means version 1.
+pub fn parse_client_message(text: &str) -> Result<ClientMessage, ProtocolError> {
+    let value: Value = serde_json::from_str(text)
+        .map_err(|e| ProtocolError::new(ChessError::InvalidJson, format!("Invalid JSON: {}", e)))?;
+
+    let object = value
+        .as_object()
+        .ok_or_else(|| ProtocolError::new(ChessError::InvalidMessage, "Messages must be JSON objects"))?;
+
+    let (type_key, message_type) = match (object.get("type"), object.get("message_type")) {
+        (Some(message_type), _) => ("type", message_type),
+        (None, Some(message_type)) => ("message_type", message_type),
+        (None, None) => return Err(ProtocolError::new(ChessError::InvalidMessage, "Missing message type")),
+    };
+
+    let message_type = message_type
+        .as_str()
+        .ok_or_else(|| ProtocolError::new(ChessError::InvalidMessage, format!("{} must be a string", type_key)))?;
+
+    if !ClientMessage::TYPES.contains(&message_type) {
+        return Err(ProtocolError::new(
+            ChessError::UnknownMessageType,
+            format!("Unknown message type: {}", message_type),
+        ));
+    }
+
+    let invalid = |e: String| ProtocolError::new(ChessError::InvalidMessage, format!("Invalid {} message: {}", message_type, e));
+
+    if type_key == "type" {
+        serde_json::from_value(value.clone()).map_err(|e| invalid(e.to_string()))
+    } else {
+        let legacy: LegacyClientMessage = serde_json::from_value(value.clone()).map_err(|e| invalid(e.to_string()))?;
+        ClientMessage::try_from(legacy).map_err(invalid)
+    }
+}
+
+/// Serialize a message for a connection speaking `version`, or `None` if
+/// that version has no such message
+pub fn encode(message: &ServerMessage, version: ProtocolVersion) -> Option<String> {
+    let encoded = match version {
+        ProtocolVersion::V1 => serde_json::to_string(&LegacyServerMessage::from_message(message)?),
+        ProtocolVersion::V2 => serde_json::to_string(message),
+    };
+
+    match encoded {
+        Ok(text) => Some(text),
+        Err(e) => {
+            warn!("Failed to serialize {:?}: {}", message, e);
+            None
+        }
+    }
+}
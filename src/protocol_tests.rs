@@ -0,0 +1,117 @@
+//! Tests for the two message formats: version 1 clients send and get flat
+//! objects keyed by `message_type`, version 2 clients objects tagged by `type`.
+
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+
+use crate::models::legacy::LegacyServerMessage;
+use crate::protocol::ProtocolVersion;
+use crate::test_support::{exchange, pump, test_app_state, TestClient};
+
+fn keys(message: &Value) -> BTreeSet<&str> {
+    message.as_object().unwrap().keys().map(String::as_str).collect()
+}
+
+/// The message of a type a client got, in either format
+fn received<'a>(client: &'a TestClient, message_type: &str) -> &'a Value {
+    client
+        .messages
+        .iter()
+        .find(|message| message["type"] == message_type || message["message_type"] == message_type)
+        .unwrap_or_else(|| panic!("no {} in {:?}", message_type, client.messages))
+}
+
+#[actix_rt::test]
+async fn version_1_clients_keep_the_flat_format() {
+    let app_state = test_app_state();
+    let mut clients = vec![
+        TestClient::connect(&app_state, ProtocolVersion::V1),
+        TestClient::connect(&app_state, ProtocolVersion::V1),
+    ];
+    pump(&mut clients);
+    // Every version 1 message has all the fields, unused ones null
+    let legacy_keys = serde_json::to_value(LegacyServerMessage::default()).unwrap();
+    let legacy_keys = keys(&legacy_keys);
+
+    let create = json!({"message_type": "create", "color_preference": "white", "start_time_minutes": 3, "increment_seconds": 2});
+    let created = exchange(&mut clients, 0, create);
+    assert_eq!(keys(&created), legacy_keys, "{}", created);
+    assert_eq!(created["message_type"], "game_created");
+    assert_eq!((created["color"].as_str(), created["active_color"].as_str()), (Some("white"), Some("white")));
+    assert_eq!((created["white_time_ms"].as_u64(), created["increment_ms"].as_u64()), (Some(180_000), Some(2000)));
+    assert_eq!((created["error"].clone(), created["pv"].clone()), (Value::Null, Value::Null));
+    assert!(created["resume_token"].is_string());
+    let game_id = created["game_id"].clone();
+
+    let joined = exchange(&mut clients, 1, json!({"message_type": "join", "game_id": game_id}));
+    assert_eq!(keys(&joined), legacy_keys, "{}", joined);
+    assert_eq!((joined["message_type"].as_str(), joined["game_id"].clone()), (Some("joined"), game_id.clone()));
+    assert_eq!((joined["color"].as_str(), joined["spectator_count"].as_u64()), (Some("black"), Some(0)));
+    assert_eq!((joined["rated"].as_bool(), joined["history"].clone()), (Some(false), json!([])));
+    let player_joined = received(&clients[0], "player_joined");
+    assert_eq!((player_joined["color"].as_str(), player_joined["black_time_ms"].as_u64()), (Some("black"), Some(180_000)));
+
+    let moved = exchange(&mut clients, 0, json!({"message_type": "move", "move_from": "e2", "move_to": "e4"}));
+    assert_eq!(keys(&moved), legacy_keys, "{}", moved);
+    assert_eq!(moved["message_type"], "move_made");
+    assert_eq!((moved["san"].as_str(), moved["uci"].as_str(), moved["game_status"].as_str()), (Some("e4"), Some("e2e4"), Some("black_turn")));
+    assert_eq!(moved["last_move"], json!({"from": "e2", "to": "e4"}));
+    assert_eq!(moved["fen"], "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1");
+    assert_eq!(received(&clients[1], "move_made"), &moved);
+
+    // Errors too, with the same codes as version 2
+    let error = exchange(&mut clients, 0, json!({"message_type": "move", "move_from": "e4", "move_to": "e5"}));
+    assert_eq!(keys(&error), legacy_keys, "{}", error);
+    assert_eq!((error["message_type"].as_str(), error["code"].as_str()), (Some("error"), Some("not_your_turn")), "{}", error);
+    assert!(error["error"].is_string());
+}
+
+#[actix_rt::test]
+async fn version_2_clients_get_tagged_messages() {
+    let app_state = test_app_state();
+    let mut clients = vec![
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V1),
+    ];
+    pump(&mut clients);
+    assert_eq!(clients[0].messages, [json!({"type": "hello", "protocol_version": 2})]);
+    assert!(clients[2].messages.is_empty());
+
+    let create = json!({"type": "create", "color_preference": "white", "start_time_minutes": 3, "increment_seconds": 2});
+    let created = exchange(&mut clients, 0, create);
+    assert_eq!(
+        keys(&created),
+        BTreeSet::from(["type", "game_id", "fen", "color", "game_status", "active_color", "white_time_ms", "black_time_ms", "increment_ms", "resume_token"]),
+        "{}",
+        created
+    );
+    assert_eq!((created["type"].as_str(), created["color"].as_str()), (Some("game_created"), Some("white")));
+    assert_eq!((created["white_time_ms"].as_u64(), created["increment_ms"].as_u64()), (Some(180_000), Some(2000)));
+    let game_id = created["game_id"].clone();
+
+    let joined = exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    assert_eq!((joined["type"].as_str(), joined["game_id"].clone()), (Some("joined"), game_id.clone()), "{}", joined);
+    assert_eq!((joined["color"].as_str(), joined["spectator_count"].as_u64()), (Some("black"), Some(0)));
+    assert_eq!((joined["rated"].as_bool(), joined["history"].clone()), (Some(false), json!([])));
+    assert!(joined.get("message_type").is_none() && joined.get("error").is_none(), "{}", joined);
+    assert_eq!(received(&clients[0], "player_joined")["color"], "black");
+
+    // A version 1 spectator of the same game still gets the flat format
+    exchange(&mut clients, 2, json!({"message_type": "watch", "game_id": game_id}));
+    let moved = exchange(&mut clients, 0, json!({"type": "move", "from": "e2", "to": "e4"}));
+    assert_eq!(
+        keys(&moved),
+        BTreeSet::from(["type", "game_id", "fen", "last_move", "san", "uci", "game_status", "white_time_ms", "black_time_ms", "increment_ms", "termination", "draw_claim"]),
+        "{}",
+        moved
+    );
+    assert_eq!((moved["type"].as_str(), moved["san"].as_str(), moved["uci"].as_str()), (Some("move_made"), Some("e4"), Some("e2e4")));
+    assert_eq!(moved["last_move"], json!({"from": "e2", "to": "e4"}));
+    let watched = received(&clients[2], "move_made");
+    assert_eq!((watched.get("type"), watched["san"].as_str()), (None, Some("e4")), "{}", watched);
+
+    let error = exchange(&mut clients, 0, json!({"type": "move", "uci": "e4e5"}));
+    assert_eq!(keys(&error), BTreeSet::from(["type", "code", "message", "game_id"]), "{}", error);
+    assert_eq!((error["type"].as_str(), error["code"].as_str()), (Some("error"), Some("not_your_turn")));
+}
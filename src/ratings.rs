@@ -0,0 +1,272 @@
+//! Glicko-2 ratings for rated games between users.
+//!
+//! Every user has a separate rating in each pool, the pools being the speed
+//! categories a time control falls into. A rating is updated as soon as a
+//! rated game ends, treating the game as a rating period of its own, and its
+//! deviation grows again while the user does not play in that pool: one
+//! period's worth for every day away. Every update is kept, which is both
+//! the rating history and how current ratings are rebuilt after a restart.
+//!
+//! See Mark Glickman's "Example of the Glicko-2 system" for the algorithm.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+
+use crate::storage::GameStore;
+use crate::time_control::TimeControl;
+
+/// Rating of a player nobody knows anything about yet
+pub const DEFAULT_RATING: f64 = 1500.0;
+pub const DEFAULT_DEVIATION: f64 = 350.0;
+pub const DEFAULT_VOLATILITY: f64 = 0.06;
+/// Glicko-2's system constant, which limits how fast volatility changes
+const TAU: f64 = 0.5;
+/// Converts between the Glicko and Glicko-2 scales
+const SCALE: f64 = 173.7178;
+/// Precision the new volatility is found to
+const EPSILON: f64 = 0.000001;
+/// Deviation grows by one rating period per this long without a game
+const RATING_PERIOD_DAYS: f64 = 1.0;
+/// Deviation above which a rating is provisional
+pub const PROVISIONAL_DEVIATION: f64 = 110.0;
+
+/// Speed categories, each with its own ratings
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Pool {
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    /// Days per move, which no clock time compares to
+    Correspondence,
+}
+
+impl Pool {
+    /// The pool of a time control, by the time a 40-move game takes each
+    /// player: under 3 minutes is bullet, under 8 blitz, under 25 rapid and
+    /// anything longer classical. Correspondence games have a pool of their own.
+    pub fn of(time_control: &TimeControl) -> Self {
+        if time_control.is_correspondence() {
+            return Pool::Correspondence;
+        }
+        match time_control.time_for_moves(40) / 1000 {
+            0..180 => Pool::Bullet,
+            180..480 => Pool::Blitz,
+            480..1500 => Pool::Rapid,
+            _ => Pool::Classical,
+        }
+    }
+}
+
+/// One player's Glicko-2 rating, on the familiar Glicko scale
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Rating {
+            rating: DEFAULT_RATING,
+            deviation: DEFAULT_DEVIATION,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+}
+
+impl Rating {
+    pub fn is_provisional(&self) -> bool {
+        self.deviation > PROVISIONAL_DEVIATION
+    }
+
+    /// The rating after `periods` rating periods without a game, which
+    /// leaves it where it was but less certain
+    pub fn decayed(&self, periods: f64) -> Rating {
+        let phi = self.deviation / SCALE;
+        let phi = (phi * phi + self.volatility * self.volatility * periods.max(0.0)).sqrt();
+        Rating {
+            deviation: (phi * SCALE).min(DEFAULT_DEVIATION),
+            ..*self
+        }
+    }
+
+    /// The rating after one rating period with these games, each against an
+    /// opponent's rating with a score of 1 for a win, 0.5 for a draw and 0
+    /// for a loss
+    pub fn update(&self, games: &[(Rating, f64)]) -> Rating {
+        if games.is_empty() {
+            return self.decayed(1.0);
+        }
+        let mu = (self.rating - DEFAULT_RATING) / SCALE;
+        let phi = self.deviation / SCALE;
+        let sigma = self.volatility;
+
+        // Estimated variance from the game outcomes, and the improvement they suggest
+        let mut variance_inverse = 0.0;
+        let mut improvement = 0.0;
+        for (opponent, score) in games {
+            let opponent_mu = (opponent.rating - DEFAULT_RATING) / SCALE;
+            let g = g(opponent.deviation / SCALE);
+            let expected = 1.0 / (1.0 + (-g * (mu - opponent_mu)).exp());
+            variance_inverse += g * g * expected * (1.0 - expected);
+            improvement += g * (score - expected);
+        }
+        let v = 1.0 / variance_inverse;
+        let delta = v * improvement;
+
+        // The new volatility, by the Illinois algorithm
+        let a = (sigma * sigma).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            let denominator = phi * phi + v + ex;
+            ex * (delta * delta - phi * phi - v - ex) / (2.0 * denominator * denominator) - (x - a) / (TAU * TAU)
+        };
+        let mut lower = a;
+        let mut upper = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * TAU
+        };
+        let mut f_lower = f(lower);
+        let mut f_upper = f(upper);
+        while (upper - lower).abs() > EPSILON {
+            let c = lower + (lower - upper) * f_lower / (f_upper - f_lower);
+            let f_c = f(c);
+            if f_c * f_upper <= 0.0 {
+                lower = upper;
+                f_lower = f_upper;
+            } else {
+                f_lower /= 2.0;
+            }
+            upper = c;
+            f_upper = f_c;
+        }
+        let new_sigma = (lower / 2.0).exp();
+
+        let phi_star = (phi * phi + new_sigma * new_sigma).sqrt();
+        let new_phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let new_mu = mu + new_phi * new_phi * improvement;
+        Rating {
+            rating: new_mu * SCALE + DEFAULT_RATING,
+            deviation: (new_phi * SCALE).min(DEFAULT_DEVIATION),
+            volatility: new_sigma,
+        }
+    }
+}
+
+/// How much an opponent's deviation discounts what a game against them says
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (std::f64::consts::PI * std::f64::consts::PI)).sqrt()
+}
+
+/// A user's rating in a pool right after a rated game
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RatingChange {
+    pub user_id: String,
+    pub pool: Pool,
+    pub game_id: String,
+    #[serde(flatten)]
+    pub rating: Rating,
+    pub at: DateTime<Utc>,
+}
+
+/// The latest rating by user and pool, and when it changed
+type CurrentRatings = HashMap<(String, Pool), (Rating, DateTime<Utc>)>;
+
+/// Every user's ratings in every pool
+pub struct Ratings {
+    current: Mutex<CurrentRatings>,
+    /// Every change so far, oldest first
+    history: Mutex<Vec<RatingChange>>,
+}
+
+impl Ratings {
+    pub fn new(changes: Vec<RatingChange>) -> Self {
+        let current = changes
+            .iter()
+            .map(|change| ((change.user_id.clone(), change.pool), (change.rating, change.at)))
+            .collect();
+        Ratings {
+            current: Mutex::new(current),
+            history: Mutex::new(changes),
+        }
+    }
+
+    /// A user's rating in a pool as of `now`, with the time since their last game in it taken into account
+    pub fn rating(&self, user_id: &str, pool: Pool, now: DateTime<Utc>) -> Rating {
+        let current = self.current.lock().unwrap();
+        match current.get(&(user_id.to_string(), pool)) {
+            Some((rating, at)) => rating.decayed(periods_between(*at, now)),
+            None => Rating::default(),
+        }
+    }
+
+    /// Rate a finished game, where `white_score` is 1 if White won, 0.5 for a
+    /// draw and 0 if Black won, and persist both players' new ratings
+    pub fn rate_game(
+        &self,
+        store: &dyn GameStore,
+        game_id: &str,
+        pool: Pool,
+        [white_id, black_id]: [&str; 2],
+        white_score: f64,
+        now: DateTime<Utc>,
+    ) -> io::Result<[RatingChange; 2]> {
+        let white = self.rating(white_id, pool, now);
+        let black = self.rating(black_id, pool, now);
+        let change = |user_id: &str, rating: Rating| RatingChange {
+            user_id: user_id.to_string(),
+            pool,
+            game_id: game_id.to_string(),
+            rating,
+            at: now,
+        };
+        let changes = [
+            change(white_id, white.update(&[(black, white_score)])),
+            change(black_id, black.update(&[(white, 1.0 - white_score)])),
+        ];
+
+        // Both changes are kept before either shows, so a failed write leaves
+        // neither player rated
+        for change in &changes {
+            store.record_rating(change)?;
+        }
+        let mut current = self.current.lock().unwrap();
+        let mut history = self.history.lock().unwrap();
+        for change in &changes {
+            current.insert((change.user_id.clone(), pool), (change.rating, now));
+            history.push(change.clone());
+        }
+        Ok(changes)
+    }
+
+    /// Every rating change of a user, oldest first
+    pub fn history(&self, user_id: &str) -> Vec<RatingChange> {
+        let history = self.history.lock().unwrap();
+        history.iter().filter(|change| change.user_id == user_id).cloned().collect()
+    }
+
+    /// A user's current rating in every pool they have played rated games in
+    pub fn ratings_of(&self, user_id: &str, now: DateTime<Utc>) -> HashMap<Pool, Rating> {
+        let current = self.current.lock().unwrap();
+        current
+            .iter()
+            .filter(|((id, _), _)| id == user_id)
+            .map(|((_, pool), (rating, at))| (*pool, rating.decayed(periods_between(*at, now))))
+            .collect()
+    }
+}
+
+fn periods_between(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    (to - from).num_seconds() as f64 / (RATING_PERIOD_DAYS * 24.0 * 60.0 * 60.0)
+}
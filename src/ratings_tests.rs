@@ -0,0 +1,190 @@
+//! Tests for ratings: the Glicko-2 arithmetic against Glickman's worked
+//! example, the pools time controls fall into, and rated games between users
+//! updating both players' ratings, or neither.
+
+use actix_web::test::{self as http, TestRequest};
+use actix_web::{web, App};
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::accounts::{Account, Credentials};
+use crate::archive::GameRecord;
+use crate::test_support::{exchange, pump, test_app_state, NullStore, TestClient};
+use crate::protocol::ProtocolVersion;
+use crate::ratings::{Pool, Rating, RatingChange, Ratings, DEFAULT_DEVIATION};
+use crate::storage::{GameEvent, GameStore};
+use crate::time_control::{ClockMode, Stage, TimeControl};
+use crate::user_ratings;
+
+fn rating(rating: f64, deviation: f64) -> Rating {
+    Rating { rating, deviation, ..Rating::default() }
+}
+
+/// A store with room for this many more rating changes, and like `NullStore` nothing else
+struct FullDisk(AtomicUsize);
+
+impl GameStore for FullDisk {
+    fn record(&self, event: &GameEvent) -> io::Result<()> {
+        NullStore.record(event)
+    }
+
+    fn load(&self) -> io::Result<Vec<GameEvent>> {
+        NullStore.load()
+    }
+
+    fn archive_game(&self, record: &GameRecord) -> io::Result<()> {
+        NullStore.archive_game(record)
+    }
+
+    fn load_archive(&self) -> io::Result<Vec<GameRecord>> {
+        NullStore.load_archive()
+    }
+
+    fn record_account(&self, account: &Account) -> io::Result<()> {
+        NullStore.record_account(account)
+    }
+
+    fn load_accounts(&self) -> io::Result<Vec<Account>> {
+        NullStore.load_accounts()
+    }
+
+    fn record_rating(&self, _: &RatingChange) -> io::Result<()> {
+        match self.0.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |room| room.checked_sub(1)) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(io::Error::other("disk full")),
+        }
+    }
+
+    fn load_ratings(&self) -> io::Result<Vec<RatingChange>> {
+        NullStore.load_ratings()
+    }
+}
+
+#[test]
+fn glickmans_example() {
+    let player = rating(1500.0, 200.0);
+    let games = [(rating(1400.0, 30.0), 1.0), (rating(1550.0, 100.0), 0.0), (rating(1700.0, 300.0), 0.0)];
+    let updated = player.update(&games);
+    assert!((updated.rating - 1464.06).abs() < 0.01, "{:?}", updated);
+    assert!((updated.deviation - 151.52).abs() < 0.01, "{:?}", updated);
+    assert!((updated.volatility - 0.05999).abs() < 0.00001, "{:?}", updated);
+}
+
+#[test]
+fn deviation_grows_while_a_player_is_away() {
+    let ratings = Ratings::new(Vec::new());
+    let now = Utc::now();
+    let [white, _] = ratings
+        .rate_game(&NullStore, "game", Pool::Blitz, ["a", "b"], 1.0, now)
+        .unwrap();
+    assert!(white.rating.rating > 1500.0);
+
+    let same_day = ratings.rating("a", Pool::Blitz, now);
+    let year_later = ratings.rating("a", Pool::Blitz, now + Duration::days(365));
+    assert_eq!(same_day.rating, year_later.rating);
+    assert!(year_later.deviation > same_day.deviation);
+    assert!(ratings.rating("a", Pool::Blitz, now + Duration::days(100_000)).deviation <= DEFAULT_DEVIATION);
+
+    // Other pools are untouched
+    assert_eq!(ratings.rating("a", Pool::Rapid, now), Rating::default());
+}
+
+#[test]
+fn ratings_change_only_once_both_are_stored() {
+    let ratings = Ratings::new(Vec::new());
+    let now = Utc::now();
+    // White's change is stored, Black's is not
+    assert!(ratings.rate_game(&FullDisk(AtomicUsize::new(1)), "game", Pool::Blitz, ["a", "b"], 1.0, now).is_err());
+    for player in ["a", "b"] {
+        assert_eq!(ratings.rating(player, Pool::Blitz, now), Rating::default());
+        assert!(ratings.history(player).is_empty());
+    }
+
+    ratings.rate_game(&FullDisk(AtomicUsize::new(2)), "game", Pool::Blitz, ["a", "b"], 1.0, now).unwrap();
+    assert_eq!((ratings.history("a").len(), ratings.history("b").len()), (1, 1));
+}
+
+#[test]
+fn pools_by_expected_game_length() {
+    let minutes = |minutes: u64, increment_seconds: u64| Pool::of(&TimeControl::fischer(minutes * 60_000, increment_seconds * 1000));
+    assert_eq!(minutes(1, 0), Pool::Bullet);
+    assert_eq!(minutes(2, 1), Pool::Bullet);
+    assert_eq!(minutes(3, 0), Pool::Blitz);
+    assert_eq!(minutes(3, 2), Pool::Blitz);
+    assert_eq!(minutes(10, 0), Pool::Rapid);
+    assert_eq!(minutes(15, 10), Pool::Rapid);
+    assert_eq!(minutes(30, 0), Pool::Classical);
+    assert_eq!(Pool::of(&TimeControl::correspondence(86_400_000)), Pool::Correspondence);
+
+    // Every stage reached in 40 moves counts, with its own increment
+    let staged = |mode: ClockMode, stages: Vec<Stage>| Pool::of(&TimeControl { mode, stages });
+    let stage = |moves: Option<u32>, seconds: u64, increment_seconds: u64| Stage {
+        moves,
+        time_ms: seconds * 1000,
+        increment_ms: increment_seconds * 1000,
+    };
+    assert_eq!(staged(ClockMode::Fischer, vec![stage(Some(10), 120, 0), stage(None, 60, 5)]), Pool::Blitz);
+    assert_eq!(staged(ClockMode::Fischer, vec![stage(Some(40), 5400, 30), stage(None, 1800, 30)]), Pool::Classical);
+    assert_eq!(staged(ClockMode::Fischer, vec![stage(Some(40), 120, 0), stage(None, 3600, 0)]), Pool::Bullet);
+    // and a delay counts like an increment, as a move taking that long gets all of it
+    assert_eq!(staged(ClockMode::Delay, vec![stage(None, 120, 2)]), Pool::Blitz);
+    assert_eq!(staged(ClockMode::Bronstein, vec![stage(None, 120, 2)]), minutes(2, 2));
+}
+
+#[actix_rt::test]
+async fn rated_games_change_both_players_ratings() {
+    let app_state = test_app_state();
+    let credentials = |username: &str| Credentials {
+        username: username.to_string(),
+        password: "long enough".to_string(),
+    };
+    let white = app_state.accounts.register(&NullStore, &credentials("tal")).unwrap();
+    let black = app_state.accounts.register(&NullStore, &credentials("petrosian")).unwrap();
+    let mut clients = vec![
+        TestClient::connect_as(&app_state, ProtocolVersion::V2, Some(white.clone()), None),
+        TestClient::connect_as(&app_state, ProtocolVersion::V2, Some(black.clone()), None),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+    ];
+    pump(&mut clients);
+
+    // Guests can neither create nor join rated games
+    let reply = exchange(&mut clients, 2, json!({"type": "create", "rated": true}));
+    assert_eq!(reply["code"], "login_required", "{}", reply);
+
+    let create = json!({"type": "create", "rated": true, "color_preference": "white", "start_time_minutes": 5, "increment_seconds": 3});
+    let reply = exchange(&mut clients, 0, create);
+    let game_id = reply["game_id"].as_str().unwrap().to_string();
+    let reply = exchange(&mut clients, 2, json!({"type": "join", "game_id": game_id}));
+    assert_eq!(reply["code"], "login_required", "{}", reply);
+
+    let reply = exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    assert_eq!(reply["type"], "joined", "{}", reply);
+    assert_eq!((reply["rated"].as_bool(), reply["white_rating"].as_u64(), reply["black_rating"].as_u64()), (Some(true), Some(1500), Some(1500)));
+
+    exchange(&mut clients, 0, json!({"type": "move", "uci": "f2f3"}));
+    exchange(&mut clients, 1, json!({"type": "move", "uci": "e7e5"}));
+    exchange(&mut clients, 0, json!({"type": "move", "uci": "g2g4"}));
+    let reply = exchange(&mut clients, 1, json!({"type": "move", "uci": "d8h4"}));
+    assert_eq!(reply["type"], "move_made", "{}", reply);
+
+    let now = Utc::now();
+    assert!(app_state.ratings.rating(&white.id, Pool::Blitz, now).rating < 1500.0);
+    assert!(app_state.ratings.rating(&black.id, Pool::Blitz, now).rating > 1500.0);
+    assert_eq!(app_state.ratings.rating(&black.id, Pool::Rapid, now), Rating::default());
+
+    let app = http::init_service(
+        App::new()
+            .app_data(app_state.clone())
+            .service(web::resource("/users/{username}/ratings").route(web::get().to(user_ratings))),
+    )
+    .await;
+    let request = TestRequest::get().uri("/users/Petrosian/ratings").to_request();
+    let body: Value = http::call_and_read_body_json(&app, request).await;
+    assert_eq!(body["user"]["username"], "petrosian");
+    assert_eq!(body["ratings"]["blitz"]["provisional"], true);
+    assert_eq!(body["history"][0]["game_id"], game_id.as_str());
+    let request = TestRequest::get().uri("/users/nobody/ratings").to_request();
+    assert_eq!(http::call_service(&app, request).await.status(), 404);
+}
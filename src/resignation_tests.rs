@@ -0,0 +1,89 @@
+//! Tests for resigning and for draw offers being made, accepted, declined
+//! and let lapse.
+
+use serde_json::json;
+
+use crate::protocol::ProtocolVersion;
+use crate::test_support::{exchange, pump, test_app_state, TestClient};
+
+#[actix_rt::test]
+async fn draw_offers_are_accepted_declined_or_lapse() {
+    let app_state = test_app_state();
+    let mut clients = vec![
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+    ];
+    pump(&mut clients);
+
+    let game_id = exchange(&mut clients, 0, json!({"type": "create", "color_preference": "white"}))["game_id"].clone();
+    let reply = exchange(&mut clients, 0, json!({"type": "offer_draw"}));
+    assert_eq!(reply["code"], "game_not_started", "{}", reply);
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    exchange(&mut clients, 2, json!({"type": "watch", "game_id": game_id}));
+
+    let reply = exchange(&mut clients, 2, json!({"type": "offer_draw"}));
+    assert_eq!(reply["code"], "not_a_player", "{}", reply);
+    let reply = exchange(&mut clients, 0, json!({"type": "accept_draw"}));
+    assert_eq!(reply["code"], "no_draw_offer", "{}", reply);
+
+    // An offer is seen by everyone, can only be made once and is declined by the opponent
+    let reply = exchange(&mut clients, 0, json!({"type": "offer_draw"}));
+    assert_eq!((reply["type"].as_str(), reply["color"].as_str()), (Some("draw_offered"), Some("white")), "{}", reply);
+    assert!(clients[1..].iter().all(|client| client.messages.iter().any(|message| message["type"] == "draw_offered")));
+    let reply = exchange(&mut clients, 0, json!({"type": "offer_draw"}));
+    assert_eq!(reply["code"], "draw_already_offered", "{}", reply);
+    let reply = exchange(&mut clients, 0, json!({"type": "decline_draw"}));
+    assert_eq!(reply["code"], "no_draw_offer", "the offer is not the offerer's to decline: {}", reply);
+    let reply = exchange(&mut clients, 1, json!({"type": "decline_draw"}));
+    assert_eq!((reply["type"].as_str(), reply["color"].as_str()), (Some("draw_declined"), Some("black")), "{}", reply);
+    let reply = exchange(&mut clients, 1, json!({"type": "accept_draw"}));
+    assert_eq!(reply["code"], "no_draw_offer", "{}", reply);
+
+    // The opponent moving instead of answering lets the offer lapse
+    exchange(&mut clients, 0, json!({"type": "offer_draw"}));
+    exchange(&mut clients, 0, json!({"type": "move", "uci": "e2e4"}));
+    exchange(&mut clients, 1, json!({"type": "move", "uci": "e7e5"}));
+    let reply = exchange(&mut clients, 1, json!({"type": "accept_draw"}));
+    assert_eq!(reply["code"], "no_draw_offer", "{}", reply);
+
+    // Offering while the opponent's offer stands accepts it
+    exchange(&mut clients, 1, json!({"type": "offer_draw"}));
+    let reply = exchange(&mut clients, 0, json!({"type": "offer_draw"}));
+    assert_eq!(reply["type"], "game_over", "{}", reply);
+    assert_eq!((reply["game_status"].as_str(), reply["termination"].as_str()), (Some("draw"), Some("draw_agreement")));
+    assert_eq!(app_state.find_game_record(game_id.as_str().unwrap()).unwrap().result.as_deref(), Some("1/2-1/2"));
+
+    let reply = exchange(&mut clients, 1, json!({"type": "resign"}));
+    assert_eq!(reply["code"], "game_over", "{}", reply);
+}
+
+#[actix_rt::test]
+async fn resigning_ends_the_game_for_everyone() {
+    let app_state = test_app_state();
+    let mut clients = vec![
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+    ];
+    pump(&mut clients);
+
+    let reply = exchange(&mut clients, 0, json!({"type": "resign"}));
+    assert_eq!(reply["code"], "not_in_game", "{}", reply);
+
+    let game_id = exchange(&mut clients, 0, json!({"type": "create", "color_preference": "black"}))["game_id"].clone();
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    exchange(&mut clients, 1, json!({"type": "offer_draw"}));
+
+    let reply = exchange(&mut clients, 0, json!({"type": "resign"}));
+    assert_eq!(reply["type"], "game_over", "{}", reply);
+    assert_eq!((reply["color"].as_str(), reply["game_status"].as_str()), (Some("black"), Some("white_wins")));
+    assert_eq!(reply["termination"], "resignation");
+    assert_eq!(clients[1].messages.first(), Some(&reply));
+
+    // Nothing more can happen in the game, a standing draw offer included
+    for message in [json!({"type": "accept_draw"}), json!({"type": "move", "uci": "e2e4"})] {
+        let reply = exchange(&mut clients, 1, message.clone());
+        assert_eq!(reply["code"], "game_over", "{} answered with {}", message, reply);
+    }
+    assert_eq!(app_state.find_game_record(game_id.as_str().unwrap()).unwrap().result.as_deref(), Some("1-0"));
+}
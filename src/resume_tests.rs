@@ -0,0 +1,91 @@
+//! Tests for players reclaiming their seats after a dropped connection, and
+//! for games nobody comes back to being removed.
+
+use actix_http::ws::Message;
+use serde_json::json;
+
+use crate::protocol::ProtocolVersion;
+use crate::test_support::{exchange, pump, test_app_state, test_app_state_from, FakeTime, TestClient};
+
+#[actix_rt::test]
+async fn players_rejoin_their_seat_with_its_token() {
+    let time = FakeTime::new();
+    let app_state = test_app_state_from(None, time.clone());
+    let mut clients: Vec<_> = (0..2).map(|_| TestClient::connect(&app_state, ProtocolVersion::V2)).collect();
+    pump(&mut clients);
+
+    let create = json!({"type": "create", "color_preference": "white", "start_time_minutes": 5, "increment_seconds": 2});
+    let game_id = exchange(&mut clients, 0, create)["game_id"].as_str().unwrap().to_string();
+    let black_token = exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}))["resume_token"].clone();
+    time.advance(3000);
+    exchange(&mut clients, 0, json!({"type": "move", "uci": "e2e4"}));
+    time.advance(1000);
+    exchange(&mut clients, 1, json!({"type": "move", "uci": "e7e5"}));
+    let last_move = exchange(&mut clients, 0, json!({"type": "move", "uci": "g1f3"}));
+
+    // Black drops while their clock runs
+    clients[1].send(Message::Close(None));
+    pump(&mut clients);
+    time.advance(500);
+    clients[1] = TestClient::connect(&app_state, ProtocolVersion::V2);
+    clients.push(TestClient::connect(&app_state, ProtocolVersion::V2));
+    pump(&mut clients);
+
+    // Only the token of that seat in that game gets it back
+    let other_game_token = exchange(&mut clients, 2, json!({"type": "create"}))["resume_token"].clone();
+    for resume_token in [json!("not-a-token"), json!(uuid::Uuid::new_v4().to_string()), other_game_token] {
+        let reply = exchange(&mut clients, 1, json!({"type": "rejoin", "game_id": game_id, "resume_token": resume_token}));
+        assert_eq!(reply["code"], "invalid_resume_token", "{}", reply);
+    }
+
+    let reply = exchange(&mut clients, 1, json!({"type": "rejoin", "game_id": game_id, "resume_token": black_token}));
+    assert_eq!(reply["type"], "rejoined", "{}", reply);
+    assert_eq!((reply["color"].as_str(), reply["active_color"].as_str()), (Some("black"), Some("black")));
+    assert_eq!(reply["game_status"], "black_turn");
+    assert_eq!(reply["fen"], last_move["fen"]);
+    assert_eq!(reply["moves"], json!(["e2e4", "e7e5", "g1f3"]));
+    assert_eq!(reply["white_time_ms"], last_move["white_time_ms"]);
+    assert_eq!(reply["black_time_ms"], last_move["black_time_ms"].as_u64().unwrap() - 500);
+    assert_eq!(reply["increment_ms"], 2000);
+
+    let player_rejoined = clients[0].messages.iter().find(|message| message["type"] == "player_rejoined").expect("no player_rejoined");
+    assert_eq!(player_rejoined["color"], "black", "{}", player_rejoined);
+    assert_eq!(player_rejoined["black_time_ms"], reply["black_time_ms"]);
+
+    // And plays on from it
+    let reply = exchange(&mut clients, 1, json!({"type": "move", "uci": "b8c6"}));
+    assert_eq!(reply["type"], "move_made", "{}", reply);
+}
+
+#[actix_rt::test]
+async fn only_the_latest_removal_timer_removes_a_game() {
+    let app_state = test_app_state();
+    let mut clients = vec![TestClient::connect(&app_state, ProtocolVersion::V2)];
+    pump(&mut clients);
+
+    let reply = exchange(&mut clients, 0, json!({"type": "create"}));
+    let game_id = reply["game_id"].as_str().unwrap().to_string();
+    let resume_token = reply["resume_token"].clone();
+    let timers = || app_state.removal_timers.lock().unwrap().get(&game_id).copied();
+
+    // Leaving sets the first timer, and coming back and leaving again a second one
+    clients[0].send(Message::Close(None));
+    pump(&mut clients);
+    assert_eq!(timers(), Some(1));
+    clients.push(TestClient::connect(&app_state, ProtocolVersion::V2));
+    pump(&mut clients);
+    let reply = exchange(&mut clients, 1, json!({"type": "rejoin", "game_id": game_id, "resume_token": resume_token}));
+    assert_eq!(reply["type"], "rejoined", "{}", reply);
+    clients[1].send(Message::Close(None));
+    pump(&mut clients);
+    assert_eq!(timers(), Some(2));
+
+    // The first timer going off early in the second grace period leaves the game alone
+    app_state.remove_abandoned_game(&game_id, 1);
+    assert!(app_state.games.lock().unwrap().contains_key(&game_id));
+
+    app_state.remove_abandoned_game(&game_id, 2);
+    assert!(!app_state.games.lock().unwrap().contains_key(&game_id));
+    assert_eq!(timers(), None);
+    assert_eq!(app_state.find_game_record(&game_id).unwrap().result, None);
+}
@@ -0,0 +1,69 @@
+//! Tests for spectators: what watchers are sent, what they may not do, and
+//! how the players and other watchers are told they come and go.
+
+use actix_http::ws::Message;
+use serde_json::{json, Value};
+
+use crate::protocol::ProtocolVersion;
+use crate::test_support::{exchange, pump, test_app_state, TestClient};
+
+/// The message of a type a client got, if any
+fn received<'a>(client: &'a TestClient, message_type: &str) -> Option<&'a Value> {
+    client.messages.iter().find(|message| message["type"] == message_type)
+}
+
+#[actix_rt::test]
+async fn spectators_follow_games_without_playing_them() {
+    let app_state = test_app_state();
+    let mut clients: Vec<_> = (0..4).map(|_| TestClient::connect(&app_state, ProtocolVersion::V2)).collect();
+    pump(&mut clients);
+
+    let game_id = exchange(&mut clients, 0, json!({"type": "create", "color_preference": "white"}))["game_id"].clone();
+    let reply = exchange(&mut clients, 2, json!({"type": "watch", "game_id": game_id}));
+    assert_eq!((reply["type"].as_str(), reply["spectator_count"].as_u64()), (Some("watching"), Some(1)), "{}", reply);
+    assert_eq!(received(&clients[0], "spectator_joined").expect("no spectator_joined")["spectator_count"], 1);
+
+    // The opponent arriving is news to the watcher too, with the count of watchers
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    for index in [0, 2] {
+        let player_joined = received(&clients[index], "player_joined").expect("no player_joined");
+        assert_eq!(player_joined["spectator_count"], 1, "{}", player_joined);
+    }
+
+    let reply = exchange(&mut clients, 3, json!({"type": "watch", "game_id": game_id}));
+    assert_eq!(reply["spectator_count"], 2, "{}", reply);
+    for index in [0, 1, 2] {
+        assert_eq!(received(&clients[index], "spectator_joined").expect("no spectator_joined")["spectator_count"], 2);
+    }
+
+    // Watchers see the moves and clocks of the game
+    exchange(&mut clients, 0, json!({"type": "move", "uci": "e2e4"}));
+    for index in [2, 3] {
+        assert_eq!(received(&clients[index], "move_made").expect("no move_made")["san"], "e4");
+    }
+    exchange(&mut clients, 2, json!({"type": "time_sync", "game_id": game_id}));
+    for index in [2, 3] {
+        assert_eq!(received(&clients[index], "time_sync").expect("no time_sync")["active_color"], "black");
+    }
+
+    // But cannot play, resign or offer draws in it
+    for message in [json!({"type": "move", "uci": "e7e5"}), json!({"type": "resign"}), json!({"type": "offer_draw"})] {
+        let reply = exchange(&mut clients, 2, message.clone());
+        assert_eq!(reply["code"], "not_a_player", "{}: {}", message, reply);
+        assert!(received(&clients[1], "draw_offered").is_none());
+    }
+    {
+        let games = app_state.games.lock().unwrap();
+        let game_state = &games[game_id.as_str().unwrap()];
+        assert_eq!((game_state.game_result, game_state.history.len()), (None, 1));
+    }
+
+    // Everyone left is told when a watcher goes
+    clients[3].send(Message::Close(None));
+    pump(&mut clients);
+    for index in [0, 1, 2] {
+        assert_eq!(received(&clients[index], "spectator_left").expect("no spectator_left")["spectator_count"], 1);
+    }
+    let reply = exchange(&mut clients, 2, json!({"type": "watch", "game_id": game_id}));
+    assert_eq!(reply["spectator_count"], 1, "{}", reply);
+}
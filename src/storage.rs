@@ -0,0 +1,379 @@
+use chess::{Board, ChessMove, Color, GameResult};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::accounts::Account;
+use crate::archive::GameRecord;
+use crate::models::{EngineKind, GameState, Termination};
+use crate::ratings::RatingChange;
+use crate::time_control::{Clock, TimeControl};
+use crate::validation;
+
+/// Durable record of every game, so games in progress survive a restart.
+///
+/// Stores only have to append events and hand them back in order; rebuilding
+/// the in-memory `GameState`s from them is done by `restore_games`.
+pub trait GameStore: Send + Sync {
+    /// Persist one event
+    fn record(&self, event: &GameEvent) -> io::Result<()>;
+
+    /// Every event recorded so far, oldest first
+    fn load(&self) -> io::Result<Vec<GameEvent>>;
+
+    /// Keep a finished or abandoned game in the archive
+    fn archive_game(&self, record: &GameRecord) -> io::Result<()>;
+
+    /// Every archived game, oldest first
+    fn load_archive(&self) -> io::Result<Vec<GameRecord>>;
+
+    /// Keep a newly registered user account
+    fn record_account(&self, account: &Account) -> io::Result<()>;
+
+    /// Every user account, oldest first
+    fn load_accounts(&self) -> io::Result<Vec<Account>>;
+
+    /// Keep a user's new rating after a rated game
+    fn record_rating(&self, change: &RatingChange) -> io::Result<()>;
+
+    /// Every rating change, oldest first
+    fn load_ratings(&self) -> io::Result<Vec<RatingChange>>;
+}
+
+/// Something that happened to a game
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum GameEvent {
+    Created {
+        game_id: String,
+        created_at: DateTime<Utc>,
+        start_fen: Option<String>,
+        initial_time_ms: u64,
+        increment_ms: u64,
+        /// The full time control, missing from games created before time
+        /// controls had stages, which used the base time and increment
+        #[serde(default)]
+        time_control: Option<TimeControl>,
+        /// Strength of the engine, which is seated like any player
+        #[serde(default)]
+        engine_level: Option<u8>,
+        #[serde(default)]
+        engine_kind: EngineKind,
+        /// Whether the result changes the players' ratings
+        #[serde(default)]
+        rated: bool,
+    },
+    /// A player took a seat and was issued a resume token for it
+    Seated {
+        game_id: String,
+        #[serde(with = "ColorDef")]
+        color: Color,
+        player_id: String,
+        resume_token: String,
+        /// When the seat was taken, missing from older logs
+        #[serde(default)]
+        at: Option<DateTime<Utc>>,
+    },
+    /// A player gave up their seat by creating or joining another game
+    Unseated {
+        game_id: String,
+        #[serde(with = "ColorDef")]
+        color: Color,
+    },
+    /// A move in UCI notation and both clocks right after it
+    Moved {
+        game_id: String,
+        uci: String,
+        white_time_ms: u64,
+        black_time_ms: u64,
+        at: DateTime<Utc>,
+    },
+    Finished {
+        game_id: String,
+        #[serde(with = "GameResultDef")]
+        result: GameResult,
+        termination: Option<Termination>,
+        white_time_ms: u64,
+        black_time_ms: u64,
+        at: DateTime<Utc>,
+    },
+    /// The game was dropped from memory and should not be restored
+    Removed { game_id: String },
+}
+
+impl GameEvent {
+    pub fn game_id(&self) -> &str {
+        match self {
+            GameEvent::Created { game_id, .. }
+            | GameEvent::Seated { game_id, .. }
+            | GameEvent::Unseated { game_id, .. }
+            | GameEvent::Moved { game_id, .. }
+            | GameEvent::Finished { game_id, .. }
+            | GameEvent::Removed { game_id } => game_id,
+        }
+    }
+
+    /// A game that was just created, before anyone is seated
+    pub fn created(game_id: &str, game_state: &GameState) -> Self {
+        let time_control = game_state.clock.control();
+        GameEvent::Created {
+            game_id: game_id.to_string(),
+            created_at: game_state.created_at,
+            start_fen: game_state.start_fen.clone(),
+            initial_time_ms: time_control.initial_time_ms(),
+            increment_ms: time_control.stages[0].increment_ms,
+            time_control: Some(time_control.clone()),
+            engine_level: game_state.engine_level,
+            engine_kind: game_state.engine_kind,
+            rated: game_state.rated,
+        }
+    }
+
+    /// Result of a game that just ended
+    pub fn finished(game_id: &str, game_state: &GameState) -> Option<Self> {
+        Some(GameEvent::Finished {
+            game_id: game_id.to_string(),
+            result: game_state.game_result?,
+            termination: game_state.termination,
+            white_time_ms: game_state.clock.time_ms(Color::White),
+            black_time_ms: game_state.clock.time_ms(Color::Black),
+            at: Utc::now(),
+        })
+    }
+}
+
+// `chess` types have no serde support of their own
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Color", rename_all = "snake_case")]
+enum ColorDef {
+    White,
+    Black,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "GameResult", rename_all = "snake_case")]
+enum GameResultDef {
+    WhiteCheckmates,
+    WhiteResigns,
+    BlackCheckmates,
+    BlackResigns,
+    Stalemate,
+    DrawAccepted,
+    DrawDeclared,
+}
+
+/// Append-only JSON logs, one entry per line, under the data directory:
+/// game events in `games.jsonl`, archived games in `archive.jsonl`, user
+/// accounts in `accounts.jsonl` and rating changes in `ratings.jsonl`
+pub struct FileGameStore {
+    events: JsonLog,
+    archive: JsonLog,
+    accounts: JsonLog,
+    ratings: JsonLog,
+}
+
+impl FileGameStore {
+    pub fn open(data_dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(data_dir)?;
+        info!("Storing games in {}", data_dir.display());
+
+        Ok(FileGameStore {
+            events: JsonLog::open(data_dir.join("games.jsonl"))?,
+            archive: JsonLog::open(data_dir.join("archive.jsonl"))?,
+            accounts: JsonLog::open(data_dir.join("accounts.jsonl"))?,
+            ratings: JsonLog::open(data_dir.join("ratings.jsonl"))?,
+        })
+    }
+}
+
+impl GameStore for FileGameStore {
+    fn record(&self, event: &GameEvent) -> io::Result<()> {
+        self.events.append(event)
+    }
+
+    fn load(&self) -> io::Result<Vec<GameEvent>> {
+        self.events.read_all()
+    }
+
+    fn archive_game(&self, record: &GameRecord) -> io::Result<()> {
+        self.archive.append(record)
+    }
+
+    fn load_archive(&self) -> io::Result<Vec<GameRecord>> {
+        self.archive.read_all()
+    }
+
+    fn record_account(&self, account: &Account) -> io::Result<()> {
+        self.accounts.append(account)
+    }
+
+    fn load_accounts(&self) -> io::Result<Vec<Account>> {
+        self.accounts.read_all()
+    }
+
+    fn record_rating(&self, change: &RatingChange) -> io::Result<()> {
+        self.ratings.append(change)
+    }
+
+    fn load_ratings(&self) -> io::Result<Vec<RatingChange>> {
+        self.ratings.read_all()
+    }
+}
+
+struct JsonLog {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl JsonLog {
+    fn open(path: PathBuf) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(JsonLog {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    fn append<T: Serialize>(&self, entry: &T) -> io::Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+
+        // One unbuffered write per entry, so a crashed process can at worst cut off the last line
+        self.file.lock().unwrap().write_all(line.as_bytes())
+    }
+
+    fn read_all<T: DeserializeOwned>(&self) -> io::Result<Vec<T>> {
+        let reader = BufReader::new(File::open(&self.path)?);
+        let mut entries = Vec::new();
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!("Skipping unreadable entry on line {} of {}: {}", index + 1, self.path.display(), e),
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Rebuild every game that was not removed from the recorded events.
+///
+/// Restored games keep their seats and resume tokens but their clocks stay
+/// stopped until both players have reconnected, except in correspondence
+/// games: their clocks carry on from the last move as of `now`.
+pub fn restore_games(events: Vec<GameEvent>, now: Instant) -> HashMap<String, GameState> {
+    let mut games: HashMap<String, GameState> = HashMap::new();
+    // When the side to move's clock started, for correspondence games
+    let mut turn_started_at: HashMap<String, DateTime<Utc>> = HashMap::new();
+
+    for event in events {
+        let game_id = event.game_id().to_string();
+
+        if let GameEvent::Created { created_at, start_fen, initial_time_ms, increment_ms, time_control, engine_level, engine_kind, rated, .. } = event {
+            let start_position = match start_fen.as_deref().map(Board::from_str) {
+                Some(Ok(board)) => board,
+                Some(Err(_)) => {
+                    warn!("Cannot restore game {}: invalid starting position", game_id);
+                    continue;
+                }
+                None => Board::default(),
+            };
+
+            turn_started_at.insert(game_id.clone(), created_at);
+            let clock = Clock::new(time_control.unwrap_or_else(|| TimeControl::fischer(initial_time_ms, increment_ms)));
+            games.insert(game_id, GameState {
+                start_halfmove_clock: start_fen.as_deref().map_or(0, validation::fen_halfmove_clock),
+                created_at,
+                engine_level,
+                engine_kind,
+                rated,
+                ..GameState::new(start_position, start_fen, clock)
+            });
+            continue;
+        }
+
+        let game_state = match games.get_mut(&game_id) {
+            Some(game_state) => game_state,
+            None => continue,
+        };
+
+        match event {
+            GameEvent::Created { .. } => {}
+            GameEvent::Seated { color, player_id, resume_token, at, .. } => {
+                if let Some(at) = at {
+                    turn_started_at.insert(game_id.clone(), at);
+                }
+                // The old connection ID keeps the seat reserved until its resume token is used
+                match color {
+                    Color::White => {
+                        game_state.white_player = Some(player_id);
+                        game_state.white_resume_token = Some(resume_token);
+                    }
+                    Color::Black => {
+                        game_state.black_player = Some(player_id);
+                        game_state.black_resume_token = Some(resume_token);
+                    }
+                }
+            }
+            GameEvent::Unseated { color, .. } => match color {
+                Color::White => {
+                    game_state.white_player = None;
+                    game_state.white_resume_token = None;
+                }
+                Color::Black => {
+                    game_state.black_player = None;
+                    game_state.black_resume_token = None;
+                }
+            },
+            GameEvent::Moved { uci, white_time_ms, black_time_ms, at, .. } => {
+                let before = game_state.game.current_position();
+                let chess_move = match ChessMove::from_str(&uci) {
+                    Ok(chess_move) if game_state.game.make_move(chess_move) => chess_move,
+                    _ => {
+                        warn!("Cannot restore game {}: illegal move {}", game_id, uci);
+                        games.remove(&game_id);
+                        continue;
+                    }
+                };
+                game_state.clock.restore_move(before.side_to_move(), white_time_ms, black_time_ms);
+                game_state.active_player = Some(game_state.game.side_to_move());
+                game_state.push_history(&before, chess_move, at);
+                turn_started_at.insert(game_id.clone(), at);
+            }
+            GameEvent::Finished { result, termination, white_time_ms, black_time_ms, .. } => {
+                game_state.game_result = Some(result);
+                game_state.termination = termination;
+                game_state.clock.set_times(white_time_ms, black_time_ms);
+            }
+            GameEvent::Removed { .. } => {
+                games.remove(&game_id);
+            }
+        }
+    }
+
+    for (game_id, game_state) in games.iter_mut() {
+        let started = game_state.white_player.is_some() && game_state.black_player.is_some();
+        if game_state.is_correspondence() && started && game_state.game_result.is_none() {
+            let elapsed = (Utc::now() - turn_started_at[game_id]).to_std().unwrap_or_default();
+            let side_to_move = game_state.game.side_to_move();
+            game_state.clock.start_after(side_to_move, now, elapsed);
+            info!("Correspondence game {} carries on, {:?} to move for {:?}", game_id, side_to_move, elapsed);
+        }
+    }
+
+    info!("Restored {} games from storage", games.len());
+    games
+}
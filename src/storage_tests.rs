@@ -0,0 +1,62 @@
+//! Tests for rebuilding games from the events the store kept of them.
+
+use chess::Color;
+use serde_json::json;
+use std::fmt::Debug;
+use std::time::Instant;
+
+use crate::models::{GameState, Termination};
+use crate::protocol::ProtocolVersion;
+use crate::storage::restore_games;
+use crate::test_support::{exchange, pump, test_app_state_storing, MemoryStore, TestClient};
+
+/// Everything about a game that is kept across a restart
+fn snapshot(game_state: &GameState) -> impl PartialEq + Debug {
+    let history: Vec<_> = game_state.history.iter().map(|entry| entry.fen.clone()).collect();
+    (
+        game_state.game.current_position(),
+        (game_state.start_position, game_state.start_fen.clone(), game_state.start_halfmove_clock),
+        (game_state.white_player.clone(), game_state.black_player.clone()),
+        (game_state.white_resume_token.clone(), game_state.black_resume_token.clone()),
+        (game_state.game_result, game_state.termination, game_state.active_player),
+        (game_state.clock.control().clone(), game_state.created_at, history),
+        (game_state.engine_level, game_state.engine_kind, game_state.rated),
+    )
+}
+
+#[actix_rt::test]
+async fn restored_games_match_the_games_played() {
+    let app_state = test_app_state_storing(Box::<MemoryStore>::default());
+    let mut clients: Vec<_> = (0..5).map(|_| TestClient::connect(&app_state, ProtocolVersion::V2)).collect();
+    pump(&mut clients);
+
+    // One game in progress from a set-up position, one resigned and one nobody joined
+    let create = json!({"type": "create", "color_preference": "black", "start_fen": "4k3/8/8/8/8/8/4P3/4K1N1 w - - 40 30", "start_time_minutes": 5, "increment_seconds": 3});
+    let in_progress = exchange(&mut clients, 0, create)["game_id"].as_str().unwrap().to_string();
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": in_progress}));
+    exchange(&mut clients, 1, json!({"type": "move", "uci": "g1f3"}));
+    exchange(&mut clients, 0, json!({"type": "move", "uci": "e8d7"}));
+    exchange(&mut clients, 1, json!({"type": "move", "uci": "e2e4"}));
+
+    let resigned = exchange(&mut clients, 2, json!({"type": "create"}))["game_id"].as_str().unwrap().to_string();
+    exchange(&mut clients, 3, json!({"type": "join", "game_id": resigned}));
+    exchange(&mut clients, 2, json!({"type": "move", "uci": "d2d4"}));
+    exchange(&mut clients, 3, json!({"type": "resign"}));
+
+    let waiting = exchange(&mut clients, 4, json!({"type": "create", "days_per_move": 2}))["game_id"].as_str().unwrap().to_string();
+
+    let restored = restore_games(app_state.store.load().unwrap(), Instant::now());
+    let played = {
+        let games = app_state.games.lock().unwrap();
+        [&in_progress, &resigned, &waiting].map(|game_id| snapshot(&games[game_id]))
+    };
+    assert_eq!(restored.len(), 3);
+    assert_eq!([&in_progress, &resigned, &waiting].map(|game_id| snapshot(&restored[game_id])), played);
+
+    assert_eq!(restored[&in_progress].start_halfmove_clock, 40);
+    assert_eq!(restored[&in_progress].active_player, Some(Color::Black));
+    // The clocks wait for both players to come back
+    assert!(restored[&in_progress].is_paused());
+    assert_eq!(restored[&resigned].termination, Some(Termination::Resignation));
+    assert!(restored[&waiting].is_correspondence());
+}
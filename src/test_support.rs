@@ -0,0 +1,324 @@
+//! Helpers shared by the tests: an app state that stores nothing and clients
+//! that speak to real `ChessWebSocket` actors without a network.
+//!
+//! Each client is a real `ChessWebSocket` actor behind a `WebsocketContext`
+//! fed with masked client frames, so parsing, validation and the game logic
+//! all run exactly as they do for a browser.
+
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{Codec, Frame, Message};
+use actix_web::error::PayloadError;
+use actix_web::web::{self, Bytes, BytesMut};
+use actix_web_actors::ws;
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::{FutureExt, Stream, StreamExt};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::accounts::{Account, Accounts, User};
+use crate::archive::GameRecord;
+use crate::guests::GuestSigner;
+use crate::lobby::Lobby;
+use crate::models::legacy::LegacyServerMessage;
+use crate::models::ServerMessage;
+use crate::protocol::ProtocolVersion;
+use crate::ratings::{RatingChange, Ratings};
+use crate::storage::{GameEvent, GameStore};
+use crate::time_control::{RealTime, TimeSource};
+use crate::uci::UciPool;
+use crate::{AppState, ChessWebSocket};
+
+/// Keeps nothing, so tests do not touch the data directory
+pub(crate) struct NullStore;
+
+impl GameStore for NullStore {
+    fn record(&self, _: &GameEvent) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn load(&self) -> io::Result<Vec<GameEvent>> {
+        Ok(Vec::new())
+    }
+
+    fn archive_game(&self, _: &GameRecord) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn load_archive(&self) -> io::Result<Vec<GameRecord>> {
+        Ok(Vec::new())
+    }
+
+    fn record_account(&self, _: &Account) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn load_accounts(&self) -> io::Result<Vec<Account>> {
+        Ok(Vec::new())
+    }
+
+    fn record_rating(&self, _: &RatingChange) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn load_ratings(&self) -> io::Result<Vec<RatingChange>> {
+        Ok(Vec::new())
+    }
+}
+
+/// Keeps game events in memory, and like `NullStore` nothing else
+#[derive(Default)]
+pub(crate) struct MemoryStore {
+    events: Mutex<Vec<GameEvent>>,
+}
+
+impl GameStore for MemoryStore {
+    fn record(&self, event: &GameEvent) -> io::Result<()> {
+        self.events.lock().unwrap().push(event.clone());
+        Ok(())
+    }
+
+    fn load(&self) -> io::Result<Vec<GameEvent>> {
+        Ok(self.events.lock().unwrap().clone())
+    }
+
+    fn archive_game(&self, record: &GameRecord) -> io::Result<()> {
+        NullStore.archive_game(record)
+    }
+
+    fn load_archive(&self) -> io::Result<Vec<GameRecord>> {
+        NullStore.load_archive()
+    }
+
+    fn record_account(&self, account: &Account) -> io::Result<()> {
+        NullStore.record_account(account)
+    }
+
+    fn load_accounts(&self) -> io::Result<Vec<Account>> {
+        NullStore.load_accounts()
+    }
+
+    fn record_rating(&self, change: &RatingChange) -> io::Result<()> {
+        NullStore.record_rating(change)
+    }
+
+    fn load_ratings(&self) -> io::Result<Vec<RatingChange>> {
+        NullStore.load_ratings()
+    }
+}
+
+pub(crate) fn test_app_state() -> web::Data<AppState> {
+    test_app_state_with(None)
+}
+
+pub(crate) fn test_app_state_with(uci_engine: Option<UciPool>) -> web::Data<AppState> {
+    test_app_state_from(uci_engine, Arc::new(RealTime))
+}
+
+pub(crate) fn test_app_state_from(uci_engine: Option<UciPool>, time_source: Arc<dyn TimeSource>) -> web::Data<AppState> {
+    app_state(uci_engine, time_source, Box::new(NullStore))
+}
+
+/// App state whose game events go to `store`, for tests of restoring games
+pub(crate) fn test_app_state_storing(store: Box<dyn GameStore>) -> web::Data<AppState> {
+    app_state(None, Arc::new(RealTime), store)
+}
+
+fn app_state(uci_engine: Option<UciPool>, time_source: Arc<dyn TimeSource>, store: Box<dyn GameStore>) -> web::Data<AppState> {
+    web::Data::new(AppState {
+        games: Mutex::new(HashMap::new()),
+        connections: Mutex::new(HashMap::new()),
+        sessions: Mutex::new(HashMap::new()),
+        players: Mutex::new(HashMap::new()),
+        clocks: Mutex::new(HashMap::new()),
+        archive: Mutex::new(HashMap::new()),
+        abandoned_game_grace: Duration::from_secs(300),
+        removal_timers: Mutex::new(HashMap::new()),
+        automatic_draws: true,
+        store,
+        uci_engine,
+        reports: Mutex::new(HashMap::new()),
+        time_source,
+        accounts: Accounts::new(Vec::new()),
+        guests: GuestSigner::new(b"test key".to_vec()),
+        ratings: Ratings::new(Vec::new()),
+        lobby: Lobby::default(),
+    })
+}
+
+/// Time that stands still until it is advanced
+pub(crate) struct FakeTime {
+    start: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl FakeTime {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(FakeTime {
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        })
+    }
+
+    pub(crate) fn advance(&self, ms: u64) {
+        *self.elapsed.lock().unwrap() += Duration::from_millis(ms);
+    }
+}
+
+impl TimeSource for FakeTime {
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+}
+
+type ServerStream = Pin<Box<dyn Stream<Item = Result<Bytes, actix_web::Error>>>>;
+
+/// One connection, driven by hand instead of through an HTTP upgrade
+pub(crate) struct TestClient {
+    pub(crate) id: String,
+    protocol: ProtocolVersion,
+    input: UnboundedSender<Result<Bytes, PayloadError>>,
+    output: ServerStream,
+    codec: Codec,
+    received: BytesMut,
+    pub(crate) messages: Vec<Value>,
+    pub(crate) closed: bool,
+}
+
+impl TestClient {
+    pub(crate) fn connect(app_state: &web::Data<AppState>, protocol: ProtocolVersion) -> Self {
+        Self::connect_as(app_state, protocol, None, None)
+    }
+
+    /// Connect as a signed-in user or a guest, as `ws_index` does for a request with a session or guest cookie
+    pub(crate) fn connect_as(
+        app_state: &web::Data<AppState>,
+        protocol: ProtocolVersion,
+        user: Option<User>,
+        guest_id: Option<String>,
+    ) -> Self {
+        let id = uuid::Uuid::new_v4().to_string();
+        let (input, stream) = unbounded();
+        let actor = ChessWebSocket {
+            id: id.clone(),
+            app_state: app_state.clone(),
+            game_id: String::new(),
+            color: None,
+            protocol,
+            analysis: None,
+            user,
+            guest_id,
+        };
+
+        TestClient {
+            id,
+            protocol,
+            input,
+            output: Box::pin(ws::WebsocketContext::create(actor, stream)),
+            codec: Codec::new().client_mode(),
+            received: BytesMut::new(),
+            messages: Vec::new(),
+            closed: false,
+        }
+    }
+
+    pub(crate) fn send(&mut self, message: Message) {
+        let mut frame = BytesMut::new();
+        self.codec.encode(message, &mut frame).unwrap();
+        // The actor may have stopped after a close frame, in which case the frame is simply dropped
+        let _ = self.input.unbounded_send(Ok(frame.freeze()));
+    }
+
+    pub(crate) fn send_text(&mut self, text: String) {
+        self.send(Message::Text(text.into()));
+    }
+
+    /// Run the actor until it has nothing more to say, returning whether it said anything
+    fn poll(&mut self) -> bool {
+        let mut progressed = false;
+        while !self.closed {
+            match self.output.next().now_or_never() {
+                Some(Some(Ok(bytes))) => {
+                    progressed = true;
+                    self.received.extend_from_slice(&bytes);
+                    self.decode_frames();
+                }
+                Some(Some(Err(e))) => panic!("connection {} failed: {}", self.id, e),
+                Some(None) => self.closed = true,
+                None => break,
+            }
+        }
+        progressed
+    }
+
+    fn decode_frames(&mut self) {
+        while let Some(frame) = self.codec.decode(&mut self.received).unwrap() {
+            if let Frame::Text(text) = frame {
+                let text = std::str::from_utf8(&text).expect("server sent invalid UTF-8");
+                self.messages.push(check_server_message(text, self.protocol));
+            }
+        }
+    }
+}
+
+/// Every frame the server sends must be a well formed message of the connection's protocol version
+fn check_server_message(text: &str, protocol: ProtocolVersion) -> Value {
+    match protocol {
+        ProtocolVersion::V1 => {
+            serde_json::from_str::<LegacyServerMessage>(text)
+                .unwrap_or_else(|e| panic!("not a version 1 message ({}): {}", e, text));
+        }
+        ProtocolVersion::V2 => {
+            serde_json::from_str::<ServerMessage>(text)
+                .unwrap_or_else(|e| panic!("not a version 2 message ({}): {}", e, text));
+        }
+    }
+    serde_json::from_str(text).unwrap()
+}
+
+/// Deliver everything sent so far, including broadcasts between connections.
+///
+/// An actor that sends itself a message while handling a frame only picks it
+/// up on its next poll, so one quiet round is not enough to call it done.
+pub(crate) fn pump(clients: &mut [TestClient]) {
+    let mut quiet_rounds = 0;
+    while quiet_rounds < 2 {
+        let mut progressed = false;
+        for client in clients.iter_mut() {
+            progressed |= client.poll();
+        }
+        quiet_rounds = if progressed { 0 } else { quiet_rounds + 1 };
+    }
+}
+
+/// Send one client a message and deliver everything that follows, returning
+/// the first message the client got back. Every client's earlier messages are
+/// cleared first, so what they hold afterwards is what this message caused.
+pub(crate) fn exchange(clients: &mut [TestClient], index: usize, message: Value) -> Value {
+    for client in clients.iter_mut() {
+        client.messages.clear();
+    }
+    clients[index].send_text(message.to_string());
+    pump(clients);
+    clients[index].messages.first().cloned().expect("no reply")
+}
+
+/// Wait for the next `move_made`, giving the engine time to think
+pub(crate) async fn wait_for_move(clients: &mut [TestClient]) -> Value {
+    wait_for(clients, 0, "move_made").await
+}
+
+/// Wait for the next message of a type, leaving the others for later
+pub(crate) async fn wait_for(clients: &mut [TestClient], index: usize, message_type: &str) -> Value {
+    for _ in 0..500 {
+        pump(clients);
+        if let Some(position) = clients[index].messages.iter().position(|message| message["type"] == message_type) {
+            return clients[index].messages.remove(position);
+        }
+        actix_rt::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("no {} in {:?}", message_type, clients[index].messages);
+}
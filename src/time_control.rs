@@ -0,0 +1,358 @@
+//! Time controls and the chess clock that keeps them.
+//!
+//! A `TimeControl` is one or more stages, such as 90 minutes for the first 40
+//! moves and then 30 minutes for the rest of the game, and a mode that says
+//! what happens after every move: a Fischer increment, a Bronstein delay or a
+//! simple (US) delay. Correspondence games instead give every move the same
+//! number of days. A `Clock` keeps both players' time under one. It never
+//! reads the time itself; every call is given `now`, which the server takes
+//! from a `TimeSource` so tests can move time forward by hand.
+
+use chess::Color;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// What a player gets for every move
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockMode {
+    /// The increment is added after every move, however long it took
+    #[default]
+    Fischer,
+    /// The time a move took is given back after it, up to the delay
+    Bronstein,
+    /// The clock only starts counting down once the delay has passed
+    Delay,
+    /// Every move gets the stage's time afresh; whatever is left is not kept
+    Correspondence,
+}
+
+/// One stage of a time control
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Stage {
+    /// Moves each player makes in this stage. The last stage lasts for the
+    /// rest of the game, or starts over every this many moves if it is set.
+    pub moves: Option<u32>,
+    /// Time added to each clock when the stage starts
+    pub time_ms: u64,
+    /// The increment, or the delay in the delay modes
+    pub increment_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TimeControl {
+    #[serde(default)]
+    pub mode: ClockMode,
+    /// At least one stage, in the order they are played
+    pub stages: Vec<Stage>,
+}
+
+impl TimeControl {
+    /// A single stage with a Fischer increment, as games were created before
+    /// time controls had stages
+    pub fn fischer(time_ms: u64, increment_ms: u64) -> Self {
+        TimeControl {
+            mode: ClockMode::Fischer,
+            stages: vec![Stage {
+                moves: None,
+                time_ms,
+                increment_ms,
+            }],
+        }
+    }
+
+    /// A correspondence game with this much time for every move
+    pub fn correspondence(time_per_move_ms: u64) -> Self {
+        TimeControl {
+            mode: ClockMode::Correspondence,
+            stages: vec![Stage {
+                moves: None,
+                time_ms: time_per_move_ms,
+                increment_ms: 0,
+            }],
+        }
+    }
+
+    pub fn is_correspondence(&self) -> bool {
+        self.mode == ClockMode::Correspondence
+    }
+
+    /// Time each player starts with
+    pub fn initial_time_ms(&self) -> u64 {
+        self.stages[0].time_ms
+    }
+
+    /// The stage a player is in after making `moves` moves
+    pub fn stage(&self, moves: u32) -> &Stage {
+        let mut start = 0;
+        for (index, stage) in self.stages.iter().enumerate() {
+            match stage.moves {
+                Some(count) if index + 1 < self.stages.len() => {
+                    start += count;
+                    if moves < start {
+                        return stage;
+                    }
+                }
+                _ => return stage,
+            }
+        }
+        unreachable!("a time control has at least one stage")
+    }
+
+    /// Most time a player can spend on their first `moves` moves: the time of
+    /// every stage they reach, and the increment or delay of each move. A
+    /// delay is not added to the clock like an increment, but a move that
+    /// takes at least the delay gets all of it, so it counts the same.
+    pub fn time_for_moves(&self, moves: u32) -> u64 {
+        let stages: u64 = (1..moves).map(|moves| self.time_added_after(moves)).sum();
+        let per_move: u64 = (0..moves).map(|moves| self.stage(moves).increment_ms).sum();
+        self.initial_time_ms() + stages + per_move
+    }
+
+    /// Time added to a player's clock once they have made `moves` moves: the
+    /// next stage's time if that move ended a stage, otherwise nothing
+    fn time_added_after(&self, moves: u32) -> u64 {
+        let mut start = 0;
+        for (index, stage) in self.stages.iter().enumerate() {
+            let count = match stage.moves {
+                Some(count) if count > 0 => count,
+                _ => return 0,
+            };
+            if index + 1 == self.stages.len() {
+                // The last stage starts over every `count` moves
+                return if (moves - start).is_multiple_of(count) { stage.time_ms } else { 0 };
+            }
+            start += count;
+            if moves == start {
+                return self.stages[index + 1].time_ms;
+            }
+            if moves < start {
+                return 0;
+            }
+        }
+        0
+    }
+
+    /// The PGN `TimeControl` tag, such as `40/5400+30:1800+30`. PGN has no way
+    /// to write a delay, so only Fischer increments appear, and correspondence
+    /// games are written as having no time control.
+    pub fn pgn_tag(&self) -> String {
+        if self.is_correspondence() {
+            return "-".to_string();
+        }
+        self.stages
+            .iter()
+            .map(|stage| {
+                let mut field = String::new();
+                if let Some(moves) = stage.moves {
+                    field.push_str(&format!("{}/", moves));
+                }
+                field.push_str(&(stage.time_ms / 1000).to_string());
+                if self.mode == ClockMode::Fischer {
+                    field.push_str(&format!("+{}", stage.increment_ms / 1000));
+                }
+                field
+            })
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+}
+
+/// Where the server reads the time for its clocks
+pub trait TimeSource: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// The system's monotonic clock
+pub struct RealTime;
+
+impl TimeSource for RealTime {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Both players' time under one time control.
+///
+/// Only one side's clock runs at a time. The stored times are as of the
+/// start of the running side's turn; what is left right now is worked out
+/// from them and `now` whenever it is asked for.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    control: TimeControl,
+    white_time_ms: u64,
+    black_time_ms: u64,
+    /// Moves each player has made, which decides the stage they are in
+    white_moves: u32,
+    black_moves: u32,
+    /// The side whose time is running and since when, `None` while stopped
+    running: Option<(Color, Instant)>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        let initial_time_ms = control.initial_time_ms();
+        Clock {
+            control,
+            white_time_ms: initial_time_ms,
+            black_time_ms: initial_time_ms,
+            white_moves: 0,
+            black_moves: 0,
+            running: None,
+        }
+    }
+
+    pub fn control(&self) -> &TimeControl {
+        &self.control
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Start `color`'s clock, when a game starts or resumes
+    pub fn start(&mut self, color: Color, now: Instant) {
+        self.running = Some((color, now));
+    }
+
+    /// Start `color`'s clock as if it had already been running for `elapsed`,
+    /// when a game restored from storage carries on
+    pub fn start_after(&mut self, color: Color, now: Instant, elapsed: Duration) {
+        match now.checked_sub(elapsed) {
+            Some(since) => self.running = Some((color, since)),
+            None => {
+                let time_ms = self.time_ms_mut(color);
+                *time_ms = time_ms.saturating_sub(elapsed.as_millis() as u64);
+                self.running = Some((color, now));
+            }
+        }
+    }
+
+    /// Stop the clock, charging the running side for the time it used
+    pub fn stop(&mut self, now: Instant) {
+        if let Some((color, _)) = self.running {
+            let time_left_ms = self.time_left_ms(color, now);
+            *self.time_ms_mut(color) = time_left_ms;
+            self.running = None;
+        }
+    }
+
+    /// Time `color` had at the start of the current turn, or when the clock stopped
+    pub fn time_ms(&self, color: Color) -> u64 {
+        match color {
+            Color::White => self.white_time_ms,
+            Color::Black => self.black_time_ms,
+        }
+    }
+
+    fn time_ms_mut(&mut self, color: Color) -> &mut u64 {
+        match color {
+            Color::White => &mut self.white_time_ms,
+            Color::Black => &mut self.black_time_ms,
+        }
+    }
+
+    fn moves_mut(&mut self, color: Color) -> &mut u32 {
+        match color {
+            Color::White => &mut self.white_moves,
+            Color::Black => &mut self.black_moves,
+        }
+    }
+
+    /// The increment or delay `color` gets for their next move
+    pub fn increment_ms(&self, color: Color) -> u64 {
+        let moves = match color {
+            Color::White => self.white_moves,
+            Color::Black => self.black_moves,
+        };
+        self.control.stage(moves).increment_ms
+    }
+
+    /// Time `color` has left at `now`. While a delay runs the clock does not
+    /// count down yet.
+    pub fn time_left_ms(&self, color: Color, now: Instant) -> u64 {
+        let time_ms = self.time_ms(color);
+        match self.running {
+            Some((running, since)) if running == color => {
+                let elapsed_ms = now.saturating_duration_since(since).as_millis() as u64;
+                let charged_ms = match self.control.mode {
+                    ClockMode::Fischer | ClockMode::Bronstein | ClockMode::Correspondence => elapsed_ms,
+                    ClockMode::Delay => elapsed_ms.saturating_sub(self.increment_ms(color)),
+                };
+                time_ms.saturating_sub(charged_ms)
+            }
+            _ => time_ms,
+        }
+    }
+
+    /// The running side and how long until it runs out of time
+    pub fn time_to_flag(&self, now: Instant) -> Option<(Color, u64)> {
+        let (color, since) = self.running?;
+        let mut remaining_ms = self.time_left_ms(color, now);
+        if self.control.mode == ClockMode::Delay {
+            // The part of the delay that has not passed yet comes first
+            let elapsed_ms = now.saturating_duration_since(since).as_millis() as u64;
+            remaining_ms += self.increment_ms(color).saturating_sub(elapsed_ms);
+        }
+        Some((color, remaining_ms))
+    }
+
+    /// The running side, if it has run out of time
+    pub fn flagged(&self, now: Instant) -> Option<Color> {
+        match self.time_to_flag(now)? {
+            (color, 0) => Some(color),
+            _ => None,
+        }
+    }
+
+    /// The running side finished its move: charge it for the time it took,
+    /// add what its mode and stage give it and start the opponent's clock.
+    /// Returns the side that had already run out of time instead, if it had.
+    pub fn punch(&mut self, now: Instant) -> Result<(), Color> {
+        let (color, since) = match self.running {
+            Some(running) => running,
+            None => return Ok(()),
+        };
+        if self.flagged(now).is_some() {
+            return Err(color);
+        }
+
+        let elapsed_ms = now.saturating_duration_since(since).as_millis() as u64;
+        let increment_ms = self.increment_ms(color);
+        let time_left_ms = self.time_left_ms(color, now);
+        let bonus_ms = match self.control.mode {
+            ClockMode::Fischer => increment_ms,
+            ClockMode::Bronstein => elapsed_ms.min(increment_ms),
+            ClockMode::Delay | ClockMode::Correspondence => 0,
+        };
+
+        let moves = {
+            let moves = self.moves_mut(color);
+            *moves += 1;
+            *moves
+        };
+        *self.time_ms_mut(color) = match self.control.mode {
+            ClockMode::Correspondence => self.control.stage(moves).time_ms,
+            _ => time_left_ms + bonus_ms + self.control.time_added_after(moves),
+        };
+        self.running = Some((!color, now));
+        Ok(())
+    }
+
+    /// End `color`'s time: their clock reads zero and stops
+    pub fn flag(&mut self, color: Color) {
+        *self.time_ms_mut(color) = 0;
+        self.running = None;
+    }
+
+    /// Replay a stored move by `color`, after which the clocks read these times
+    pub fn restore_move(&mut self, color: Color, white_time_ms: u64, black_time_ms: u64) {
+        *self.moves_mut(color) += 1;
+        self.set_times(white_time_ms, black_time_ms);
+    }
+
+    pub fn set_times(&mut self, white_time_ms: u64, black_time_ms: u64) {
+        self.white_time_ms = white_time_ms;
+        self.black_time_ms = black_time_ms;
+    }
+}
@@ -0,0 +1,350 @@
+//! Tests for time controls, the clock and correspondence games, driven by a
+//! fake time source that only moves when a test tells it to.
+
+use actix_http::ws::Message;
+use chess::Color;
+use chrono::Utc;
+use serde_json::json;
+use std::time::Instant;
+
+use crate::archive::GameRecordStatus;
+use crate::correspondence;
+use crate::test_support::{exchange, pump, test_app_state_from, FakeTime, TestClient};
+use crate::protocol::ProtocolVersion;
+use crate::storage::{restore_games, GameEvent};
+use crate::time_control::{ClockMode, Clock, Stage, TimeControl, TimeSource};
+
+const MINUTE: u64 = 60 * 1000;
+
+fn stage(moves: Option<u32>, time_ms: u64, increment_ms: u64) -> Stage {
+    Stage {
+        moves,
+        time_ms,
+        increment_ms,
+    }
+}
+
+fn started_clock(mode: ClockMode, stages: Vec<Stage>, time: &FakeTime) -> Clock {
+    let mut clock = Clock::new(TimeControl { mode, stages });
+    clock.start(Color::White, time.now());
+    clock
+}
+
+/// Let the running side think for `ms` and then move
+fn play(clock: &mut Clock, time: &FakeTime, ms: u64) {
+    time.advance(ms);
+    clock.punch(time.now()).unwrap();
+}
+
+#[test]
+fn fischer_adds_the_increment_after_every_move() {
+    let time = FakeTime::new();
+    let mut clock = started_clock(ClockMode::Fischer, vec![stage(None, 5 * MINUTE, 3000)], &time);
+
+    time.advance(10_000);
+    assert_eq!(clock.time_left_ms(Color::White, time.now()), 290_000);
+    assert_eq!(clock.time_left_ms(Color::Black, time.now()), 300_000);
+    clock.punch(time.now()).unwrap();
+    assert_eq!(clock.time_ms(Color::White), 293_000);
+
+    play(&mut clock, &time, 2000);
+    assert_eq!(clock.time_ms(Color::Black), 301_000);
+    assert_eq!(clock.time_to_flag(time.now()), Some((Color::White, 293_000)));
+}
+
+#[test]
+fn bronstein_gives_back_at_most_the_delay() {
+    let time = FakeTime::new();
+    let mut clock = started_clock(ClockMode::Bronstein, vec![stage(None, 5 * MINUTE, 5000)], &time);
+
+    // The clock counts down during the move and gets the time back afterwards
+    time.advance(3000);
+    assert_eq!(clock.time_left_ms(Color::White, time.now()), 297_000);
+    clock.punch(time.now()).unwrap();
+    assert_eq!(clock.time_ms(Color::White), 300_000);
+
+    play(&mut clock, &time, 8000);
+    assert_eq!(clock.time_ms(Color::Black), 297_000);
+}
+
+#[test]
+fn simple_delay_holds_the_clock_back() {
+    let time = FakeTime::new();
+    let mut clock = started_clock(ClockMode::Delay, vec![stage(None, MINUTE, 5000)], &time);
+    assert_eq!(clock.time_to_flag(time.now()), Some((Color::White, 65_000)));
+
+    time.advance(3000);
+    assert_eq!(clock.time_left_ms(Color::White, time.now()), 60_000);
+    time.advance(5000);
+    assert_eq!(clock.time_left_ms(Color::White, time.now()), 57_000);
+    clock.punch(time.now()).unwrap();
+    assert_eq!(clock.time_ms(Color::White), 57_000);
+
+    // The delay starts over on every move and is never banked
+    play(&mut clock, &time, 1000);
+    assert_eq!(clock.time_ms(Color::Black), 60_000);
+}
+
+#[test]
+fn stages_add_time_when_they_start() {
+    let time = FakeTime::new();
+    let stages = vec![stage(Some(2), 10 * MINUTE, 30_000), stage(None, 5 * MINUTE, 0)];
+    let mut clock = started_clock(ClockMode::Fischer, stages, &time);
+
+    play(&mut clock, &time, 1000);
+    assert_eq!(clock.time_ms(Color::White), 629_000);
+    play(&mut clock, &time, 0);
+
+    // The second move ends the first stage and brings the second stage's time
+    play(&mut clock, &time, 1000);
+    assert_eq!(clock.time_ms(Color::White), 958_000);
+    assert_eq!(clock.increment_ms(Color::White), 0);
+    assert_eq!(clock.increment_ms(Color::Black), 30_000);
+    play(&mut clock, &time, 0);
+
+    // and the second stage has no increment
+    play(&mut clock, &time, 1000);
+    assert_eq!(clock.time_ms(Color::White), 957_000);
+}
+
+#[test]
+fn a_last_stage_with_moves_repeats() {
+    let time = FakeTime::new();
+    let mut clock = started_clock(ClockMode::Fischer, vec![stage(Some(2), MINUTE, 0)], &time);
+
+    let mut white_times = Vec::new();
+    for _ in 0..4 {
+        play(&mut clock, &time, 1000);
+        white_times.push(clock.time_ms(Color::White));
+        play(&mut clock, &time, 0);
+    }
+    assert_eq!(white_times, [59_000, 118_000, 117_000, 176_000]);
+}
+
+#[test]
+fn running_out_of_time_flags() {
+    let time = FakeTime::new();
+    let mut clock = started_clock(ClockMode::Fischer, vec![stage(None, MINUTE, 2000)], &time);
+    time.advance(MINUTE - 1);
+    assert_eq!(clock.flagged(time.now()), None);
+    time.advance(1);
+    assert_eq!(clock.flagged(time.now()), Some(Color::White));
+    assert_eq!(clock.punch(time.now()), Err(Color::White));
+
+    clock.flag(Color::White);
+    assert!(!clock.is_running());
+    assert_eq!(clock.time_left_ms(Color::White, time.now()), 0);
+
+    // Under a simple delay the delay passes before the time does
+    let clock = started_clock(ClockMode::Delay, vec![stage(None, MINUTE, 5000)], &time);
+    time.advance(MINUTE);
+    assert_eq!(clock.flagged(time.now()), None);
+    time.advance(5000);
+    assert_eq!(clock.flagged(time.now()), Some(Color::White));
+}
+
+#[test]
+fn stopped_clocks_keep_their_time() {
+    let time = FakeTime::new();
+    let mut clock = started_clock(ClockMode::Fischer, vec![stage(None, MINUTE, 0)], &time);
+    time.advance(15_000);
+    clock.stop(time.now());
+    assert!(!clock.is_running());
+
+    time.advance(MINUTE);
+    assert_eq!(clock.time_left_ms(Color::White, time.now()), 45_000);
+    assert_eq!(clock.time_to_flag(time.now()), None);
+
+    clock.start(Color::White, time.now());
+    time.advance(5000);
+    assert_eq!(clock.time_left_ms(Color::White, time.now()), 40_000);
+}
+
+#[test]
+fn pgn_time_control_tags() {
+    let stages = vec![stage(Some(40), 90 * MINUTE, 30_000), stage(None, 30 * MINUTE, 30_000)];
+    let control = TimeControl {
+        mode: ClockMode::Fischer,
+        stages: stages.clone(),
+    };
+    assert_eq!(control.pgn_tag(), "40/5400+30:1800+30");
+    assert_eq!(TimeControl { mode: ClockMode::Delay, stages }.pgn_tag(), "40/5400:1800");
+    assert_eq!(TimeControl::fischer(15 * MINUTE, 10_000).pgn_tag(), "900+10");
+}
+
+#[actix_rt::test]
+async fn server_clocks_follow_the_time_source() {
+    let time = FakeTime::new();
+    let app_state = test_app_state_from(None, time.clone());
+    let mut clients = vec![
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+    ];
+    pump(&mut clients);
+
+    for stages in [json!([]), json!([{"minutes": 90}, {"minutes": 30}]), json!([{"minutes": 0}])] {
+        let reply = exchange(&mut clients, 0, json!({"type": "create", "time_control": {"stages": stages}}));
+        assert_eq!(reply["code"], "invalid_time_control", "{}", stages);
+    }
+
+    let time_control = json!({
+        "mode": "bronstein",
+        "stages": [{"moves": 40, "minutes": 90, "increment_seconds": 30}, {"minutes": 30, "increment_seconds": 30}],
+    });
+    let reply = exchange(&mut clients, 0, json!({"type": "create", "time_control": time_control}));
+    assert_eq!(reply["white_time_ms"], 90 * MINUTE, "{}", reply);
+    let game_id = reply["game_id"].as_str().unwrap().to_string();
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+
+    // A 10 second move gets back the 30 second delay's worth of its own time
+    time.advance(10_000);
+    let reply = exchange(&mut clients, 0, json!({"type": "move", "uci": "e2e4"}));
+    assert_eq!(reply["white_time_ms"], 90 * MINUTE, "{}", reply);
+
+    time.advance(40_000);
+    let reply = exchange(&mut clients, 1, json!({"type": "time_sync", "game_id": game_id}));
+    assert_eq!(reply["black_time_ms"], 90 * MINUTE - 40_000, "{}", reply);
+
+    time.advance(90 * MINUTE);
+    let reply = exchange(&mut clients, 1, json!({"type": "time_sync", "game_id": game_id}));
+    assert_eq!(reply["termination"], "timeout", "{}", reply);
+    assert_eq!(reply["black_time_ms"], 0);
+
+    let records = app_state.all_game_records();
+    assert_eq!(records[0].time_control().pgn_tag(), "40/5400:1800");
+}
+
+const DAY: u64 = 24 * 60 * MINUTE;
+
+#[test]
+fn correspondence_moves_get_the_full_time_again() {
+    let time = FakeTime::new();
+    let mut clock = started_clock(ClockMode::Correspondence, vec![stage(None, DAY, 0)], &time);
+
+    play(&mut clock, &time, 20 * 60 * MINUTE);
+    assert_eq!(clock.time_ms(Color::White), DAY);
+    time.advance(DAY);
+    assert_eq!(clock.flagged(time.now()), Some(Color::Black));
+    assert_eq!(TimeControl::correspondence(DAY).pgn_tag(), "-");
+}
+
+#[test]
+fn restored_correspondence_clocks_keep_running() {
+    let created_at = Utc::now() - chrono::Duration::hours(30);
+    let seated = |color, at| GameEvent::Seated {
+        game_id: "game".to_string(),
+        color,
+        player_id: format!("{:?}", color),
+        resume_token: uuid::Uuid::new_v4().to_string(),
+        at: Some(at),
+    };
+    let events = vec![
+        GameEvent::Created {
+            game_id: "game".to_string(),
+            created_at,
+            start_fen: None,
+            initial_time_ms: DAY,
+            increment_ms: 0,
+            time_control: Some(TimeControl::correspondence(DAY)),
+            engine_level: None,
+            engine_kind: Default::default(),
+            rated: false,
+        },
+        seated(Color::White, created_at),
+        seated(Color::Black, created_at + chrono::Duration::hours(20)),
+    ];
+
+    let now = Instant::now();
+    let games = restore_games(events, now);
+    let game_state = &games["game"];
+    assert!(!game_state.is_paused());
+    // The game started when Black sat down, ten hours ago
+    let time_left_ms = game_state.clock.time_left_ms(Color::White, now);
+    assert!((13 * 60 * MINUTE..=14 * 60 * MINUTE).contains(&time_left_ms), "{}", time_left_ms);
+}
+
+#[actix_rt::test]
+async fn correspondence_games_wait_for_players_and_time_out_in_the_sweeper() {
+    let time = FakeTime::new();
+    let app_state = test_app_state_from(None, time.clone());
+    let mut clients = vec![
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+    ];
+    pump(&mut clients);
+
+    for days_per_move in [0, 15] {
+        let reply = exchange(&mut clients, 0, json!({"type": "create", "days_per_move": days_per_move}));
+        assert_eq!(reply["code"], "invalid_time_control", "{}", reply);
+    }
+
+    let reply = exchange(&mut clients, 0, json!({"type": "create", "days_per_move": 3}));
+    let game_id = reply["game_id"].as_str().unwrap().to_string();
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    let joined = clients[1].messages.iter().find(|message| message["type"] == "joined").unwrap();
+    let black_token = joined["resume_token"].clone();
+    time.advance(DAY);
+    exchange(&mut clients, 0, json!({"type": "move", "uci": "e2e4"}));
+
+    // Nobody syncs the clock, yet the sweeper keeps track of the days
+    time.advance(2 * DAY);
+    assert_eq!(correspondence::sweep(&app_state), 0);
+
+    // Black comes back on a new connection and hears about White's move
+    clients.push(TestClient::connect(&app_state, ProtocolVersion::V2));
+    exchange(&mut clients, 2, json!({"type": "rejoin", "game_id": game_id, "resume_token": black_token}));
+    let notice = clients[2].messages.iter().find(|message| message["type"] == "opponent_moved").cloned();
+    let notice = notice.expect("no opponent_moved");
+    assert_eq!((notice["san"].as_str(), notice["uci"].as_str()), (Some("e4"), Some("e2e4")));
+    assert!(notice["move_deadline"].is_string(), "{}", notice);
+
+    time.advance(DAY);
+    assert_eq!(correspondence::sweep(&app_state), 1);
+    pump(&mut clients);
+    let game_over = clients[2].messages.last().unwrap();
+    assert_eq!((game_over["type"].as_str(), game_over["termination"].as_str()), (Some("game_over"), Some("timeout")));
+    assert_eq!(app_state.find_game_record(&game_id).unwrap().result.as_deref(), Some("1-0"));
+}
+
+#[actix_rt::test]
+async fn correspondence_games_timed_out_while_nobody_is_connected_are_removed() {
+    let time = FakeTime::new();
+    let app_state = test_app_state_from(None, time.clone());
+    let mut clients = vec![
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+        TestClient::connect(&app_state, ProtocolVersion::V2),
+    ];
+    pump(&mut clients);
+
+    let game_id = exchange(&mut clients, 0, json!({"type": "create", "days_per_move": 1}))["game_id"].as_str().unwrap().to_string();
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    for client in &mut clients {
+        client.send(Message::Close(None));
+    }
+    pump(&mut clients);
+
+    // The game outlives its players' connections until the sweeper ends it
+    let timer = || app_state.removal_timers.lock().unwrap().get(&game_id).copied().unwrap();
+    let first_timer = timer();
+    app_state.remove_abandoned_game(&game_id, first_timer);
+    assert!(app_state.games.lock().unwrap().contains_key(&game_id));
+
+    // and then sets a timer of its own to remove it
+    time.advance(DAY);
+    assert_eq!(correspondence::sweep(&app_state), 1);
+    assert_eq!(timer(), first_timer + 1);
+    app_state.remove_abandoned_game(&game_id, timer());
+    assert!(!app_state.games.lock().unwrap().contains_key(&game_id));
+    assert_eq!(app_state.find_game_record(&game_id).unwrap().result.as_deref(), Some("0-1"));
+
+    // A challenge nobody took up is not kept for its creator
+    clients.push(TestClient::connect(&app_state, ProtocolVersion::V2));
+    pump(&mut clients);
+    let game_id = exchange(&mut clients, 2, json!({"type": "create", "days_per_move": 1}))["game_id"].as_str().unwrap().to_string();
+    clients[2].send(Message::Close(None));
+    pump(&mut clients);
+    let timer = app_state.removal_timers.lock().unwrap().get(&game_id).copied().unwrap();
+    app_state.remove_abandoned_game(&game_id, timer);
+    assert!(!app_state.games.lock().unwrap().contains_key(&game_id));
+    assert_eq!(app_state.find_game_record(&game_id).unwrap().status, GameRecordStatus::Abandoned);
+}
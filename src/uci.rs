@@ -0,0 +1,333 @@
+//! Driving an external engine over UCI.
+//!
+//! An engine binary such as Stockfish is started as a child process and spoken
+//! to line by line on its stdin and stdout. Processes are kept in a small pool
+//! between searches; one that crashes, stops answering or talks nonsense is
+//! killed and a fresh one is started for the next search.
+
+use chess::{Action, ChessMove, Game};
+use log::{info, warn};
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::time::{timeout_at, Instant};
+
+use crate::engine::{self, Score, SearchLimits};
+use crate::validation;
+
+/// How long a new engine has to finish the `uci` and `isready` handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long past its thinking time an engine may take before it is told to stop
+const MOVE_GRACE: Duration = Duration::from_secs(2);
+/// How long an engine has to answer `stop` before it is killed
+const STOP_GRACE: Duration = Duration::from_secs(1);
+/// How often a thinking engine is checked on for a request to stop
+const STOP_POLL: Duration = Duration::from_millis(50);
+/// Idle engines kept running for the next search
+const MAX_IDLE_ENGINES: usize = 4;
+
+/// Why talking to an engine failed
+#[derive(Debug)]
+pub enum UciError {
+    /// The engine binary could not be started
+    Spawn(io::Error),
+    /// Reading from or writing to the engine failed
+    Io(io::Error),
+    /// The engine exited
+    Crashed,
+    /// The engine did not answer in time
+    Timeout,
+    /// The engine sent something that is not valid UCI
+    Protocol(String),
+}
+
+impl fmt::Display for UciError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UciError::Spawn(e) => write!(f, "could not start the engine: {}", e),
+            UciError::Io(e) => write!(f, "could not talk to the engine: {}", e),
+            UciError::Crashed => f.write_str("the engine exited"),
+            UciError::Timeout => f.write_str("the engine did not answer in time"),
+            UciError::Protocol(line) => write!(f, "unexpected output from the engine: {}", line),
+        }
+    }
+}
+
+/// The program to run, and its arguments
+#[derive(Debug, Clone)]
+pub struct EngineCommand {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+}
+
+/// One `info` line from a search
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Info {
+    pub depth: Option<u32>,
+    pub multipv: Option<u32>,
+    pub score: Option<Score>,
+    pub nodes: Option<u64>,
+    /// Principal variation in UCI notation
+    pub pv: Vec<String>,
+}
+
+impl Info {
+    /// Read an `info` line, or `None` for any other line and for `info string` comments
+    pub fn parse(line: &str) -> Option<Info> {
+        let mut tokens = line.split_whitespace();
+        if tokens.next() != Some("info") {
+            return None;
+        }
+
+        let mut info = Info::default();
+        let mut found = false;
+        while let Some(token) = tokens.next() {
+            match token {
+                "depth" => info.depth = tokens.next().and_then(|value| value.parse().ok()),
+                "multipv" => info.multipv = tokens.next().and_then(|value| value.parse().ok()),
+                "nodes" => info.nodes = tokens.next().and_then(|value| value.parse().ok()),
+                "score" => {
+                    info.score = match (tokens.next(), tokens.next().and_then(|value| value.parse().ok())) {
+                        (Some("cp"), Some(value)) => Some(Score::Centipawns(value)),
+                        (Some("mate"), Some(value)) => Some(Score::Mate(value)),
+                        _ => None,
+                    }
+                }
+                // Everything after `pv` is the line itself
+                "pv" => info.pv = tokens.by_ref().map(str::to_string).collect(),
+                // Everything after `string` is free text
+                "string" => break,
+                _ => continue,
+            }
+            found = true;
+        }
+        found.then_some(info)
+    }
+}
+
+/// The `position` command for the current position of a game, given as the
+/// FEN it started from, if not the standard position, and the moves played
+/// since, so the engine sees repetitions and the fifty-move count
+pub fn position_command(start_fen: Option<&str>, game: &Game) -> String {
+    let mut command = match start_fen {
+        Some(fen) => {
+            // Starting FENs may leave out the move counters, which engines expect
+            let fields = fen.split_whitespace().count();
+            let counters = [" 0", " 1"];
+            format!("position fen {}{}", fen, counters[fields.clamp(4, 6) - 4..].concat())
+        }
+        None => "position startpos".to_string(),
+    };
+    let mut moves = game.actions().iter().filter_map(|action| match action {
+        Action::MakeMove(chess_move) => Some(chess_move),
+        _ => None,
+    });
+    if let Some(first_move) = moves.next() {
+        command.push_str(" moves ");
+        command.push_str(&first_move.to_string());
+        for chess_move in moves {
+            command.push(' ');
+            command.push_str(&chess_move.to_string());
+        }
+    }
+    command
+}
+
+/// A running engine process that has completed the UCI handshake
+pub struct UciEngine {
+    // Killed when dropped
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    /// Names of the options the engine announced
+    options: Vec<String>,
+    /// Game the engine last searched, so it is told when a new one starts
+    game_id: Option<String>,
+}
+
+impl UciEngine {
+    pub async fn start(command: &EngineCommand) -> Result<Self, UciError> {
+        let mut child = Command::new(&command.program)
+            .args(&command.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(UciError::Spawn)?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = BufReader::new(child.stdout.take().expect("stdout is piped")).lines();
+
+        let mut engine = UciEngine {
+            _child: child,
+            stdin,
+            stdout,
+            options: Vec::new(),
+            game_id: None,
+        };
+
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        engine.send("uci").await?;
+        loop {
+            let line = engine.read_line(deadline).await?;
+            if line.trim() == "uciok" {
+                break;
+            }
+            if let Some(option) = line.trim().strip_prefix("option name ") {
+                let name = option.split(" type ").next().unwrap_or(option);
+                engine.options.push(name.trim().to_string());
+            }
+        }
+        engine.wait_until_ready(deadline).await?;
+        Ok(engine)
+    }
+
+    /// Whether the engine announced an option with this name
+    pub fn has_option(&self, name: &str) -> bool {
+        self.options.iter().any(|option| option.eq_ignore_ascii_case(name))
+    }
+
+    pub async fn set_option(&mut self, name: &str, value: &str) -> Result<(), UciError> {
+        self.send(&format!("setoption name {} value {}", name, value)).await
+    }
+
+    /// Search a position given as a `position` command and return the best
+    /// move, or `None` if the engine found no legal move. `on_info` is called
+    /// with every `info` line while the engine thinks, and setting `stop` has
+    /// it answer early.
+    pub async fn search(
+        &mut self,
+        game_id: &str,
+        position: &str,
+        limits: SearchLimits,
+        stop: &AtomicBool,
+        mut on_info: impl FnMut(Info),
+    ) -> Result<Option<ChessMove>, UciError> {
+        if self.game_id.as_deref() != Some(game_id) {
+            self.send("ucinewgame").await?;
+            self.wait_until_ready(Instant::now() + HANDSHAKE_TIMEOUT).await?;
+            self.game_id = Some(game_id.to_string());
+        }
+
+        self.send(position).await?;
+        self.send(&format!("go depth {} movetime {}", limits.max_depth, limits.max_time.as_millis()))
+            .await?;
+
+        let mut deadline = Instant::now() + limits.max_time + MOVE_GRACE;
+        let mut stopped = false;
+        loop {
+            // Give the engine a last chance to answer with what it has once it
+            // is asked to stop or runs out of time
+            if !stopped && (stop.load(Ordering::Relaxed) || Instant::now() >= deadline) {
+                self.send("stop").await?;
+                stopped = true;
+                deadline = Instant::now() + STOP_GRACE;
+            }
+
+            let wake_up = if stopped { deadline } else { deadline.min(Instant::now() + STOP_POLL) };
+            let line = match self.read_line(wake_up).await {
+                Err(UciError::Timeout) if !stopped => continue,
+                result => result?,
+            };
+
+            if let Some(info) = Info::parse(&line) {
+                on_info(info);
+            } else if let Some(rest) = line.trim().strip_prefix("bestmove") {
+                return match rest.split_whitespace().next() {
+                    Some("(none)" | "0000") => Ok(None),
+                    Some(uci) => validation::parse_uci(uci)
+                        .map(Some)
+                        .map_err(|_| UciError::Protocol(line.clone())),
+                    None => Err(UciError::Protocol(line.clone())),
+                };
+            }
+        }
+    }
+
+    async fn wait_until_ready(&mut self, deadline: Instant) -> Result<(), UciError> {
+        self.send("isready").await?;
+        while self.read_line(deadline).await?.trim() != "readyok" {}
+        Ok(())
+    }
+
+    async fn send(&mut self, line: &str) -> Result<(), UciError> {
+        self.stdin.write_all(format!("{}\n", line).as_bytes()).await.map_err(UciError::Io)?;
+        self.stdin.flush().await.map_err(UciError::Io)
+    }
+
+    async fn read_line(&mut self, deadline: Instant) -> Result<String, UciError> {
+        match timeout_at(deadline, self.stdout.next_line()).await {
+            Ok(Ok(Some(line))) => Ok(line),
+            Ok(Ok(None)) => Err(UciError::Crashed),
+            Ok(Err(e)) => Err(UciError::Io(e)),
+            Err(_) => Err(UciError::Timeout),
+        }
+    }
+}
+
+/// Engine processes for one configured binary, started as they are needed
+pub struct UciPool {
+    command: EngineCommand,
+    idle: Mutex<Vec<UciEngine>>,
+}
+
+impl UciPool {
+    pub fn new(command: EngineCommand) -> Self {
+        UciPool {
+            command,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Run a search on an idle engine, or a new one if none is idle. The
+    /// engine goes back to the pool only if the search went well.
+    pub async fn search(
+        &self,
+        game_id: &str,
+        position: &str,
+        limits: SearchLimits,
+        level: Option<u8>,
+        stop: &AtomicBool,
+        on_info: impl FnMut(Info),
+    ) -> Result<Option<ChessMove>, UciError> {
+        let idle_engine = self.idle.lock().unwrap().pop();
+        let mut uci_engine = match idle_engine {
+            Some(uci_engine) => uci_engine,
+            None => {
+                info!("Starting UCI engine {}", self.command.program.display());
+                UciEngine::start(&self.command).await?
+            }
+        };
+
+        // Engines that can play weaker get a skill level to match; the rest only
+        // feel the depth and time limits
+        if uci_engine.has_option("Skill Level") {
+            let skill = match level {
+                Some(level) => (level.clamp(engine::MIN_LEVEL, engine::MAX_LEVEL) - engine::MIN_LEVEL) as u32 * 20
+                    / (engine::MAX_LEVEL - engine::MIN_LEVEL) as u32,
+                None => 20,
+            };
+            uci_engine.set_option("Skill Level", &skill.to_string()).await?;
+        }
+
+        match uci_engine.search(game_id, position, limits, stop, on_info).await {
+            Ok(best_move) => {
+                let mut idle = self.idle.lock().unwrap();
+                if idle.len() < MAX_IDLE_ENGINES {
+                    idle.push(uci_engine);
+                }
+                Ok(best_move)
+            }
+            Err(e) => {
+                warn!("UCI engine {} failed and was stopped: {}", self.command.program.display(), e);
+                Err(e)
+            }
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! Tests for driving UCI engines, against `tests/mock_uci.sh` standing in for
+//! a real engine binary.
+
+use chess::{Board, ChessMove, Game, Square};
+use serde_json::json;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use crate::engine::{Score, SearchLimits};
+use crate::test_support::{exchange, pump, test_app_state, test_app_state_with, wait_for, wait_for_move, TestClient};
+use crate::protocol::ProtocolVersion;
+use crate::uci::{self, EngineCommand, Info, UciEngine, UciError, UciPool};
+
+const LIMITS: SearchLimits = SearchLimits {
+    max_depth: 4,
+    max_time: Duration::from_millis(100),
+};
+
+fn mock(mode: &str, reply: &str) -> EngineCommand {
+    EngineCommand {
+        program: concat!(env!("CARGO_MANIFEST_DIR"), "/tests/mock_uci.sh").into(),
+        args: vec![mode.to_string(), reply.to_string()],
+    }
+}
+
+fn uci_move(uci: &str) -> ChessMove {
+    ChessMove::new(Square::from_str(&uci[0..2]).unwrap(), Square::from_str(&uci[2..4]).unwrap(), None)
+}
+
+#[test]
+fn info_lines() {
+    let info = Info::parse("info depth 12 seldepth 18 multipv 2 score cp -31 nodes 81234 nps 900000 pv e7e5 g1f3").unwrap();
+    assert_eq!(
+        info,
+        Info {
+            depth: Some(12),
+            multipv: Some(2),
+            score: Some(Score::Centipawns(-31)),
+            nodes: Some(81234),
+            pv: vec!["e7e5".to_string(), "g1f3".to_string()],
+        }
+    );
+
+    let info = Info::parse("info depth 20 score mate -4 upperbound pv h7h8q").unwrap();
+    assert_eq!(info.score, Some(Score::Mate(-4)));
+    assert_eq!(info.pv, ["h7h8q"]);
+
+    assert_eq!(Info::parse("info string NNUE evaluation enabled depth 3"), None);
+    assert_eq!(Info::parse("bestmove e2e4"), None);
+    assert_eq!(Info::parse("info"), None);
+}
+
+#[test]
+fn positions_include_the_moves_played() {
+    let mut game = Game::new();
+    assert_eq!(uci::position_command(None, &game), "position startpos");
+    game.make_move(uci_move("e2e4"));
+    game.make_move(uci_move("e7e5"));
+    assert_eq!(uci::position_command(None, &game), "position startpos moves e2e4 e7e5");
+
+    // Games from a FEN keep its move counters, so the engine knows how close the fifty-move rule is
+    let fen = "4k3/8/8/8/8/8/4P3/4K1N1 w - - 97 60";
+    let mut game = Game::new_with_board(Board::from_str(fen).unwrap());
+    game.make_move(uci_move("g1f3"));
+    assert_eq!(uci::position_command(Some(fen), &game), format!("position fen {} moves g1f3", fen));
+    assert_eq!(
+        uci::position_command(Some("4k3/8/8/8/8/8/4P3/4K1N1 w - -"), &game),
+        "position fen 4k3/8/8/8/8/8/4P3/4K1N1 w - - 0 1 moves g1f3"
+    );
+    assert_eq!(
+        uci::position_command(Some("4k3/8/8/8/8/8/4P3/4K1N1 w - - 12"), &game),
+        "position fen 4k3/8/8/8/8/8/4P3/4K1N1 w - - 12 1 moves g1f3"
+    );
+}
+
+#[actix_rt::test]
+async fn search_reports_info_and_best_move() {
+    let mut engine = UciEngine::start(&mock("play", "c7c5")).await.unwrap();
+    assert!(engine.has_option("skill level"));
+
+    let mut infos = Vec::new();
+    let best_move = engine
+        .search("game", "position startpos moves e2e4", LIMITS, &AtomicBool::new(false), |info| infos.push(info))
+        .await
+        .unwrap();
+    assert_eq!(best_move, Some(uci_move("c7c5")));
+    assert_eq!(infos.len(), 2);
+    assert_eq!(infos[1].score, Some(Score::Mate(-3)));
+    assert_eq!(infos[1].pv, ["c7c5", "e2e4"]);
+
+    // The same process serves the next search
+    let best_move = engine.search("game", "position startpos", LIMITS, &AtomicBool::new(false), |_| {}).await.unwrap();
+    assert_eq!(best_move, Some(uci_move("c7c5")));
+}
+
+#[actix_rt::test]
+async fn engine_failures_are_errors() {
+    let error = UciEngine::start(&mock("silent", "e7e5")).await.err().unwrap();
+    assert!(matches!(error, UciError::Timeout), "{}", error);
+
+    let missing = EngineCommand {
+        program: "/nonexistent/stockfish".into(),
+        args: Vec::new(),
+    };
+    let error = UciEngine::start(&missing).await.err().unwrap();
+    assert!(matches!(error, UciError::Spawn(_)), "{}", error);
+
+    for (mode, expected) in [("crash", "exited"), ("hang", "in time"), ("garbage", "unexpected output")] {
+        let mut engine = UciEngine::start(&mock(mode, "e7e5")).await.unwrap();
+        let error = engine.search("game", "position startpos", LIMITS, &AtomicBool::new(false), |_| {}).await.unwrap_err();
+        assert!(error.to_string().contains(expected), "{}: {}", mode, error);
+    }
+}
+
+#[actix_rt::test]
+async fn stopped_searches_end_early() {
+    let limits = SearchLimits {
+        max_depth: 30,
+        max_time: Duration::from_secs(30),
+    };
+    let mut engine = UciEngine::start(&mock("hang", "e7e5")).await.unwrap();
+    let started = std::time::Instant::now();
+    let error = engine.search("game", "position startpos", limits, &AtomicBool::new(true), |_| {}).await.unwrap_err();
+    // The engine is told to stop at once, and given up on when it does not
+    assert!(matches!(error, UciError::Timeout), "{}", error);
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    let mut engine = UciEngine::start(&mock("play", "e7e5")).await.unwrap();
+    let best_move = engine.search("game", "position startpos moves e2e4", limits, &AtomicBool::new(true), |_| {}).await.unwrap();
+    assert_eq!(best_move, Some(uci_move("e7e5")));
+}
+
+#[actix_rt::test]
+async fn pool_replaces_failed_engines() {
+    let pool = UciPool::new(mock("crash", "e7e5"));
+    assert!(pool.search("game", "position startpos", LIMITS, Some(1), &AtomicBool::new(false), |_| {}).await.is_err());
+    // A new process is started for the next search, which crashes again
+    assert!(matches!(
+        pool.search("game", "position startpos", LIMITS, Some(1), &AtomicBool::new(false), |_| {}).await,
+        Err(UciError::Crashed)
+    ));
+
+    let pool = UciPool::new(mock("play", "e7e5"));
+    for _ in 0..3 {
+        let best_move = pool.search("game", "position startpos", LIMITS, Some(8), &AtomicBool::new(false), |_| {}).await.unwrap();
+        assert_eq!(best_move, Some(uci_move("e7e5")));
+    }
+}
+
+#[actix_rt::test]
+async fn uci_engine_plays_games() {
+    let app_state = test_app_state_with(Some(UciPool::new(mock("play", "e7e5"))));
+    let mut clients = vec![TestClient::connect(&app_state, ProtocolVersion::V2)];
+    pump(&mut clients);
+
+    clients[0].send_text(
+        json!({"type": "create", "color_preference": "white", "engine_level": 2, "engine": "uci"}).to_string(),
+    );
+    pump(&mut clients);
+    clients[0].messages.clear();
+    clients[0].send_text(json!({"type": "move", "uci": "e2e4"}).to_string());
+    wait_for_move(&mut clients).await;
+    let engine_move = wait_for_move(&mut clients).await;
+    assert_eq!(engine_move["san"], "e5", "{}", engine_move);
+
+    // e7e5 is no longer legal, so the built-in engine stands in for this move
+    clients[0].send_text(json!({"type": "move", "uci": "g1f3"}).to_string());
+    wait_for_move(&mut clients).await;
+    let engine_move = wait_for_move(&mut clients).await;
+    assert_eq!(engine_move["game_status"], "white_turn", "{}", engine_move);
+}
+
+#[actix_rt::test]
+async fn uci_games_need_an_engine() {
+    let app_state = test_app_state();
+    let mut clients = vec![TestClient::connect(&app_state, ProtocolVersion::V2)];
+    pump(&mut clients);
+
+    for (message, code) in [
+        (json!({"type": "create", "engine_level": 3, "engine": "uci"}), "engine_unavailable"),
+        (json!({"type": "create", "engine": "builtin"}), "invalid_engine_level"),
+    ] {
+        clients[0].messages.clear();
+        clients[0].send_text(message.to_string());
+        pump(&mut clients);
+        assert_eq!(clients[0].messages[0]["code"], code, "{}", message);
+    }
+}
+
+#[actix_rt::test]
+async fn uci_engine_analyses_positions() {
+    let app_state = test_app_state_with(Some(UciPool::new(mock("play", "g8f6"))));
+    let mut clients: Vec<_> = (0..3).map(|_| TestClient::connect(&app_state, ProtocolVersion::V2)).collect();
+    pump(&mut clients);
+
+    let game_id = exchange(&mut clients, 0, json!({"type": "create", "color_preference": "white"}))["game_id"].clone();
+    exchange(&mut clients, 1, json!({"type": "join", "game_id": game_id}));
+    exchange(&mut clients, 0, json!({"type": "move", "uci": "d2d4"}));
+
+    // The engine's lines are shown like the built-in engine's, for White and in SAN
+    clients[2].send_text(json!({"type": "start_analysis", "game_id": game_id}).to_string());
+    let mut analysis = Vec::new();
+    for _ in 0..3 {
+        analysis.push(wait_for(&mut clients, 2, "analysis").await);
+    }
+    let summary: Vec<_> = analysis
+        .iter()
+        .map(|update| (update["depth"].clone(), update["evaluation"].clone(), update["pv"].clone(), update["done"].clone()))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (json!(1), json!({"cp": -25}), json!(["Nf6"]), json!(false)),
+            (json!(2), json!({"mate": 3}), json!(["Nf6", "e4"]), json!(false)),
+            (json!(2), json!({"mate": 3}), json!(["Nf6", "e4"]), json!(true)),
+        ]
+    );
+    assert_eq!(analysis[0]["fen"], "rnbqkbnr/pppppppp/8/8/3P4/8/PPP1PPPP/RNBQKBNR b KQkq - 0 1");
+}
@@ -0,0 +1,188 @@
+//! Checks on values supplied by clients.
+//!
+//! The `chess` crate assumes well formed input in places (a square such as
+//! `"é"` makes `Square::from_str` index out of bounds), so anything that came
+//! off the wire goes through here before it reaches the game.
+
+use chess::{ChessMove, File, Piece, Rank, Square};
+use uuid::Uuid;
+
+use crate::accounts::AccountError;
+use crate::models::{ChessError, TimeControlRequest};
+use crate::time_control::{ClockMode, Stage, TimeControl};
+
+/// Longest base time a game can be created with
+pub const MAX_START_TIME_MINUTES: u64 = 24 * 60;
+/// Largest increment a game can be created with
+pub const MAX_INCREMENT_SECONDS: u64 = 60 * 60;
+/// Most stages a time control can have
+pub const MAX_STAGES: usize = 4;
+/// Most moves a stage can last
+pub const MAX_STAGE_MOVES: u32 = 200;
+/// Longest a correspondence game can give for each move
+pub const MAX_DAYS_PER_MOVE: u32 = 14;
+/// Usernames are this many characters
+pub const USERNAME_LENGTH: std::ops::RangeInclusive<usize> = 3..=20;
+/// Passwords are this many characters
+pub const PASSWORD_LENGTH: std::ops::RangeInclusive<usize> = 8..=128;
+
+/// A square in algebraic notation such as `e4`, in either case
+pub fn parse_square(square: &str) -> Result<Square, ChessError> {
+    match square.as_bytes() {
+        [file @ (b'a'..=b'h' | b'A'..=b'H'), rank @ b'1'..=b'8'] => Ok(Square::make_square(
+            Rank::from_index((rank - b'1') as usize),
+            File::from_index((file.to_ascii_lowercase() - b'a') as usize),
+        )),
+        _ => Err(ChessError::InvalidSquare),
+    }
+}
+
+/// The piece a pawn promotes to, by name (`queen`) or letter (`q`)
+pub fn parse_promotion(piece: Option<&str>) -> Result<Option<Piece>, ChessError> {
+    let piece = match piece {
+        Some(piece) => piece,
+        None => return Ok(None),
+    };
+
+    match piece.to_ascii_lowercase().as_str() {
+        "queen" | "q" => Ok(Some(Piece::Queen)),
+        "rook" | "r" => Ok(Some(Piece::Rook)),
+        "bishop" | "b" => Ok(Some(Piece::Bishop)),
+        "knight" | "n" => Ok(Some(Piece::Knight)),
+        _ => Err(ChessError::InvalidPromotion),
+    }
+}
+
+/// A move in UCI notation such as `e2e4`, or `e7e8q` for a promotion
+pub fn parse_uci(uci: &str) -> Result<ChessMove, ChessError> {
+    if !uci.is_ascii() || !(4..=5).contains(&uci.len()) {
+        return Err(ChessError::InvalidNotation);
+    }
+
+    let from = parse_square(&uci[0..2])?;
+    let to = parse_square(&uci[2..4])?;
+    let promotion = parse_promotion(uci.get(4..).filter(|piece| !piece.is_empty()))?;
+    Ok(ChessMove::new(from, to, promotion))
+}
+
+/// Game IDs are UUIDs handed out by the server
+pub fn check_game_id(game_id: &str) -> Result<(), ChessError> {
+    Uuid::try_parse(game_id).map(|_| ()).map_err(|_| ChessError::InvalidGameId)
+}
+
+/// Resume tokens are UUIDs handed out by the server
+pub fn check_resume_token(resume_token: &str) -> Result<(), ChessError> {
+    Uuid::try_parse(resume_token).map(|_| ()).map_err(|_| ChessError::InvalidResumeToken)
+}
+
+/// Usernames are letters, digits, `_` and `-`, so they are safe to show anywhere
+pub fn check_username(username: &str) -> Result<(), AccountError> {
+    let allowed = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if !USERNAME_LENGTH.contains(&username.len()) || !username.chars().all(allowed) {
+        return Err(AccountError::InvalidUsername);
+    }
+    Ok(())
+}
+
+/// Passwords can be anything of a reasonable length, counted in characters
+pub fn check_password(password: &str) -> Result<(), AccountError> {
+    if !PASSWORD_LENGTH.contains(&password.chars().count()) {
+        return Err(AccountError::InvalidPassword);
+    }
+    Ok(())
+}
+
+/// A base time and Fischer increment for a new game
+pub fn time_control(start_time_minutes: u64, increment_seconds: u64) -> Result<TimeControl, ChessError> {
+    if !(1..=MAX_START_TIME_MINUTES).contains(&start_time_minutes) || increment_seconds > MAX_INCREMENT_SECONDS {
+        return Err(ChessError::InvalidTimeControl);
+    }
+    Ok(TimeControl::fischer(start_time_minutes * 60 * 1000, increment_seconds * 1000))
+}
+
+/// A time control with stages for a new game. Every stage but the last needs
+/// a number of moves, and only the first has to give the players any time.
+pub fn staged_time_control(request: &TimeControlRequest) -> Result<TimeControl, ChessError> {
+    // Correspondence games are asked for in days per move instead
+    if request.stages.is_empty() || request.stages.len() > MAX_STAGES || request.mode == ClockMode::Correspondence {
+        return Err(ChessError::InvalidTimeControl);
+    }
+
+    let mut stages = Vec::new();
+    for (index, stage) in request.stages.iter().enumerate() {
+        let last = index + 1 == request.stages.len();
+        let moves_valid = match stage.moves {
+            Some(moves) => (1..=MAX_STAGE_MOVES).contains(&moves),
+            None => last,
+        };
+        let min_minutes = if index == 0 { 1 } else { 0 };
+        if !moves_valid
+            || !(min_minutes..=MAX_START_TIME_MINUTES).contains(&stage.minutes)
+            || stage.increment_seconds > MAX_INCREMENT_SECONDS
+        {
+            return Err(ChessError::InvalidTimeControl);
+        }
+        stages.push(Stage {
+            moves: stage.moves,
+            time_ms: stage.minutes * 60 * 1000,
+            increment_ms: stage.increment_seconds * 1000,
+        });
+    }
+
+    Ok(TimeControl {
+        mode: request.mode,
+        stages,
+    })
+}
+
+/// A correspondence time control for a new game
+pub fn correspondence_time_control(days_per_move: u32) -> Result<TimeControl, ChessError> {
+    if !(1..=MAX_DAYS_PER_MOVE).contains(&days_per_move) {
+        return Err(ChessError::InvalidTimeControl);
+    }
+    Ok(TimeControl::correspondence(days_per_move as u64 * 24 * 60 * 60 * 1000))
+}
+
+/// Check the shape of a FEN string before handing it to the `chess` crate,
+/// whose parser wraps around on too many squares instead of failing
+pub fn check_fen_layout(fen: &str) -> Result<(), String> {
+    if !fen.is_ascii() {
+        return Err("FEN must be ASCII".to_string());
+    }
+
+    let fields: Vec<&str> = fen.split(' ').collect();
+    if !(4..=6).contains(&fields.len()) {
+        return Err("FEN must have between 4 and 6 space separated fields".to_string());
+    }
+
+    let ranks: Vec<&str> = fields[0].split('/').collect();
+    if ranks.len() != 8 {
+        return Err("FEN must describe 8 ranks".to_string());
+    }
+
+    if fields.len() > 4 && fields[4].parse::<u32>().is_err() {
+        return Err("the halfmove clock must be a number".to_string());
+    }
+
+    for rank in ranks {
+        let mut squares = 0;
+        for c in rank.chars() {
+            squares += match c {
+                '1'..='8' => c as u32 - '0' as u32,
+                'p' | 'n' | 'b' | 'r' | 'q' | 'k' | 'P' | 'N' | 'B' | 'R' | 'Q' | 'K' => 1,
+                _ => return Err(format!("unexpected character {} in the board", c)),
+            };
+        }
+        if squares != 8 {
+            return Err("every rank must have 8 squares".to_string());
+        }
+    }
+
+    Ok(())
+}
+
+/// Half-moves since the last capture or pawn move in a FEN whose layout was
+/// checked, 0 if the FEN leaves the counters out
+pub fn fen_halfmove_clock(fen: &str) -> u32 {
+    fen.split(' ').nth(4).and_then(|field| field.parse().ok()).unwrap_or(0)
+}
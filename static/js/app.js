@@ -2,7 +2,13 @@
 document.addEventListener('DOMContentLoaded', () => {
     // Initialize elements
     const createGameBtn = document.getElementById('create-game');
+    const seekGameBtn = document.getElementById('seek-game');
+    const cancelSeekBtn = document.getElementById('cancel-seek');
+    const seekList = document.getElementById('seek-list');
+    const gameList = document.getElementById('game-list');
     const joinGameBtn = document.getElementById('join-game');
+    const watchGameBtn = document.getElementById('watch-game');
+    const spectatorCountDisplay = document.getElementById('spectator-count');
     const gameIdInput = document.getElementById('game-id-input');
     const gameIdDisplay = document.getElementById('game-id-display');
     const copyIdBtn = document.getElementById('copy-id-btn');
@@ -13,8 +19,34 @@ document.addEventListener('DOMContentLoaded', () => {
     const chessboard = document.getElementById('chessboard');
     const startTimeSelect = document.getElementById('start-time');
     const incrementSelect = document.getElementById('increment');
+    const clockModeSelect = document.getElementById('clock-mode');
+    const startFenInput = document.getElementById('start-fen');
+    const colorPreferenceSelect = document.getElementById('color-preference');
+    const opponentSelect = document.getElementById('opponent');
+    const ratedCheckbox = document.getElementById('rated');
+    const playerRatingsDisplay = document.getElementById('player-ratings');
     const whiteTimeDisplay = document.getElementById('white-time');
     const blackTimeDisplay = document.getElementById('black-time');
+    const gameActions = document.getElementById('game-actions');
+    const resignBtn = document.getElementById('resign-btn');
+    const offerDrawBtn = document.getElementById('offer-draw-btn');
+    const claimDrawBtn = document.getElementById('claim-draw-btn');
+    const drawOffer = document.getElementById('draw-offer');
+    const acceptDrawBtn = document.getElementById('accept-draw-btn');
+    const declineDrawBtn = document.getElementById('decline-draw-btn');
+    const downloadPgnBtn = document.getElementById('download-pgn-btn');
+    const moveInput = document.getElementById('move-input');
+    const analysisPanel = document.getElementById('analysis-panel');
+    const analyseBtn = document.getElementById('analyse-btn');
+    const reportBtn = document.getElementById('report-btn');
+    const analysisOutput = document.getElementById('analysis-output');
+    const moveList = document.getElementById('move-list');
+    const accountName = document.getElementById('account-name');
+    const usernameInput = document.getElementById('username-input');
+    const passwordInput = document.getElementById('password-input');
+    const loginBtn = document.getElementById('login-btn');
+    const registerBtn = document.getElementById('register-btn');
+    const logoutBtn = document.getElementById('logout-btn');
 
     // Game state
     let socket;
@@ -31,11 +63,68 @@ document.addEventListener('DOMContentLoaded', () => {
     let incrementMs = 10000; // 10 seconds in milliseconds
     let lastMoveTime = null;
     let activeColor = 'white';
+    let sanMoves = []; // Moves played so far, in SAN
+    let judgements = []; // The post-game report's verdict on each move
+    let analysing = false;
+
+    // Remember the game we are seated in so we can reclaim it after a dropped connection
+    const SESSION_KEY = 'chessSession';
+    const saveSession = (id, resumeToken) => {
+        if (id && resumeToken) {
+            localStorage.setItem(SESSION_KEY, JSON.stringify({ gameId: id, resumeToken }));
+        }
+    };
+    const loadSession = () => {
+        try {
+            return JSON.parse(localStorage.getItem(SESSION_KEY));
+        } catch (e) {
+            return null;
+        }
+    };
+    const clearSession = () => localStorage.removeItem(SESSION_KEY);
+    let rejoinPending = false;
+
+    // Show the moves played so far as numbered pairs, marked with the report's judgements
+    const JUDGEMENT_MARKS = { inaccuracy: '?!', mistake: '?', blunder: '??' };
+    const renderMoveList = () => {
+        moveList.innerHTML = '';
+        const marked = sanMoves.map((san, i) => san + (JUDGEMENT_MARKS[judgements[i]] || ''));
+        for (let i = 0; i < marked.length; i += 2) {
+            const item = document.createElement('li');
+            item.textContent = marked.slice(i, i + 2).join(' ');
+            moveList.appendChild(item);
+        }
+        moveList.scrollTop = moveList.scrollHeight;
+    };
+    const setHistory = (history) => {
+        sanMoves = (history || []).map(entry => entry.san);
+        judgements = [];
+        renderMoveList();
+        resetAnalysis();
+    };
+
+    // Analysis is offered to spectators and once the game is over
+    const resetAnalysis = () => {
+        analysing = false;
+        analyseBtn.textContent = 'Analyse';
+        analysisOutput.textContent = '';
+        analysisPanel.style.display = gameId ? 'flex' : 'none';
+    };
+    const formatEvaluation = (evaluation) => {
+        if (!evaluation) {
+            return 'game over';
+        }
+        if (evaluation.mate !== undefined) {
+            return `${evaluation.mate > 0 ? '' : '-'}M${Math.abs(evaluation.mate)}`;
+        }
+        const pawns = evaluation.cp / 100;
+        return (pawns > 0 ? '+' : '') + pawns.toFixed(2);
+    };
 
     // Initialize WebSocket connection
     const connectWebSocket = () => {
         const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
-        const wsUrl = `${protocol}//${window.location.host}/ws`;
+        const wsUrl = `${protocol}//${window.location.host}/ws?protocol=2`;
         console.log('Connecting to WebSocket at:', wsUrl);
         
         socket = new WebSocket(wsUrl);
@@ -44,23 +133,51 @@ document.addEventListener('DOMContentLoaded', () => {
             console.log('WebSocket connection established');
             isConnected = true;
             createGameBtn.disabled = false;
+            seekGameBtn.disabled = false;
             joinGameBtn.disabled = false;
+            watchGameBtn.disabled = false;
             gameIdInput.disabled = false;
             startTimeSelect.disabled = false;
             incrementSelect.disabled = false;
+            clockModeSelect.disabled = false;
+            startFenInput.disabled = false;
+            colorPreferenceSelect.disabled = false;
+            opponentSelect.disabled = false;
+            ratedCheckbox.disabled = false;
             connectionStatus.textContent = 'Connected';
             connectionStatus.style.color = 'green';
             gameStatus.textContent = 'Connected to server. Create a new game or join an existing one.';
+            socket.send(JSON.stringify({ type: 'subscribe_lobby' }));
+
+            // Try to reclaim our seat if we were in a game before the connection dropped
+            const session = loadSession();
+            if (session) {
+                rejoinPending = true;
+                socket.send(JSON.stringify({
+                    type: 'rejoin',
+                    game_id: session.gameId,
+                    resume_token: session.resumeToken
+                }));
+                gameStatus.textContent = 'Reconnecting to your game...';
+            }
         };
         
         socket.onclose = (event) => {
             console.log('WebSocket connection closed:', event);
             isConnected = false;
             createGameBtn.disabled = true;
+            seekGameBtn.disabled = true;
+            cancelSeekBtn.style.display = 'none';
             joinGameBtn.disabled = true;
+            watchGameBtn.disabled = true;
             gameIdInput.disabled = true;
             startTimeSelect.disabled = true;
             incrementSelect.disabled = true;
+            clockModeSelect.disabled = true;
+            startFenInput.disabled = true;
+            colorPreferenceSelect.disabled = true;
+            opponentSelect.disabled = true;
+            ratedCheckbox.disabled = true;
             connectionStatus.textContent = 'Disconnected';
             connectionStatus.style.color = 'red';
             gameStatus.textContent = 'Connection lost. Please refresh the page.';
@@ -85,15 +202,22 @@ document.addEventListener('DOMContentLoaded', () => {
     const handleMessage = (message) => {
         console.log('Handling message:', message);
         
-        switch (message.message_type) {
+        switch (message.type) {
+            case 'hello':
+                console.log('Server speaks protocol version', message.protocol_version);
+                break;
+
             case 'game_created':
                 gameId = message.game_id;
                 playerColor = message.color;
+                activeColor = message.active_color || 'white';
+                saveSession(gameId, message.resume_token);
                 gameIdDisplay.textContent = `${gameId}`;
                 copyIdBtn.style.display = 'inline-block'; // Show the copy button when game ID is available
                 playerColorDisplay.textContent = `You are playing as: ${playerColor}`;
                 playerInfo.style.display = 'flex';
                 gameStatus.textContent = formatGameStatus(message.game_status || 'waiting_for_opponent');
+                setHistory([]);
                 
                 // Parse FEN and update board
                 if (message.fen) {
@@ -106,13 +230,18 @@ document.addEventListener('DOMContentLoaded', () => {
                 break;
                 
             case 'joined':
+                cancelSeekBtn.style.display = 'none';
                 gameId = message.game_id;
                 playerColor = message.color;
+                activeColor = message.active_color || 'white';
+                saveSession(gameId, message.resume_token);
                 gameIdDisplay.textContent = `Game ID: ${gameId}`;
                 copyIdBtn.style.display = 'inline-block'; // Show the copy button when game ID is available
                 playerColorDisplay.textContent = `You are playing as: ${playerColor}`;
                 playerInfo.style.display = 'flex';
                 gameStatus.textContent = formatGameStatus(message.game_status || 'in_progress');
+                setHistory(message.history);
+                showRatings(message);
                 
                 // Parse FEN and update board
                 if (message.fen) {
@@ -123,6 +252,86 @@ document.addEventListener('DOMContentLoaded', () => {
                 
                 break;
                 
+            case 'rejoined':
+                rejoinPending = false;
+                gameId = message.game_id;
+                playerColor = message.color;
+                activeColor = message.active_color;
+                saveSession(gameId, message.resume_token);
+                gameIdDisplay.textContent = `Game ID: ${gameId}`;
+                copyIdBtn.style.display = 'inline-block';
+                playerColorDisplay.textContent = `You are playing as: ${playerColor}`;
+                playerInfo.style.display = 'flex';
+                setHistory(message.history);
+
+                if (message.fen) {
+                    const chess = new Chess();
+                    const position = chess.parseFen(message.fen);
+                    updateBoard(position, true);
+                }
+
+                if (message.last_move) {
+                    const { from, to } = message.last_move;
+                    highlightLastMove(from, to);
+                }
+
+                handleGameJoined(message);
+                if (message.game_status) {
+                    gameStatus.textContent = formatGameStatus(message.game_status);
+                    if (!['white_wins', 'black_wins', 'draw', 'waiting_for_opponent'].includes(message.game_status)) {
+                        startTimers();
+                        gameActions.style.display = 'flex';
+                    }
+                }
+                break;
+
+            case 'watching':
+                gameId = message.game_id;
+                playerColor = '';
+                activeColor = message.active_color;
+                gameIdDisplay.textContent = `Game ID: ${gameId}`;
+                copyIdBtn.style.display = 'inline-block';
+                playerColorDisplay.textContent = 'You are watching this game';
+                playerInfo.style.display = 'flex';
+                updateSpectatorCount(message.spectator_count);
+                setHistory(message.history);
+
+                if (message.fen) {
+                    const chess = new Chess();
+                    const position = chess.parseFen(message.fen);
+                    updateBoard(position, true);
+                }
+
+                if (message.last_move) {
+                    const { from, to } = message.last_move;
+                    highlightLastMove(from, to);
+                }
+
+                handleGameJoined(message);
+                if (message.game_status) {
+                    gameStatus.textContent = formatGameStatus(message.game_status);
+                    if (!['white_wins', 'black_wins', 'draw', 'waiting_for_opponent'].includes(message.game_status)) {
+                        startTimers();
+                    }
+                }
+                break;
+
+            case 'spectator_joined':
+            case 'spectator_left':
+                updateSpectatorCount(message.spectator_count);
+                break;
+
+            case 'opponent_moved':
+                gameStatus.textContent = `Your opponent played ${message.san}. Reply by ${new Date(message.move_deadline).toLocaleString()}`;
+                break;
+
+            case 'player_rejoined':
+                console.log(`The ${message.color} player reconnected`);
+                if (message.game_status) {
+                    gameStatus.textContent = formatGameStatus(message.game_status);
+                }
+                break;
+
             case 'game_update':
                 // Parse FEN and update board
                 if (message.fen) {
@@ -149,6 +358,12 @@ document.addEventListener('DOMContentLoaded', () => {
                 break;
                 
             case 'move_made':
+                // Making a move declines any pending draw offer
+                drawOffer.style.display = 'none';
+
+                // Threefold repetition or the fifty-move rule lets either player claim a draw
+                claimDrawBtn.style.display = message.draw_claim && playerColor ? 'inline-block' : 'none';
+
                 // Parse FEN and update board
                 if (message.fen) {
                     const chess = new Chess();
@@ -168,7 +383,7 @@ document.addEventListener('DOMContentLoaded', () => {
                 
                 // Update game status if provided
                 if (message.game_status) {
-                    gameStatus.textContent = formatGameStatus(message.game_status);
+                    gameStatus.textContent = formatGameStatus(message.game_status) + formatTermination(message.termination);
                 }
                 
                 // Update last move if provided
@@ -176,6 +391,10 @@ document.addEventListener('DOMContentLoaded', () => {
                     const { from, to } = message.last_move;
                     highlightLastMove(from, to);
                 }
+                if (message.san) {
+                    sanMoves.push(message.san);
+                    renderMoveList();
+                }
                 
                 // Update timer values
                 if (message.white_time_ms !== undefined) whiteTimeMs = message.white_time_ms;
@@ -186,7 +405,11 @@ document.addEventListener('DOMContentLoaded', () => {
                 
                 // Restart the timers to ensure they're running with the correct active player
                 stopTimers();
-                startTimers();
+                if (['white_wins', 'black_wins', 'draw'].includes(message.game_status)) {
+                    gameActions.style.display = 'none';
+                } else {
+                    startTimers();
+                }
                 
                 // Clear selection and valid moves
                 selectedSquare = null;
@@ -195,6 +418,12 @@ document.addEventListener('DOMContentLoaded', () => {
                 break;
                 
             case 'player_joined':
+                updateSpectatorCount(message.spectator_count);
+                showRatings(message);
+                if (playerColor) {
+                    gameActions.style.display = 'flex';
+                }
+
                 // Update game status
                 if (message.game_status) {
                     gameStatus.textContent = formatGameStatus(message.game_status);
@@ -214,18 +443,51 @@ document.addEventListener('DOMContentLoaded', () => {
                 }
                 break;
                 
+            case 'seeks':
+                showSeeks(message.seeks);
+                break;
+
+            case 'lobby_games':
+                showLobbyGames(message);
+                break;
+
+            case 'seek_created':
+                cancelSeekBtn.style.display = 'inline-block';
+                gameStatus.textContent = 'Looking for an opponent...';
+                break;
+
+            case 'seek_cancelled':
+                cancelSeekBtn.style.display = 'none';
+                gameStatus.textContent = 'Stopped looking for an opponent.';
+                break;
+
             case 'available_moves':
                 validMoves = message.available_moves || [];
                 highlightValidMoves(validMoves);
                 break;
                 
             case 'error':
-                console.log('Error from server:', message.error);
-                if (message.error) {
+                console.log('Error from server:', message.code, message.message);
+                if (rejoinPending && (message.code === 'game_not_found' || message.code === 'invalid_resume_token')) {
+                    // The game is gone or our seat cannot be reclaimed
+                    rejoinPending = false;
+                    clearSession();
+                    gameStatus.textContent = 'Connected to server. Create a new game or join an existing one.';
+                }
+                if (message.code === 'analysis_not_allowed' || message.code === 'game_not_over') {
+                    resetAnalysis();
+                }
+                if (message.code === 'illegal_move' || message.code === 'not_your_turn') {
+                    // Drop the selection so the player can start the move again
+                    selectedSquare = null;
+                    validMoves = [];
+                    clearHighlights();
+                }
+                if (message.message) {
                     // Show error message to the user
                     const errorToast = document.createElement('div');
                     errorToast.className = 'error-toast';
-                    errorToast.textContent = message.error;
+                    errorToast.textContent = message.message;
                     document.body.appendChild(errorToast);
                     
                     // Remove the error toast after 3 seconds
@@ -257,10 +519,194 @@ document.addEventListener('DOMContentLoaded', () => {
                 }
                 
                 break;
-                
+
+            case 'game_over':
+                // The server ended the game (timeout, resignation, draw agreement...)
+                if (message.white_time_ms !== undefined) whiteTimeMs = message.white_time_ms;
+                if (message.black_time_ms !== undefined) blackTimeMs = message.black_time_ms;
+
+                stopTimers();
+                updateTimerDisplays();
+                gameActions.style.display = 'none';
+
+                if (message.game_status) {
+                    gameStatus.textContent = formatGameStatus(message.game_status) + formatTermination(message.termination);
+                }
+                break;
+
+            case 'analysis':
+                analysisOutput.textContent = message.depth > 0
+                    ? `${formatEvaluation(message.evaluation)} (depth ${message.depth}) ${message.pv.join(' ')}`
+                    : formatEvaluation(message.evaluation);
+                break;
+
+            case 'report':
+                judgements = message.moves.map(entry => entry.judgement);
+                renderMoveList();
+                analysisOutput.textContent = '';
+                break;
+
+            case 'draw_offered':
+                if (playerColor && message.color !== playerColor) {
+                    drawOffer.style.display = 'flex';
+                } else if (!playerColor) {
+                    console.log(`The ${message.color} player offered a draw`);
+                }
+                break;
+
+            case 'draw_declined':
+                drawOffer.style.display = 'none';
+                if (playerColor && message.color !== playerColor) {
+                    gameStatus.textContent = 'Your draw offer was declined';
+                }
+                break;
+
+            default:
+                console.log('Unknown message type:', message.type);
+        }
+    };
+
+    // Explain why a finished game ended
+    const formatTermination = (termination) => {
+        switch (termination) {
+            case 'timeout':
+                return ' (on time)';
+            case 'resignation':
+                return ' (by resignation)';
+            case 'draw_agreement':
+                return ' (by agreement)';
+            case 'threefold_repetition':
+                return ' (threefold repetition)';
+            case 'fivefold_repetition':
+                return ' (fivefold repetition)';
+            case 'fifty_move_rule':
+                return ' (fifty-move rule)';
+            case 'seventy_five_move_rule':
+                return ' (75-move rule)';
+            case 'checkmate':
+                return ' (by checkmate)';
+            case 'stalemate':
+                return ' (stalemate)';
             default:
-                console.log('Unknown message type:', message.message_type);
+                return '';
+        }
+    };
+
+    // Show how many people are watching the game
+    const updateSpectatorCount = (count) => {
+        if (count === undefined || count === null) return;
+        spectatorCountDisplay.textContent = count > 0 ? `Spectators: ${count}` : '';
+    };
+
+    // Describe a time control from the server, e.g. "5+3" or "3 days per move"
+    const describeTimeControl = (timeControl) => {
+        const stage = timeControl.stages[0];
+        if (timeControl.mode === 'correspondence') {
+            const days = stage.time_ms / 86400000;
+            return `${days} day${days === 1 ? '' : 's'} per move`;
+        }
+        const text = timeControl.stages.map((stage) => `${stage.time_ms / 60000}+${stage.increment_ms / 1000}`).join(', then ');
+        return timeControl.mode === 'fischer' ? text : `${text} (${timeControl.mode})`;
+    };
+
+    // The time fields of a seek for the same game as a listed seek
+    const seekTimeFields = (timeControl) => {
+        if (timeControl.mode === 'correspondence') {
+            return { days_per_move: timeControl.stages[0].time_ms / 86400000 };
+        }
+        return {
+            time_control: {
+                mode: timeControl.mode,
+                stages: timeControl.stages.map((stage) => ({
+                    moves: stage.moves,
+                    minutes: stage.time_ms / 60000,
+                    increment_seconds: stage.increment_ms / 1000
+                }))
+            }
+        };
+    };
+
+    // List the open seeks; accepting one seeks the same game with the other color
+    const showSeeks = (seeks) => {
+        seekList.innerHTML = '';
+        if (!seeks || seeks.length === 0) {
+            const empty = document.createElement('li');
+            empty.textContent = 'Nobody is looking for a game right now';
+            seekList.appendChild(empty);
+            return;
         }
+        const otherColor = { white: 'black', black: 'white', random: 'random' };
+        seeks.forEach((seek) => {
+            const item = document.createElement('li');
+            const player = seek.username ? `${seek.username} (${seek.rating})` : 'Guest';
+            const details = [describeTimeControl(seek.time_control), seek.rated ? 'rated' : 'casual'];
+            if (seek.color_preference !== 'random') {
+                details.push(`plays ${seek.color_preference}`);
+            }
+            item.textContent = `${player}: ${details.join(', ')}`;
+
+            const acceptBtn = document.createElement('button');
+            acceptBtn.className = 'small-btn';
+            acceptBtn.textContent = 'Play';
+            acceptBtn.addEventListener('click', () => {
+                socket.send(JSON.stringify({
+                    type: 'seek',
+                    rated: seek.rated,
+                    color_preference: otherColor[seek.color_preference],
+                    ...seekTimeFields(seek.time_control)
+                }));
+            });
+            item.appendChild(acceptBtn);
+            seekList.appendChild(item);
+        });
+    };
+
+    // List the games waiting for an opponent, to join, and the games in progress, to watch
+    const showLobbyGames = (games) => {
+        gameList.innerHTML = '';
+        const name = (username, rating) => {
+            if (!username) return 'Guest';
+            return rating === undefined || rating === null ? username : `${username} (${rating})`;
+        };
+        // Each game gets a button that joins or watches it as if its ID had been entered
+        const addGame = (text, gameIdToUse, label, button) => {
+            const item = document.createElement('li');
+            item.textContent = text;
+            const actionBtn = document.createElement('button');
+            actionBtn.className = 'small-btn';
+            actionBtn.textContent = label;
+            actionBtn.addEventListener('click', () => {
+                gameIdInput.value = gameIdToUse;
+                button.click();
+            });
+            item.appendChild(actionBtn);
+            gameList.appendChild(item);
+        };
+
+        games.waiting.forEach((game) => {
+            const details = [describeTimeControl(game.time_control), game.rated ? 'rated' : 'casual', `plays ${game.color}`];
+            addGame(`${name(game.creator, game.creator_rating)}: ${details.join(', ')}`, game.game_id, 'Join', joinGameBtn);
+        });
+        games.in_progress.forEach((game) => {
+            const players = `${name(game.white, game.white_rating)} vs ${name(game.black, game.black_rating)}`;
+            const clocks = `${formatTime(game.white_time_ms)} - ${formatTime(game.black_time_ms)}`;
+            addGame(`${players}, ${describeTimeControl(game.time_control)}, move ${game.move_count}, ${clocks}`, game.game_id, 'Watch', watchGameBtn);
+        });
+        if (gameList.children.length === 0) {
+            const empty = document.createElement('li');
+            empty.textContent = 'No games right now';
+            gameList.appendChild(empty);
+        }
+    };
+
+    // Show the players' ratings in rated games
+    const showRatings = (message) => {
+        if (!message.rated) {
+            playerRatingsDisplay.textContent = '';
+            return;
+        }
+        const rating = (value) => value === undefined || value === null ? '?' : value;
+        playerRatingsDisplay.textContent = `Rated: White ${rating(message.white_rating)}, Black ${rating(message.black_rating)}`;
     };
 
     // Format game status for display
@@ -280,6 +726,8 @@ document.addEventListener('DOMContentLoaded', () => {
                 return 'Check!';
             case 'waiting_for_opponent':
                 return 'Waiting for opponent to join...';
+            case 'paused':
+                return 'Game paused until both players reconnect';
             case 'in_progress':
                 return 'Game in progress';
             default:
@@ -288,25 +736,170 @@ document.addEventListener('DOMContentLoaded', () => {
     };
 
     // Create a new game
+    // Add the time control chosen in the form to a `create` or `seek` message
+    const addTimeFields = (message) => {
+        const incrementSeconds = parseInt(incrementSelect.value, 10);
+
+        // Delays and multi-stage controls need a full time control; a plain increment does not
+        if (startTimeSelect.value.startsWith('days:')) {
+            message.days_per_move = parseInt(startTimeSelect.value.slice('days:'.length), 10);
+        } else if (startTimeSelect.value === 'classical') {
+            message.time_control = {
+                mode: clockModeSelect.value,
+                stages: [
+                    { moves: 40, minutes: 90, increment_seconds: incrementSeconds },
+                    { minutes: 30, increment_seconds: incrementSeconds }
+                ]
+            };
+        } else if (clockModeSelect.value !== 'fischer') {
+            message.time_control = {
+                mode: clockModeSelect.value,
+                stages: [{ minutes: parseInt(startTimeSelect.value, 10), increment_seconds: incrementSeconds }]
+            };
+        } else {
+            message.start_time_minutes = parseInt(startTimeSelect.value, 10);
+            message.increment_seconds = incrementSeconds;
+        }
+    };
+
     createGameBtn.addEventListener('click', () => {
         if (!isConnected) {
             console.error('Cannot create game: WebSocket not connected');
             return;
         }
         
-        const startTimeMinutes = parseInt(startTimeSelect.value, 10);
-        const incrementSeconds = parseInt(incrementSelect.value, 10);
-        
         const message = {
-            message_type: 'create',
-            start_time_minutes: startTimeMinutes,
-            increment_seconds: incrementSeconds
+            type: 'create',
+            color_preference: colorPreferenceSelect.value
         };
+        addTimeFields(message);
+        
+        const startFen = startFenInput.value.trim();
+        if (startFen) {
+            message.start_fen = startFen;
+        }
+
+        if (opponentSelect.value) {
+            message.engine_level = parseInt(opponentSelect.value, 10);
+        }
+
+        if (ratedCheckbox.checked) {
+            message.rated = true;
+        }
         
         socket.send(JSON.stringify(message));
         gameStatus.textContent = 'Creating a new game...';
     });
 
+    // Look for an opponent in the lobby with the settings in the form
+    seekGameBtn.addEventListener('click', () => {
+        if (!isConnected) {
+            return;
+        }
+        const message = {
+            type: 'seek',
+            color_preference: colorPreferenceSelect.value,
+            rated: ratedCheckbox.checked
+        };
+        addTimeFields(message);
+        socket.send(JSON.stringify(message));
+    });
+
+    cancelSeekBtn.addEventListener('click', () => {
+        if (isConnected) {
+            socket.send(JSON.stringify({ type: 'cancel_seek' }));
+        }
+    });
+
+    // Resign and draw offers
+    resignBtn.addEventListener('click', () => {
+        if (isConnected && gameId && confirm('Are you sure you want to resign?')) {
+            socket.send(JSON.stringify({ type: 'resign' }));
+        }
+    });
+
+    offerDrawBtn.addEventListener('click', () => {
+        if (isConnected && gameId) {
+            socket.send(JSON.stringify({ type: 'offer_draw' }));
+            gameStatus.textContent = 'Draw offered';
+        }
+    });
+
+    claimDrawBtn.addEventListener('click', () => {
+        if (isConnected && gameId) {
+            socket.send(JSON.stringify({ type: 'claim_draw' }));
+            claimDrawBtn.style.display = 'none';
+        }
+    });
+
+    // Moves typed as UCI (e2e4, e7e8q) or SAN (Nf3, O-O); the server works out which piece is meant
+    moveInput.addEventListener('keydown', (e) => {
+        const move = moveInput.value.trim();
+        if (e.key !== 'Enter' || !move || !isConnected || !gameId || !playerColor) {
+            return;
+        }
+        const message = /^[a-h][1-8][a-h][1-8][qrbn]?$/.test(move)
+            ? { type: 'move', uci: move }
+            : { type: 'move', san: move };
+        socket.send(JSON.stringify(message));
+        moveInput.value = '';
+    });
+
+    analyseBtn.addEventListener('click', () => {
+        if (!isConnected || !gameId) {
+            return;
+        }
+        analysing = !analysing;
+        socket.send(JSON.stringify(analysing ? { type: 'start_analysis' } : { type: 'stop_analysis' }));
+        analyseBtn.textContent = analysing ? 'Stop Analysis' : 'Analyse';
+        if (!analysing) {
+            analysisOutput.textContent = '';
+        }
+    });
+
+    reportBtn.addEventListener('click', () => {
+        if (isConnected && gameId) {
+            socket.send(JSON.stringify({ type: 'get_report' }));
+            analysisOutput.textContent = 'Preparing the report...';
+        }
+    });
+
+    downloadPgnBtn.addEventListener('click', () => {
+        if (gameId) {
+            window.location.href = `/games/${gameId}/pgn`;
+        }
+    });
+
+    acceptDrawBtn.addEventListener('click', () => {
+        socket.send(JSON.stringify({ type: 'accept_draw' }));
+        drawOffer.style.display = 'none';
+    });
+
+    declineDrawBtn.addEventListener('click', () => {
+        socket.send(JSON.stringify({ type: 'decline_draw' }));
+        drawOffer.style.display = 'none';
+    });
+
+    // Watch an existing game without taking a seat
+    watchGameBtn.addEventListener('click', () => {
+        if (!isConnected) {
+            console.error('Cannot watch game: WebSocket not connected');
+            return;
+        }
+
+        const gameIdToWatch = gameIdInput.value.trim();
+        if (!gameIdToWatch) {
+            gameStatus.textContent = 'Please enter a valid Game ID';
+            return;
+        }
+
+        socket.send(JSON.stringify({
+            type: 'watch',
+            game_id: gameIdToWatch
+        }));
+        gameStatus.textContent = 'Attempting to watch game...';
+    });
+
     // Join an existing game
     joinGameBtn.addEventListener('click', () => {
         if (!isConnected) {
@@ -321,7 +914,7 @@ document.addEventListener('DOMContentLoaded', () => {
         }
         
         const message = {
-            message_type: 'join',
+            type: 'join',
             game_id: gameIdToJoin
         };
         
@@ -381,9 +974,9 @@ document.addEventListener('DOMContentLoaded', () => {
                         } else {
                             // Make the regular move
                             const message = {
-                                message_type: 'move',
-                                move_from: fromSquare,
-                                move_to: squareName
+                                type: 'move',
+                                from: fromSquare,
+                                to: squareName
                             };
                             
                             socket.send(JSON.stringify(message));
@@ -481,8 +1074,8 @@ document.addEventListener('DOMContentLoaded', () => {
                     
                     // Request valid moves from the server
                     const message = {
-                        message_type: 'get_moves',
-                        move_from: square
+                        type: 'get_moves',
+                        from: square
                     };
                     
                     socket.send(JSON.stringify(message));
@@ -534,8 +1127,8 @@ document.addEventListener('DOMContentLoaded', () => {
             
             // Request valid moves from the server
             const message = {
-                message_type: 'get_moves',
-                move_from: squareName
+                type: 'get_moves',
+                from: squareName
             };
             
             socket.send(JSON.stringify(message));
@@ -549,9 +1142,9 @@ document.addEventListener('DOMContentLoaded', () => {
                 } else {
                     // Make the move
                     const message = {
-                        message_type: 'move',
-                        move_from: selectedSquare,
-                        move_to: squareName
+                        type: 'move',
+                        from: selectedSquare,
+                        to: squareName
                     };
                     
                     socket.send(JSON.stringify(message));
@@ -578,8 +1171,8 @@ document.addEventListener('DOMContentLoaded', () => {
                     
                     // Request valid moves for the new square
                     const message = {
-                        message_type: 'get_moves',
-                        move_from: squareName
+                        type: 'get_moves',
+                        from: squareName
                     };
                     
                     socket.send(JSON.stringify(message));
@@ -598,9 +1191,9 @@ document.addEventListener('DOMContentLoaded', () => {
         if (!isConnected || !gameId) return;
         
         const message = {
-            message_type: 'get_moves',
+            type: 'get_moves',
             game_id: gameId,
-            move_from: squareName
+            from: squareName
         };
         
         socket.send(JSON.stringify(message));
@@ -679,6 +1272,12 @@ document.addEventListener('DOMContentLoaded', () => {
     // Format time in mm:ss format
     const formatTime = (timeMs) => {
         const totalSeconds = Math.max(0, Math.floor(timeMs / 1000));
+        // Correspondence clocks run for days
+        if (totalSeconds >= 24 * 60 * 60) {
+            const days = Math.floor(totalSeconds / (24 * 60 * 60));
+            const hours = Math.floor(totalSeconds / 3600) % 24;
+            return `${days}d ${hours}h`;
+        }
         const minutes = Math.floor(totalSeconds / 60);
         const seconds = totalSeconds % 60;
         return `${minutes.toString().padStart(2, '0')}:${seconds.toString().padStart(2, '0')}`;
@@ -712,7 +1311,7 @@ document.addEventListener('DOMContentLoaded', () => {
             // Request time update from server
             if (socket && isConnected && gameId) {
                 socket.send(JSON.stringify({
-                    message_type: 'time_sync',
+                    type: 'time_sync',
                     game_id: gameId
                 }));
             }
@@ -849,9 +1448,9 @@ document.addEventListener('DOMContentLoaded', () => {
                 
                 // Make the promotion move
                 const message = {
-                    message_type: 'move',
-                    move_from: fromSquare,
-                    move_to: toSquare,
+                    type: 'move',
+                    from: fromSquare,
+                    to: toSquare,
                     promote_to: piece
                 };
                 
@@ -900,7 +1499,49 @@ document.addEventListener('DOMContentLoaded', () => {
         }
     });
 
+    // Accounts: the session cookie is sent when the WebSocket opens, so the socket
+    // is reopened whenever the user logs in or out
+    const showUser = (user) => {
+        accountName.textContent = user ? `Logged in as ${user.username}` : 'Playing as a guest';
+        [usernameInput, passwordInput, loginBtn, registerBtn].forEach(element => {
+            element.style.display = user ? 'none' : '';
+        });
+        logoutBtn.style.display = user ? '' : 'none';
+    };
+    const reconnectWebSocket = () => {
+        if (socket) {
+            socket.onclose = null;
+            socket.close();
+        }
+        connectWebSocket();
+    };
+    const submitCredentials = async (path) => {
+        const response = await fetch(path, {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({ username: usernameInput.value, password: passwordInput.value })
+        });
+        if (!response.ok) {
+            gameStatus.textContent = await response.text();
+            return;
+        }
+        passwordInput.value = '';
+        showUser(await response.json());
+        reconnectWebSocket();
+    };
+    loginBtn.addEventListener('click', () => submitCredentials('/login'));
+    registerBtn.addEventListener('click', () => submitCredentials('/register'));
+    logoutBtn.addEventListener('click', async () => {
+        await fetch('/logout', { method: 'POST' });
+        showUser(null);
+        reconnectWebSocket();
+    });
+
     // Initialize the game
     initializeBoard();
-    connectWebSocket();
+    fetch('/me')
+        .then(response => (response.ok ? response.json() : null))
+        .then(showUser)
+        .catch(() => showUser(null))
+        .finally(connectWebSocket);
 });
@@ -0,0 +1,56 @@
+#!/bin/sh
+# Stand-in for a UCI engine in tests.
+#
+# Usage: mock_uci.sh [MODE] [MOVE]
+#
+# MODE is how the engine behaves once asked to search:
+#   play     report the position it was given in an `info string`, then play MOVE
+#   crash    exit
+#   hang     never answer, not even to `stop`
+#   garbage  answer with a move that is not UCI
+#   silent   never finish the handshake
+# MOVE defaults to e7e5.
+
+mode=${1:-play}
+reply=${2:-e7e5}
+position=""
+
+while read -r line; do
+    case "$line" in
+        uci)
+            echo "id name Mock"
+            echo "id author Tests"
+            if [ "$mode" != silent ]; then
+                echo "option name Skill Level type spin default 20 min 0 max 20"
+                echo "uciok"
+            fi
+            ;;
+        isready)
+            echo "readyok"
+            ;;
+        position*)
+            position=$line
+            ;;
+        go*)
+            case "$mode" in
+                crash)
+                    exit 1
+                    ;;
+                hang)
+                    ;;
+                garbage)
+                    echo "bestmove z9z9"
+                    ;;
+                *)
+                    echo "info string $position"
+                    echo "info depth 1 seldepth 1 multipv 1 score cp 25 nodes 20 pv $reply"
+                    echo "info depth 2 seldepth 2 multipv 1 score mate -3 nodes 400 pv $reply e2e4"
+                    echo "bestmove $reply ponder e2e4"
+                    ;;
+            esac
+            ;;
+        quit)
+            exit 0
+            ;;
+    esac
+done